@@ -0,0 +1,62 @@
+// Times what the input loop does on large inputs, without a terminal:
+// recalling and searching a long history, completing from many candidates
+// and parsing a long line. Run with `cargo bench`; the keystroke-to-redraw
+// latency of a live terminal is measured by the `latency-probe` feature.
+extern crate term_rs;
+
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use term_rs::completion::{self, Index};
+use term_rs::{parse, CommandHistory, Completer, Matcher};
+
+// runs `f` for about a second, printing how long one run took on average
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    f();
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    println!("{:32} {:>12?} ({} runs)", name, start.elapsed() / runs, runs);
+}
+
+fn main() {
+    let path = env::temp_dir().join(format!("term-rs-bench-{}", std::process::id()));
+    let mut history = CommandHistory::default();
+    for i in 0..50_000 {
+        history.add_command(format!("deploy --service svc{} --region eu-{}", i, i % 7));
+    }
+    history.save(&path, None).unwrap();
+    bench("history load (50k entries)", || {
+        CommandHistory::default().load(&path, None).unwrap();
+    });
+    bench("history recall to the oldest", || {
+        history.browse_from(history.len());
+        while history.prev_command().is_some() {}
+    });
+    bench("history search", || {
+        history.browse_from(history.len());
+        history.search(true, |entry| entry.contains("svc7 "));
+    });
+    let _ = fs::remove_file(&path);
+
+    let symbols: Vec<String> = (0..200_000).map(|i| format!("symbol_{:x}", i * 7919 % 200_000)).collect();
+    let index: Index = symbols.iter().collect();
+    let list = move |_: &str, _: &str| symbols.clone();
+    let completers: Vec<Box<dyn Completer>> = vec![Box::new(list)];
+    bench("complete 200k candidates, list", || {
+        completion::candidates(&completers, Matcher::Prefix, "symbol_1a2", "symbol_1a2");
+    });
+    let completers: Vec<Box<dyn Completer>> = vec![Box::new(index)];
+    bench("complete 200k candidates, index", || {
+        completion::candidates(&completers, Matcher::Prefix, "symbol_1a2", "symbol_1a2");
+    });
+
+    let line = (0..2_000).map(|i| format!("arg{} 'quoted {}'", i, i)).collect::<Vec<_>>().join(" ");
+    bench("parse a 30k character line", || {
+        parse::split(&line).unwrap();
+    });
+}
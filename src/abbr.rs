@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Fish-style abbreviations: a short first word expanded in place when
+/// followed by a space or Enter. Kept in `name expansion` lines when a file
+/// is set.
+#[derive(Default)]
+pub struct Abbreviations {
+    table: BTreeMap<String, String>,
+    file: Option<PathBuf>,
+}
+
+impl Abbreviations {
+    /// Loads abbreviations from `path`, which is also where changes are
+    /// saved. A missing file is not an error.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.file = Some(path.as_ref().to_owned());
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let mut parts = line.trim().splitn(2, ' ');
+            if let (Some(name), Some(expansion)) = (parts.next(), parts.next()) {
+                self.table.insert(name.to_owned(), expansion.trim().to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(ref path) = self.file {
+            let mut file = File::create(path)?;
+            for (name, expansion) in &self.table {
+                writeln!(file, "{} {}", name, expansion)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, name: &str, expansion: &str) -> io::Result<()> {
+        self.table.insert(name.to_owned(), expansion.to_owned());
+        self.save()
+    }
+
+    pub fn erase(&mut self, name: &str) -> io::Result<bool> {
+        let found = self.table.remove(name).is_some();
+        self.save()?;
+        Ok(found)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.table.get(name).map(|s| s.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.table.iter()
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::prompt::PromptContext;
+
+/// How a command recorded by an `AuditSink` ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// Not run, e.g. for exceeding the rate limit.
+    Refused,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Refused => "refused",
+        }
+    }
+}
+
+/// One command run in a session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    /// Who logged in, when there is an `Authenticator`.
+    pub user: Option<String>,
+    /// The remote session the command ran in, `None` locally.
+    pub session: Option<usize>,
+    pub command: String,
+    pub outcome: Outcome,
+    pub elapsed: Duration,
+}
+
+impl AuditEntry {
+    // an entry for `command`, run now in the session described by `status`
+    pub(crate) fn new(status: &PromptContext, command: &str, outcome: Outcome, elapsed: Duration) -> Self {
+        AuditEntry {
+            time: SystemTime::now(),
+            user: status.user.clone(),
+            session: status.session,
+            command: command.trim().to_owned(),
+            outcome,
+            elapsed,
+        }
+    }
+}
+
+/// Receives every command the terminal dispatches, see
+/// `TerminalBuilder::audit`.
+pub trait AuditSink {
+    fn record(&self, entry: &AuditEntry) -> io::Result<()>;
+}
+
+/// Appends entries to a file as JSON lines:
+///
+/// ```text
+/// {"time":"2024-05-01T09:30:00.250Z","user":"alice","session":null,"command":"restart","outcome":"success","elapsed_ms":12}
+/// ```
+pub struct JsonlAudit {
+    file: File,
+}
+
+impl JsonlAudit {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonlAudit { file })
+    }
+}
+
+impl AuditSink for JsonlAudit {
+    fn record(&self, entry: &AuditEntry) -> io::Result<()> {
+        let user = entry.user.as_deref().map_or("null".to_owned(), json_string);
+        let session = entry.session.map_or("null".to_owned(), |id| id.to_string());
+        let line = format!("{{\"time\":\"{}\",\"user\":{},\"session\":{},\"command\":{},\"outcome\":\"{}\",\
+                            \"elapsed_ms\":{}}}\n",
+                           rfc3339(entry.time), user, session, json_string(&entry.command), entry.outcome.as_str(),
+                           entry.elapsed.as_millis());
+        // one write per line, so lines of concurrent writers don't interleave
+        (&self.file).write_all(line.as_bytes())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+// UTC with milliseconds, e.g. 2024-05-01T09:30:00.250Z
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // civil date from days since the epoch, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+            secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, since_epoch.subsec_millis())
+}
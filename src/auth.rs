@@ -0,0 +1,49 @@
+use std::cell::RefCell;
+
+/// Checks the secret, such as a password or token, that users enter before
+/// they get the prompt, see `TerminalBuilder::authenticator`.
+///
+/// ```
+/// use term_rs::Authenticator;
+///
+/// struct Token(String);
+///
+/// impl Authenticator for Token {
+///     fn prompt(&self) -> String {
+///         "token: ".to_owned()
+///     }
+///
+///     fn authenticate(&self, secret: &str) -> Option<String> {
+///         if secret == self.0 { Some("operator".to_owned()) } else { None }
+///     }
+/// }
+/// ```
+pub trait Authenticator {
+    fn prompt(&self) -> String {
+        "password: ".to_owned()
+    }
+
+    /// The name of the user `secret` belongs to, or `None` to refuse it.
+    fn authenticate(&self, secret: &str) -> Option<String>;
+}
+
+thread_local! {
+    // the user of the session whose command is being processed
+    static CURRENT_USER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The user logged in to the session whose command is being processed, for
+/// processors shared by several sessions. `None` outside of processing and
+/// in sessions without an `Authenticator`.
+pub fn current_user() -> Option<String> {
+    CURRENT_USER.with(|user| user.borrow().clone())
+}
+
+// runs `f` with `user` as the current user
+pub(crate) fn as_user<F, T>(user: Option<&str>, f: F) -> T
+    where F: FnOnce() -> T {
+    let previous = CURRENT_USER.with(|current| current.replace(user.map(|u| u.to_owned())));
+    let ret = f();
+    CURRENT_USER.with(|current| *current.borrow_mut() = previous);
+    ret
+}
@@ -0,0 +1,67 @@
+fn partner(c: u8) -> Option<(u8, bool)> {
+    match c {
+        b'(' => Some((b')', true)),
+        b'[' => Some((b']', true)),
+        b'{' => Some((b'}', true)),
+        b')' => Some((b'(', false)),
+        b']' => Some((b'[', false)),
+        b'}' => Some((b'{', false)),
+        _ => None,
+    }
+}
+
+/// Finds the bracket under the cursor, or else the one right before it, and
+/// returns its index with the index of its match, `None` if unbalanced.
+pub fn find_match(buf: &[u8], pos: usize) -> Option<(usize, Option<usize>)> {
+    let at = if pos < buf.len() && partner(buf[pos]).is_some() {
+        pos
+    } else if pos > 0 && partner(buf[pos - 1]).is_some() {
+        pos - 1
+    } else {
+        return None;
+    };
+    let open = buf[at];
+    let (close, forward) = partner(open).unwrap();
+    let mut depth = 0;
+    let mut i = at;
+    loop {
+        if buf[i] == open {
+            depth += 1;
+        } else if buf[i] == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some((at, Some(i)));
+            }
+        }
+        if forward {
+            i += 1;
+            if i == buf.len() {
+                return Some((at, None));
+            }
+        } else {
+            if i == 0 {
+                return Some((at, None));
+            }
+            i -= 1;
+        }
+    }
+}
+
+/// Net number of brackets left open in `text`, ignoring quoted ones.
+pub fn depth(text: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            _ if escaped => { escaped = false; }
+            (_, '\\') => { escaped = true; }
+            (None, '\'') | (None, '"') => { quote = Some(c); }
+            (Some(q), c) if q == c => { quote = None; }
+            (None, '(') | (None, '[') | (None, '{') => { depth += 1; }
+            (None, ')') | (None, ']') | (None, '}') => { depth -= 1; }
+            _ => {}
+        }
+    }
+    depth
+}
@@ -0,0 +1,1090 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use super::audit::AuditSink;
+use super::auth::{self, Authenticator};
+use super::command::{HistoryCipher, HistoryFilter, HistoryFormat};
+use super::completion::{Completer, Matcher, Policy};
+use super::config;
+use super::environment;
+use super::editor::{EditorState, Highlighter};
+use super::keymap::Action;
+use super::limits::RateLimit;
+use super::messages::{English, Messages};
+use super::notify::Notifier;
+use super::plugin::Plugin;
+use super::plain;
+use super::remote::{self, Sessions};
+use super::prompt::{CommandHook, PromptContext, PromptFn, PromptSegments};
+use super::registry::{Param, Registry};
+use super::result::{CommandResult, Pending};
+use super::scrollback::Scrollback;
+use super::stats::SessionStats;
+use super::terminal::Terminal;
+use super::websocket::WebSocketListener;
+use super::workdir;
+
+/// How the terminal signals a key that can't do anything, such as
+/// Backspace at the start of the line or Tab without completions.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Bell {
+    #[default]
+    Audible,
+    /// Flashes the screen instead.
+    Visible,
+    Off,
+}
+
+/// The shape of the cursor, see `TerminalBuilder::cursor_shape`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    /// A vertical bar between characters.
+    Bar,
+}
+
+/// The time output lines start with, see `TerminalBuilder::timestamps`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Timestamps {
+    /// The local time of day, e.g. `14:03:27`.
+    #[default]
+    Absolute,
+    /// The time since the command started, e.g. `+1.250s`.
+    Relative,
+}
+
+/// What batch input does after a command fails, see
+/// `TerminalBuilder::on_error`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ErrorPolicy {
+    /// Runs the next line, reporting nothing more than the command did.
+    #[default]
+    Continue,
+    /// Reports the line and stops reading.
+    Stop,
+    /// Reports the line and asks on the terminal whether to go on,
+    /// stopping if there is no terminal to ask on.
+    Prompt,
+}
+
+/// How much the terminal shows besides command output.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Verbosity {
+    /// Doesn't echo submitted lines or show empty results, for scripts.
+    Quiet,
+    #[default]
+    Normal,
+    /// Also reports each command's status and duration.
+    Verbose,
+}
+
+/// A text style.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Style {
+    #[default]
+    Plain,
+    Bold,
+    Dim,
+    Underline,
+    Reverse,
+    /// The style of error output.
+    Error,
+    /// The style of lines a diff adds: green where there are colors.
+    Added,
+    /// The style of lines a diff removes: red where there are colors.
+    Removed,
+}
+
+pub type CommandHelp = Box<dyn Fn(&str) -> Option<String>>;
+
+pub type CustomAction = Box<dyn Fn(&mut EditorState)>;
+
+pub type Banner = Box<dyn Fn() -> String>;
+
+/// Configures a `Terminal` before running it.
+///
+/// ```no_run
+/// use term_rs::Terminal;
+///
+/// Terminal::builder()
+///     .prompt("app> ")
+///     .run(|command| format!("{}: command not found", command));
+/// ```
+pub struct TerminalBuilder {
+    pub(crate) prompt: String,
+    pub(crate) abbreviations_file: Option<PathBuf>,
+    pub(crate) history_file: Option<PathBuf>,
+    pub(crate) history_imports: Vec<(PathBuf, Option<HistoryFormat>)>,
+    pub(crate) history_cipher: Option<Box<dyn HistoryCipher>>,
+    pub(crate) history_exclude: Option<HistoryFilter>,
+    pub(crate) auto_pairs: bool,
+    pub(crate) match_brackets: bool,
+    pub(crate) highlighter: Option<Box<dyn Highlighter>>,
+    pub(crate) async_highlighter: Option<(Arc<dyn Highlighter + Send + Sync>, Duration)>,
+    pub(crate) multiline: bool,
+    pub(crate) auto_indent: bool,
+    pub(crate) indent_after_brace: bool,
+    pub(crate) completers: Vec<Box<dyn Completer>>,
+    pub(crate) matcher: Matcher,
+    pub(crate) complete_from_history: bool,
+    pub(crate) completion_policy: Policy,
+    pub(crate) command_help: Option<CommandHelp>,
+    pub(crate) snippets: BTreeMap<String, String>,
+    pub(crate) wizards: BTreeMap<String, Vec<Param>>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) timeouts: BTreeMap<String, Duration>,
+    pub(crate) notifier: Option<Notifier>,
+    pub(crate) notifications_in_status: bool,
+    pub(crate) bindings: Vec<(String, Action)>,
+    // key sequences and action names from configuration files
+    pub(crate) config_bindings: Vec<(String, String)>,
+    pub(crate) config_errors: Vec<String>,
+    pub(crate) actions: BTreeMap<&'static str, CustomAction>,
+    pub(crate) key_timeout: Duration,
+    pub(crate) bell: Bell,
+    pub(crate) cursor_shapes: Option<(CursorShape, CursorShape)>,
+    pub(crate) tab_width: usize,
+    pub(crate) expand_tabs: bool,
+    pub(crate) carriage_return_overwrites: bool,
+    pub(crate) max_output: Option<usize>,
+    pub(crate) max_input: Option<usize>,
+    pub(crate) fold_output: Option<usize>,
+    pub(crate) truncate_lines: bool,
+    pub(crate) timestamps: Option<Timestamps>,
+    pub(crate) paginate: bool,
+    pub(crate) page_lines: Option<usize>,
+    pub(crate) pager_keys: (char, char, char),
+    pub(crate) paginated_commands: BTreeMap<String, bool>,
+    pub(crate) command_names: Vec<String>,
+    pub(crate) dry_run: bool,
+    pub(crate) on_error: ErrorPolicy,
+    pub(crate) working_directory: bool,
+    pub(crate) job_indicator: bool,
+    pub(crate) scrollback: Scrollback,
+    pub(crate) transient_prompt: Option<String>,
+    pub(crate) output_prefix: String,
+    pub(crate) error_prefix: String,
+    pub(crate) prompt_fn: Option<PromptFn>,
+    pub(crate) failure_prompt_style: bool,
+    pub(crate) after_command: Option<CommandHook>,
+    pub(crate) verbosity: Verbosity,
+    pub(crate) banner: Option<Banner>,
+    pub(crate) banner_style: Style,
+    pub(crate) colors: bool,
+    pub(crate) escape_timeout: Duration,
+    pub(crate) escape_pasted_controls: bool,
+    pub(crate) confirm_paste: bool,
+    pub(crate) messages: Box<dyn Messages>,
+    pub(crate) plain: bool,
+    pub(crate) sessions: Sessions,
+    pub(crate) stats: SessionStats,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) authenticator: Option<Box<dyn Authenticator>>,
+    pub(crate) login_attempts: usize,
+    pub(crate) idle_lock: Option<Duration>,
+    pub(crate) unlock: Option<Box<dyn Fn() -> bool>>,
+    pub(crate) audit: Option<Box<dyn AuditSink>>,
+    pub(crate) plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Default for TerminalBuilder {
+    fn default() -> Self {
+        TerminalBuilder {
+            prompt: "debug> ".to_owned(),
+            abbreviations_file: None,
+            history_file: None,
+            history_imports: Vec::new(),
+            history_cipher: None,
+            history_exclude: None,
+            auto_pairs: false,
+            match_brackets: false,
+            highlighter: None,
+            async_highlighter: None,
+            multiline: false,
+            auto_indent: true,
+            indent_after_brace: false,
+            completers: Vec::new(),
+            matcher: Matcher::Prefix,
+            complete_from_history: false,
+            completion_policy: Policy::List,
+            command_help: None,
+            snippets: BTreeMap::new(),
+            wizards: BTreeMap::new(),
+            timeout: None,
+            timeouts: BTreeMap::new(),
+            notifier: None,
+            notifications_in_status: false,
+            bindings: Vec::new(),
+            config_bindings: Vec::new(),
+            config_errors: Vec::new(),
+            actions: BTreeMap::new(),
+            key_timeout: Duration::from_secs(1),
+            bell: Bell::Audible,
+            cursor_shapes: None,
+            tab_width: 8,
+            expand_tabs: false,
+            carriage_return_overwrites: false,
+            max_output: Some(16 << 20),
+            max_input: None,
+            fold_output: None,
+            truncate_lines: false,
+            timestamps: None,
+            paginate: false,
+            page_lines: None,
+            pager_keys: (' ', '\n', 'q'),
+            paginated_commands: BTreeMap::new(),
+            command_names: Vec::new(),
+            dry_run: false,
+            on_error: ErrorPolicy::Continue,
+            working_directory: false,
+            job_indicator: false,
+            scrollback: Scrollback::default(),
+            transient_prompt: None,
+            output_prefix: String::new(),
+            error_prefix: String::new(),
+            prompt_fn: None,
+            failure_prompt_style: false,
+            after_command: None,
+            verbosity: Verbosity::Normal,
+            banner: None,
+            banner_style: Style::Plain,
+            colors: true,
+            escape_timeout: Duration::from_millis(50),
+            escape_pasted_controls: false,
+            confirm_paste: true,
+            messages: Box::new(English),
+            plain: false,
+            sessions: Sessions::default(),
+            stats: SessionStats::default(),
+            rate_limit: None,
+            authenticator: None,
+            login_attempts: 3,
+            idle_lock: None,
+            unlock: None,
+            audit: None,
+            plugins: Vec::new(),
+        }
+    }
+}
+
+impl TerminalBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_owned();
+        self
+    }
+
+    /// Computes the prompt before each command line, e.g. to show whether
+    /// the last one failed. Overrides `prompt`.
+    pub fn prompt_fn<P>(mut self, prompt: P) -> Self
+        where P: Fn(&PromptContext) -> String + 'static {
+        self.prompt_fn = Some(Box::new(prompt));
+        self
+    }
+
+    /// Composes the prompt of `segments`, rendering again only the ones
+    /// that are stale. Overrides `prompt` and `prompt_fn`.
+    pub fn prompt_segments(self, segments: PromptSegments) -> Self {
+        self.prompt_fn(move |context| segments.render(context))
+    }
+
+    /// Shows the prompt in the error style after a failed command line.
+    pub fn failure_prompt_style(mut self, failure_prompt_style: bool) -> Self {
+        self.failure_prompt_style = failure_prompt_style;
+        self
+    }
+
+    /// Calls `hook` with every command line run and whether it succeeded.
+    pub fn after_command<H>(mut self, hook: H) -> Self
+        where H: Fn(&str, bool) + 'static {
+        self.after_command = Some(Box::new(hook));
+        self
+    }
+
+    /// Loads abbreviations from, and saves `abbr` changes to, `path`.
+    pub fn abbreviations_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.abbreviations_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Loads history from `path` at startup and saves it after every command.
+    pub fn history_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.history_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Appends the entries of an existing readline/bash history file, such
+    /// as `~/.app_history`, to the history at startup. zsh and fish history
+    /// files, e.g. `~/.zsh_history` or `~/.local/share/fish/fish_history`,
+    /// are recognized by their names or contents and converted, see
+    /// `HistoryFormat::detect`.
+    pub fn import_history<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.history_imports.push((path.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Like `import_history`, for a file known to be in `format`, such as a
+    /// zsh history without timestamps under a name not telling it.
+    pub fn import_history_as<P: AsRef<Path>>(mut self, path: P, format: HistoryFormat) -> Self {
+        self.history_imports.push((path.as_ref().to_owned(), Some(format)));
+        self
+    }
+
+    /// Encrypts the history file with `cipher`.
+    pub fn history_cipher<C: HistoryCipher + 'static>(mut self, cipher: C) -> Self {
+        self.history_cipher = Some(Box::new(cipher));
+        self
+    }
+
+    /// Never records commands for which `exclude` returns true, neither in
+    /// memory nor in the history file, e.g. `|c| c.starts_with("login ")`.
+    pub fn history_exclude<P: Fn(&str) -> bool + 'static>(mut self, exclude: P) -> Self {
+        self.history_exclude = Some(Box::new(exclude));
+        self
+    }
+
+    /// Inserts the closing `)]}"'` along with the opening one, types over
+    /// an existing closer and deletes an empty pair with one backspace.
+    pub fn auto_pairs(mut self, auto_pairs: bool) -> Self {
+        self.auto_pairs = auto_pairs;
+        self
+    }
+
+    /// Highlights the bracket matching the one at or before the cursor, and
+    /// shows an unbalanced one in red.
+    pub fn match_brackets(mut self, match_brackets: bool) -> Self {
+        self.match_brackets = match_brackets;
+        self
+    }
+
+    /// Styles the line after each key, e.g. to show the command name in
+    /// bold or unknown ones as errors. Matched brackets and snippet
+    /// placeholders are shown over it.
+    pub fn highlighter<H>(mut self, highlighter: H) -> Self
+        where H: Highlighter + 'static {
+        self.highlighter = Some(Box::new(highlighter));
+        self.async_highlighter = None;
+        self
+    }
+
+    /// Like `highlighter`, for a highlighter too slow to run on every key,
+    /// e.g. one parsing the line: it runs on a background thread once
+    /// typing pauses for `delay`, and the line is styled when it is done.
+    /// Meanwhile, the styles of the last line highlighted stay on while the
+    /// text is the same.
+    pub fn async_highlighter<H>(mut self, highlighter: H, delay: Duration) -> Self
+        where H: Highlighter + Send + Sync + 'static {
+        self.async_highlighter = Some((Arc::new(highlighter), delay));
+        self.highlighter = None;
+        self
+    }
+
+    /// Keeps reading continuation lines while brackets are unclosed or a
+    /// line ends in `\`, and submits them as one newline-joined command.
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Starts continuation lines with the previous line's indentation.
+    /// On by default.
+    pub fn auto_indent(mut self, auto_indent: bool) -> Self {
+        self.auto_indent = auto_indent;
+        self
+    }
+
+    /// Indents a continuation line by four more spaces when the previous
+    /// line ends with an opening bracket.
+    pub fn indent_after_brace(mut self, indent_after_brace: bool) -> Self {
+        self.indent_after_brace = indent_after_brace;
+        self
+    }
+
+    /// Adds a source of Tab completion candidates. Candidates of all
+    /// completers are offered together.
+    pub fn completer<C: Completer + 'static>(mut self, completer: C) -> Self {
+        self.completers.push(Box::new(completer));
+        self
+    }
+
+    /// Sets how candidates are matched against the word being completed,
+    /// for completers that don't choose their own. Defaults to
+    /// `Matcher::Prefix`.
+    pub fn completion_matcher(mut self, matcher: Matcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    /// Sets what Tab does with several candidates. Defaults to `Policy::List`.
+    pub fn completion_policy(mut self, policy: Policy) -> Self {
+        self.completion_policy = policy;
+        self
+    }
+
+    /// Also offers the arguments previously given to the same command, at the
+    /// same position, as completion candidates.
+    pub fn complete_from_history(mut self, complete_from_history: bool) -> Self {
+        self.complete_from_history = complete_from_history;
+        self
+    }
+
+    /// Looks up the help F1 shows for the command being typed.
+    pub fn command_help<H>(mut self, help: H) -> Self
+        where H: Fn(&str) -> Option<String> + 'static {
+        self.command_help = Some(Box::new(help));
+        self
+    }
+
+    /// Registers a template such as `"connect ${host} ${port}"`, inserted
+    /// in place of `name` by completing it or by Ctrl+X s. Tab then moves
+    /// between the `${...}` placeholders, each highlighted until typed over.
+    pub fn snippet(mut self, name: &str, template: &str) -> Self {
+        self.snippets.insert(name.to_owned(), template.to_owned());
+        self
+    }
+
+    /// Lets `wizard <command>` ask for each of `params` in turn, then run
+    /// `command` with them. `run_registry` does this for the commands that
+    /// declare params.
+    pub fn wizard(mut self, command: &str, params: Vec<Param>) -> Self {
+        self.wizards.insert(command.to_owned(), params);
+        self
+    }
+
+    /// Registers an action keys can be bound to as `Action::Custom(name)`,
+    /// which may read and change the line being edited.
+    ///
+    /// ```no_run
+    /// use term_rs::{Action, EditorState, Terminal};
+    ///
+    /// Terminal::builder()
+    ///     .action("insert-greeting", |state: &mut EditorState| state.insert("hello "))
+    ///     .bind("Ctrl+X g", Action::Custom("insert-greeting"))
+    ///     .run(|command| command);
+    /// ```
+    pub fn action<F>(mut self, name: &'static str, action: F) -> Self
+        where F: Fn(&mut EditorState) + 'static {
+        self.actions.insert(name, Box::new(action));
+        self
+    }
+
+    /// Reads settings from a TOML file, so that users of the application
+    /// can change them. Settings given to the builder, before or after,
+    /// take precedence over the file's, unless they are left at their
+    /// defaults. A missing file is ignored and errors are reported at
+    /// startup.
+    ///
+    /// ```toml
+    /// prompt = "app> "
+    /// transient_prompt = "> "
+    /// verbosity = "normal"              # quiet, normal or verbose
+    ///
+    /// [theme]
+    /// banner_style = "bold"             # plain, bold, dim, underline, reverse, error, added or removed
+    /// failure_prompt = true
+    /// output_prefix = ""
+    /// error_prefix = "! "
+    /// bell = "visible"                  # audible, visible or off
+    /// timestamps = "relative"           # absolute, relative or off
+    ///
+    /// [pager]
+    /// enabled = true
+    /// lines = 20                        # the height of the terminal if unset
+    ///
+    /// [keys]
+    /// "Ctrl+P" = "prev-command"         # action names in kebab case, or
+    /// "Ctrl+X g" = "insert-greeting"    # those registered with `action`
+    ///
+    /// [history]
+    /// file = "/home/me/.app_history"
+    /// import = "/home/me/.bash_history"  # or a zsh or fish history file
+    ///
+    /// [completion]
+    /// matcher = "substring"             # prefix, case-insensitive or substring
+    /// policy = "two-stage"              # list or two-stage
+    /// from_history = true
+    ///
+    /// [editing]
+    /// auto_pairs = true
+    /// match_brackets = true
+    /// multiline = true
+    /// tab_width = 4
+    /// confirm_paste = false
+    /// ```
+    pub fn load_config<P: AsRef<Path>>(self, path: P) -> Self {
+        config::load(self, path.as_ref())
+    }
+
+    /// Binds a key sequence such as `"Ctrl+X Ctrl+S"` or `"g g"` to `action`.
+    /// Invalid sequences are reported at startup.
+    pub fn bind(mut self, keys: &str, action: Action) -> Self {
+        self.bindings.push((keys.to_owned(), action));
+        self
+    }
+
+    /// Sets how long to wait for the next key of a sequence before acting on
+    /// the keys typed so far. Defaults to one second.
+    pub fn key_timeout(mut self, timeout: Duration) -> Self {
+        self.key_timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait after ESC for the rest of an Alt combination or
+    /// function key sequence before taking it as a bare ESC press. Defaults
+    /// to 50 milliseconds.
+    pub fn escape_timeout(mut self, timeout: Duration) -> Self {
+        self.escape_timeout = timeout;
+        self
+    }
+
+    /// Shows control characters in pasted text in caret notation, e.g. `^[`
+    /// for ESC, instead of dropping them. Either way they are never taken as
+    /// keys, as terminals are asked to mark pastes (bracketed paste mode);
+    /// line breaks are kept and tabs become spaces. Defaults to false.
+    pub fn escape_pasted_controls(mut self, escape: bool) -> Self {
+        self.escape_pasted_controls = escape;
+        self
+    }
+
+    /// Asks before running a paste of several lines, showing them in an
+    /// overlay: `y` runs them one by one, as if typed, any other key drops
+    /// the paste. Off, they run straight away. Defaults to true.
+    pub fn confirm_paste(mut self, confirm: bool) -> Self {
+        self.confirm_paste = confirm;
+        self
+    }
+
+    /// Sets how keys that can't do anything are signaled. Defaults to
+    /// `Bell::Audible`.
+    pub fn bell(mut self, bell: Bell) -> Self {
+        self.bell = bell;
+        self
+    }
+
+    /// Shows the cursor as `insert` while typing inserts and as `overwrite`
+    /// in overwrite mode, e.g. a bar and a block. Only done on terminals
+    /// known to take the DECSCUSR sequence that sets it, such as xterm,
+    /// VTE-based ones and tmux; others keep their cursor, as is the default.
+    pub fn cursor_shape(mut self, insert: CursorShape, overwrite: CursorShape) -> Self {
+        self.cursor_shapes = Some((insert, overwrite));
+        self
+    }
+
+    /// Sets the distance between tab stops in output. Defaults to 8; other
+    /// widths imply `expand_tabs`.
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width;
+        self
+    }
+
+    /// Replaces tabs in output with spaces, so the screen holds the aligned
+    /// text rather than tab characters.
+    pub fn expand_tabs(mut self, expand_tabs: bool) -> Self {
+        self.expand_tabs = expand_tabs;
+        self
+    }
+
+    /// Makes a bare `\r` in output return to the start of the line and
+    /// overwrite it, as progress and spinner output expects, instead of
+    /// starting a new line.
+    pub fn carriage_return_overwrites(mut self, overwrites: bool) -> Self {
+        self.carriage_return_overwrites = overwrites;
+        self
+    }
+
+    /// Shows at most `max` bytes of a command's output, followed by a
+    /// truncation notice; `None` shows everything. Defaults to 16 MiB.
+    /// Long output is drawn in chunks either way and can be cut short with
+    /// Ctrl+C.
+    pub fn max_output(mut self, max: Option<usize>) -> Self {
+        self.max_output = max;
+        self
+    }
+
+    /// Shows output of more than `lines` lines as a one-line summary, which
+    /// Ctrl+X o (`Action::ToggleFold`) expands in place and folds again
+    /// until the next command. Defaults to `None`, showing all output, as
+    /// plain mode always does.
+    pub fn fold_output(mut self, lines: Option<usize>) -> Self {
+        self.fold_output = lines;
+        self
+    }
+
+    /// Cuts output lines wider than the screen, ending them with an
+    /// ellipsis, instead of wrapping them, which leaves wide tables
+    /// unreadable. Ctrl+X w (`Action::ToggleWrap`) switches between the
+    /// two for the output that follows. Defaults to `false`.
+    pub fn truncate_lines(mut self, truncate: bool) -> Self {
+        self.truncate_lines = truncate;
+        self
+    }
+
+    /// Starts every output line with the time it was printed, to match it
+    /// with the logs of a server, say. Ctrl+X t (`Action::ToggleTimestamps`)
+    /// shows or hides them; when `None`, the default, they start hidden and
+    /// show the time of day once shown. Plain mode can't toggle them.
+    pub fn timestamps(mut self, timestamps: Option<Timestamps>) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Shows output taller than a page a screenful at a time, asking
+    /// `-- More --` in between. Defaults to `false`; `command_paginate`
+    /// overrides it for commands that stream on purpose.
+    pub fn paginate(mut self, paginate: bool) -> Self {
+        self.paginate = paginate;
+        self
+    }
+
+    /// The rows of output `paginate` shows at a time, or `None`, the
+    /// default, for the height of the terminal less the prompt's row.
+    pub fn page_lines(mut self, lines: Option<usize>) -> Self {
+        self.page_lines = lines;
+        self
+    }
+
+    /// The keys that show the next page, the next line, and that quit at the
+    /// `-- More --` prompt. Default to Space, Enter and `q`.
+    pub fn pager_keys(mut self, next_page: char, next_line: char, quit: char) -> Self {
+        self.pager_keys = (next_page, next_line, quit);
+        self
+    }
+
+    /// Overrides `paginate` for `command`.
+    pub fn command_paginate(mut self, command: &str, paginate: bool) -> Self {
+        self.paginated_commands.insert(command.to_owned(), paginate);
+        self
+    }
+
+    /// Prints each command of a script or other batch input instead of
+    /// running it: lines are split at `;` and `&&` as usual, and every
+    /// command is taken to succeed. Commands are shown with `$VAR`,
+    /// `${VAR:-default}` and `~` expanded, as `parse::split_expanded` and a
+    /// `Registry` with `expand_vars` do, and arguments quoted where needed;
+    /// any pipe or redirection is left in as written. Abbreviations are
+    /// only expanded as they are typed, so batch input has none to expand.
+    /// Only plain mode reads batch input, see
+    /// `plain`; the `TERM_RS_DRY_RUN` variable turns it on too. Defaults to
+    /// `false`.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// What a script or other batch input does once one of its lines
+    /// fails: go on, stop, or ask. Stopping and asking report the number of
+    /// the failed line and the line itself. Only plain mode reads batch
+    /// input, see `plain`. Defaults to `ErrorPolicy::Continue`.
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Keeps a working directory for each session, starting at the
+    /// process's, which the `cd [dir]` built-in changes and `pwd` prints.
+    /// Processors and completers find it with `workdir::current_dir`, and
+    /// the directory `cd` takes completes from it. The process's own
+    /// directory never changes. Defaults to `false`.
+    pub fn working_directory(mut self, track: bool) -> Self {
+        self.working_directory = track;
+        self
+    }
+
+    /// Starts the prompt with the number of background jobs still running,
+    /// e.g. `[1+] debug> `, updated as they complete. Defaults to `false`.
+    pub fn job_indicator(mut self, show: bool) -> Self {
+        self.job_indicator = show;
+        self
+    }
+
+    /// Keeps the line being edited to at most `max` characters: typing more
+    /// rings the bell, and the length shows in the status area once it is
+    /// reached. Longer lines read in plain mode are refused. Defaults to
+    /// `None`, no limit.
+    pub fn max_input(mut self, max: Option<usize>) -> Self {
+        self.max_input = max;
+        self
+    }
+
+    /// Keeps the scrollback in `scrollback`, e.g. `Scrollback::new(1000,
+    /// 1 << 20)`, instead of the default buffer of 10000 lines or 4 MiB.
+    /// Keep a clone to query or clear it while the terminal runs.
+    pub fn scrollback(mut self, scrollback: Scrollback) -> Self {
+        self.scrollback = scrollback;
+        self
+    }
+
+    /// Replaces the prompt of submitted commands with `marker`, e.g. `"> "`,
+    /// keeping the screen and scrollback compact under a rich prompt.
+    pub fn transient_prompt(mut self, marker: &str) -> Self {
+        self.transient_prompt = Some(marker.to_owned());
+        self
+    }
+
+    /// Starts every line of command output with `prefix`, such as `"  "` or
+    /// `"│ "`, including the rows long lines wrap onto.
+    pub fn output_prefix(mut self, prefix: &str) -> Self {
+        self.output_prefix = prefix.to_owned();
+        self
+    }
+
+    /// Starts every line of error output with `prefix`, e.g. `"error: "`.
+    pub fn error_prefix(mut self, prefix: &str) -> Self {
+        self.error_prefix = prefix.to_owned();
+        self
+    }
+
+    /// Sets how much is shown besides command output; the `set output
+    /// quiet|normal|verbose` built-in changes it at runtime. Defaults to
+    /// `Verbosity::Normal`.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Prints `banner` once before the first prompt, wrapped at spaces to
+    /// the screen width.
+    pub fn banner(self, banner: &str) -> Self {
+        let banner = banner.to_owned();
+        self.banner_fn(move || banner.clone())
+    }
+
+    /// Like `banner`, computing the text at startup, e.g. to show
+    /// connection details.
+    pub fn banner_fn<B: Fn() -> String + 'static>(mut self, banner: B) -> Self {
+        self.banner = Some(Box::new(banner));
+        self
+    }
+
+    /// The style the banner is printed in. Only the curses terminal styles
+    /// it: plain mode and remote clients get the text alone. Defaults to
+    /// `Style::Plain`.
+    pub fn banner_style(mut self, style: Style) -> Self {
+        self.banner_style = style;
+        self
+    }
+
+    /// Replaces the terminal's own messages, e.g. to translate them.
+    pub fn messages<M>(mut self, messages: M) -> Self
+        where M: Messages + 'static {
+        self.messages = Box::new(messages);
+        self
+    }
+
+    /// Runs a plain line-based interface instead of the full-screen one,
+    /// for screen readers: the prompt and output are printed once and never
+    /// redrawn, and lines are edited by the terminal itself, so key
+    /// bindings, completion and the editing built-ins are unavailable. Also
+    /// enabled by setting `TERM_RS_PLAIN=1`, and when curses can't be used:
+    /// input or output isn't a terminal, or `TERM` is `dumb`, unset or has
+    /// no terminfo entry. `run` then returns at the end of input.
+    pub fn plain(mut self, plain: bool) -> Self {
+        self.plain = plain;
+        self
+    }
+
+    /// Runs the terminal until the end of input, passing each command to
+    /// `process`. Users can change any application with environment
+    /// variables, which override its own settings:
+    ///
+    /// - `TERM_RS_CONFIG`: a configuration file, see `load_config`.
+    /// - `TERM_RS_HISTFILE`: the history file.
+    /// - `TERM_RS_NO_COLOR` or `NO_COLOR`: show errors in bold rather than
+    ///   red.
+    /// - `TERM_RS_DRY_RUN`: print commands instead of running them, see
+    ///   `dry_run`.
+    /// - `TERM_RS_PLAIN`: run in plain mode, see `plain`.
+    pub fn run<F, R>(self, process: F)
+        where F: Fn(String) -> R,
+              R: Into<CommandResult> {
+        let builder = environment::apply(self);
+        if builder.plain || plain::requested() || plain::needed() {
+            plain::run(builder, process)
+        } else {
+            Terminal::start(builder, process)
+        }
+    }
+
+    /// Shows `output` as it is sent, without a prompt or line editing, e.g.
+    /// for a log viewer, until every sender is dropped. The output is kept
+    /// in the scrollback, which the keys bound to `Action::CopyMode` open.
+    ///
+    /// ```no_run
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    /// use term_rs::Terminal;
+    ///
+    /// let (sender, output) = mpsc::channel();
+    /// thread::spawn(move || for i in 0..100 {
+    ///     sender.send(format!("event {}", i)).unwrap();
+    /// });
+    /// Terminal::builder().run_read_only(output);
+    /// ```
+    pub fn run_read_only(self, output: Receiver<String>) {
+        let builder = environment::apply(self);
+        if builder.plain || plain::requested() || plain::needed() {
+            plain::view(builder, output)
+        } else {
+            Terminal::open(builder, |command: String| command).view(output)
+        }
+    }
+
+    /// Like `run`, but runs each command on a worker thread, so the
+    /// terminal shows a spinner with the time taken while a command is slow
+    /// rather than appearing frozen. Past its `timeout`, the user may keep
+    /// waiting, leave the command running in the background or cancel its
+    /// `cancel_token`.
+    pub fn run_threaded<F, R>(self, process: F)
+        where F: Fn(String) -> R + Send + Sync + 'static,
+              R: Into<CommandResult> {
+        let process = Arc::new(process);
+        self.run(move |line: String| {
+            let pending = Pending::new();
+            let (process, done, user, dir) = (process.clone(), pending.clone(), auth::current_user(), workdir::tracked());
+            thread::spawn(move || {
+                done.enter();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    workdir::in_dir(dir.as_deref(), || auth::as_user(user.as_deref(), || process(line).into()))
+                }));
+                done.complete(result.unwrap_or_else(|panic| {
+                    let message = panic.downcast_ref::<&str>().map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    CommandResult::err(format!("panicked: {}", message))
+                }));
+            });
+            CommandResult::pending(pending)
+        })
+    }
+
+    /// How long commands of `run_threaded` run before the terminal asks
+    /// whether to keep waiting.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Overrides `timeout` for `command`.
+    pub fn command_timeout(mut self, command: &str, timeout: Duration) -> Self {
+        self.timeouts.insert(command.to_owned(), timeout);
+        self
+    }
+
+    /// Serves `process` on `listener` instead of the local terminal, with a
+    /// telnet-style line protocol: each client gets the prompt, sends a line
+    /// and receives its output, as in `plain` mode. Any number of clients
+    /// can be connected, each with a history of its own, while their
+    /// commands run one at a time on the calling thread, so `process` needs
+    /// no synchronization. A daemon typically calls this from a thread of
+    /// its own. Returns when accepting a connection fails.
+    ///
+    /// Sessions survive their connection: `detach`, or losing the
+    /// connection, keeps a session with its history and scrollback, and
+    /// `attach <id>` resumes it from any connection of the same user,
+    /// replaying what it has shown. `sessions` lists the detached ones the
+    /// user can attach to. Only sessions served this way can be detached,
+    /// not that of the local terminal, and detached sessions are only kept
+    /// in memory: at most 16 of them, the oldest ending first, and none
+    /// outlives the server.
+    ///
+    /// ```no_run
+    /// use std::net::TcpListener;
+    /// use term_rs::Terminal;
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:4000").unwrap();
+    /// Terminal::builder()
+    ///     .prompt("daemon> ")
+    ///     .serve_tcp(listener, |command| format!("{}: command not found", command))
+    ///     .unwrap();
+    /// ```
+    pub fn serve_tcp<F, R>(self, listener: TcpListener, process: F) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult> {
+        remote::serve_tcp(self, listener, process)
+    }
+
+    /// Like `serve_tcp`, on a Unix domain socket.
+    #[cfg(unix)]
+    pub fn serve_unix<F, R>(self, listener: UnixListener, process: F) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult> {
+        remote::serve_unix(self, listener, process)
+    }
+
+    /// Like `serve_tcp`, for terminal emulators in a web page such as
+    /// xterm.js connecting over WebSocket. Keystrokes are sent as text
+    /// messages and echoed back, with Backspace, Ctrl+U and Ctrl+C editing
+    /// the line; output is sent as text messages with `\r\n` line ends.
+    /// Messages may be fragmented, even within a character. Clients sending
+    /// an unmasked frame or one over 64 KiB are disconnected.
+    ///
+    /// This is a plain text bridge, not a JSON protocol, and the curses
+    /// editor isn't run for the connection: the arrows, history browsing
+    /// and completion aren't available to WebSocket clients.
+    pub fn serve_websocket<F, R>(self, listener: TcpListener, process: F) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult> {
+        remote::serve(self, WebSocketListener::new(listener), process)
+    }
+
+    /// Counts commands and their latency in `stats`, which the `stats`
+    /// built-in shows. Remote sessions all count in the same one.
+    pub fn stats(mut self, stats: SessionStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Limits each session, local or remote, to `limit`, which remote
+    /// sessions of the same user or address share. Refused commands, the
+    /// remote built-ins included, fail with a message saying when to retry.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use term_rs::{RateLimit, Terminal};
+    ///
+    /// let limit = RateLimit::new(10, Duration::from_secs(1))
+    ///     .lockout("login", 3, Duration::from_secs(60));
+    /// Terminal::builder().rate_limit(limit).run(|command| command);
+    /// ```
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Asks for a secret without showing it before the first prompt, and
+    /// on every remote connection, until `authenticator` accepts it. After
+    /// `login_attempts` refusals the terminal exits, or the connection is
+    /// closed.
+    pub fn authenticator<A>(mut self, authenticator: A) -> Self
+        where A: Authenticator + 'static {
+        self.authenticator = Some(Box::new(authenticator));
+        self
+    }
+
+    /// How many secrets `authenticator` may refuse in a row, when logging
+    /// in or unlocking after `idle_lock`, before the terminal exits or the
+    /// connection is closed. An `attempts` of 0 counts as 1. Defaults to 3.
+    pub fn login_attempts(mut self, attempts: usize) -> Self {
+        self.login_attempts = attempts.max(1);
+        self
+    }
+
+    /// Locks the terminal after `timeout` at the prompt without a key
+    /// pressed, as for an operator console left unattended: the screen is
+    /// cleared and the secret of the logged-in user asked for again, see
+    /// `authenticator`, or `unlock_with` called. The output is drawn again
+    /// only once unlocked. Without either, any key unlocks it.
+    pub fn idle_lock(mut self, timeout: Duration) -> Self {
+        self.idle_lock = Some(timeout);
+        self
+    }
+
+    /// Unlocks a terminal locked by `idle_lock` with `unlock`, e.g. a
+    /// badge reader, instead of the authenticator's prompt. It runs with
+    /// curses ended, as in `suspend_ui`, and returns whether to unlock;
+    /// otherwise the terminal exits.
+    pub fn unlock_with<U>(mut self, unlock: U) -> Self
+        where U: Fn() -> bool + 'static {
+        self.unlock = Some(Box::new(unlock));
+        self
+    }
+
+    /// Records every command run, local or remote, with its user, session
+    /// and outcome in `sink`, e.g. a `JsonlAudit`.
+    pub fn audit<A>(mut self, sink: A) -> Self
+        where A: AuditSink + 'static {
+        self.audit = Some(Box::new(sink));
+        self
+    }
+
+    /// Adds `plugin`, whose hooks are called in the order plugins are added.
+    pub fn plugin<P>(mut self, plugin: P) -> Self
+        where P: Plugin + 'static {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Shows the notifications sent through `notifier` above the prompt.
+    pub fn notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Also shows the last warning or error in the status area until it is
+    /// acknowledged with Ctrl+X n.
+    pub fn notifications_in_status(mut self, in_status: bool) -> Self {
+        self.notifications_in_status = in_status;
+        self
+    }
+
+    /// Keeps track of the clients of `serve_tcp` and `serve_unix`, to
+    /// broadcast messages to them.
+    pub fn sessions(mut self, sessions: Sessions) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Runs a single `serve_tcp` session on any byte stream, such as the
+    /// channel of an SSH server library, and returns when `input` ends.
+    /// Lines are expected to end with `\n`: there is no line editing, so
+    /// channels must not request a pty, or have it echo and edit locally.
+    ///
+    /// This is the only SSH integration: there is no SSH server feature,
+    /// and no pty or window size handling. Users are authenticated by the
+    /// SSH server, `authenticator` not being asked. With OpenSSH, a program
+    /// serving its standard input and output can be the `ForceCommand` of
+    /// the console's users, with `PermitTTY no`:
+    ///
+    /// ```no_run
+    /// use std::io;
+    /// use term_rs::Terminal;
+    ///
+    /// Terminal::builder()
+    ///     .prompt("console> ")
+    ///     .serve_stream(io::stdin(), io::stdout(), |command| format!("{}: command not found", command))
+    ///     .unwrap();
+    /// ```
+    pub fn serve_stream<F, R, I, O>(self, input: I, output: O, process: F) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult>,
+              I: Read,
+              O: Write {
+        remote::serve_stream(self, input, output, process)
+    }
+
+    /// Runs the terminal dispatching to `registry`, whose command names
+    /// complete the first word, whose help texts F1 shows, whose declared
+    /// params `wizard` asks for and whose commands'
+    /// `paginate` settings apply unless `command_paginate` set them.
+    pub fn run_registry(mut self, registry: Registry) {
+        let help: BTreeMap<String, String> = registry.commands()
+            .filter(|c| !c.help_text().is_empty())
+            .map(|c| (c.name().to_owned(), c.help_text().to_owned()))
+            .collect();
+        for command in registry.commands().filter(|c| !c.params().is_empty()) {
+            self.wizards.insert(command.name().to_owned(), command.params().to_vec());
+        }
+        self.command_names = registry.commands().map(|c| c.name().to_owned()).collect();
+        self.command_names.push("help".to_owned());
+        for command in registry.commands() {
+            if let Some(paginate) = command.paginated() {
+                self.paginated_commands.entry(command.name().to_owned()).or_insert(paginate);
+            }
+        }
+        self.command_help(move |name| help.get(name).cloned())
+            .run(registry.into_process())
+    }
+}
@@ -0,0 +1,43 @@
+use super::pipe::find_unquoted;
+
+/// How a command in a chain depends on the one before it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Op {
+    /// First command, or separated by `;`: always runs.
+    Always,
+    /// Separated by `&&`: runs only if the previous command succeeded.
+    IfSuccess,
+}
+
+/// Splits `a && b; c` into its commands at unquoted `;` and `&&`.
+pub fn split(line: &str) -> Vec<(Op, &str)> {
+    let mut ret = Vec::new();
+    let mut op = Op::Always;
+    let mut rest = line;
+    loop {
+        let next = match (find_unquoted(rest, ";"), find_unquoted(rest, "&&")) {
+            (Some(a), Some(b)) if b < a => Some((b, 2, Op::IfSuccess)),
+            (Some(a), _) => Some((a, 1, Op::Always)),
+            (None, Some(b)) => Some((b, 2, Op::IfSuccess)),
+            (None, None) => None,
+        };
+        match next {
+            Some((i, len, next_op)) => {
+                ret.push((op, &rest[..i]));
+                op = next_op;
+                rest = &rest[i + len..];
+            }
+            None => {
+                ret.push((op, rest));
+                break;
+            }
+        }
+    }
+    if ret.len() > 1 {
+        ret = ret.into_iter()
+            .map(|(op, command)| (op, command.trim()))
+            .filter(|&(_, command)| !command.is_empty())
+            .collect();
+    }
+    ret
+}
@@ -1,40 +1,523 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::normalize;
+
+/// Encrypts the persisted history, for applications whose commands may
+/// contain secrets. The crate ships no cipher of its own: implement this
+/// over age, AES-GCM or similar, typically fetching the key from a callback
+/// or keyring when constructed.
+pub trait HistoryCipher {
+    fn encrypt(&self, plain: &[u8]) -> Vec<u8>;
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+pub type HistoryFilter = Box<dyn Fn(&str) -> bool>;
+
+struct Entry {
+    command: String,
+    // seconds since the epoch, as in readline's `#<time>` lines
+    timestamp: Option<u64>,
+    // how long it ran, known for the commands of this session and those
+    // imported from zsh, but not kept in the history file
+    duration: Option<Duration>,
+}
+
+/// How often a command was run, and for how long, as `top_commands` counts
+/// it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandUsage {
+    pub name: String,
+    pub count: usize,
+    /// The runs whose duration is known, which `total` adds up.
+    pub timed: usize,
+    pub total: Duration,
+}
+
+impl CommandUsage {
+    pub fn mean(&self) -> Duration {
+        if self.timed == 0 {
+            Duration::default()
+        } else {
+            self.total / self.timed as u32
+        }
+    }
+}
+
+/// The commands entered, with when and for how long they ran, as a
+/// terminal keeps them in its history file.
 #[derive(Default)]
 pub struct CommandHistory {
-    history: Vec<String>,
+    history: Vec<Entry>,
     cur: i32,
+    // the unsubmitted line kept as the last entry while browsing
+    draft: bool,
 }
 
 impl CommandHistory {
-    pub fn default() -> Self {
-        Default::default()
-    }
-
     pub fn prev_command(&mut self) -> Option<&String> {
-        if self.cur < 0 {
+        if self.cur <= 0 {
             None
         } else {
             self.cur -= 1;
-            self.history.get(self.cur as usize)
+            self.get(self.cur as usize)
 
         }
     }
 
     pub fn next_command(&mut self) -> Option<&String> {
-        if self.cur == self.history.len() as i32 {
+        if self.cur + 1 >= self.history.len() as i32 {
             None
         } else {
             self.cur += 1;
-            let ret = self.history.get(self.cur as usize);
-            ret
+            self.get(self.cur as usize)
         }
     }
 
     pub fn add_command(&mut self, command: String) {
-        self.history.push(command);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+        self.push(command, now, None);
+    }
+
+    /// Records how long the last command added ran.
+    pub fn set_duration(&mut self, duration: Duration) {
+        if let Some(last) = self.len().checked_sub(1) {
+            self.history[last].duration = Some(duration);
+        }
+    }
+
+    fn push(&mut self, command: String, timestamp: Option<u64>, duration: Option<Duration>) {
+        let command = normalize::into_nfc(command);
+        if self.draft {
+            self.history.pop();
+            self.draft = false;
+        }
+        self.history.push(Entry { command, timestamp, duration });
         self.cur = self.history.len() as i32;
     }
 
+    /// Keeps the line being edited reachable with Down while browsing.
+    pub fn save_draft(&mut self, command: String) {
+        if self.draft {
+            self.history.pop();
+        }
+        self.history.push(Entry { command, timestamp: None, duration: None });
+        self.draft = true;
+        self.cur = self.history.len() as i32;
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() - self.draft as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&String> {
+        self.history.get(index).map(|e| &e.command)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &String> {
+        self.history.iter().take(self.len()).map(|e| &e.command)
+    }
+
+    /// Loads a readline/bash history file, decrypting it first if a cipher
+    /// is given and the file isn't missing or empty. Plain files hold one command per line; in files with
+    /// `#<seconds>` timestamp lines, everything up to the next timestamp is
+    /// one (possibly multi-line) entry, as bash writes with `lithist`.
+    /// Lines that aren't UTF-8 or hold NUL bytes, as a crash can leave, are
+    /// skipped; returns how many were.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P, cipher: Option<&dyn HistoryCipher>) -> io::Result<usize> {
+        let mut data = read(path.as_ref())?;
+        // a missing file reads as empty, which no cipher produces
+        if let Some(cipher) = cipher.filter(|_| !data.is_empty()) {
+            data = cipher.decrypt(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        let (lines, skipped) = decode(&data);
+        self.push_bash(lines);
+        Ok(skipped)
+    }
+
+    /// Appends the entries of a shell's history file, in the format its
+    /// name or contents tell, see `HistoryFormat::detect`. Returns how many
+    /// corrupt lines were skipped.
+    pub fn import<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        let data = read(path.as_ref())?;
+        Ok(self.append(&data, HistoryFormat::detect(path.as_ref(), &data)))
+    }
+
+    /// Like `import`, for a file known to be in `format`.
+    pub fn import_as<P: AsRef<Path>>(&mut self, path: P, format: HistoryFormat) -> io::Result<usize> {
+        let data = read(path.as_ref())?;
+        Ok(self.append(&data, format))
+    }
+
+    // appends the entries of `data`, returning how many lines were skipped
+    fn append(&mut self, data: &[u8], format: HistoryFormat) -> usize {
+        let (lines, skipped) = match format {
+            HistoryFormat::Zsh => decode(&unmetafy(data)),
+            _ => decode(data),
+        };
+        match format {
+            HistoryFormat::Bash => self.push_bash(lines),
+            HistoryFormat::Zsh => self.push_zsh(lines),
+            HistoryFormat::Fish => self.push_fish(lines),
+        }
+        skipped
+    }
+
+    // a timestamp line starts an entry running up to the next one, while
+    // lines before the first are commands of their own
+    fn push_bash(&mut self, lines: Vec<String>) {
+        let mut entry: Option<(u64, Vec<String>)> = None;
+        for line in lines {
+            match (timestamp(&line), entry.as_mut()) {
+                (Some(time), _) => {
+                    if let Some((time, lines)) = entry.replace((time, Vec::new())) {
+                        self.push(lines.join("\n"), Some(time), None);
+                    }
+                }
+                (None, Some((_, lines))) => lines.push(line),
+                (None, None) => self.push(line, None, None),
+            }
+        }
+        if let Some((time, lines)) = entry {
+            self.push(lines.join("\n"), Some(time), None);
+        }
+    }
+
+    // `: <start>:<duration>;<command>` lines, or bare commands, continued
+    // on the next line after a trailing backslash
+    fn push_zsh(&mut self, lines: Vec<String>) {
+        let mut entry: Option<(Option<u64>, Option<Duration>, String)> = None;
+        for line in lines {
+            let (time, duration, command) = match entry.take() {
+                Some((time, duration, mut command)) => {
+                    command.push('\n');
+                    command.push_str(&line);
+                    (time, duration, command)
+                }
+                None => match zsh_entry(&line) {
+                    Some((time, duration, command)) => (Some(time), Some(duration), command.to_owned()),
+                    None => (None, None, line),
+                },
+            };
+            match command.strip_suffix('\\') {
+                Some(command) => entry = Some((time, duration, command.to_owned())),
+                None => self.push(command, time, duration),
+            }
+        }
+        if let Some((time, duration, command)) = entry {
+            self.push(command, time, duration);
+        }
+    }
+
+    // `- cmd: <command>` items, with `when: <seconds>` among the fields
+    // indented under them
+    fn push_fish(&mut self, lines: Vec<String>) {
+        let mut entry: Option<(Option<u64>, String)> = None;
+        for line in lines {
+            if let Some(command) = line.strip_prefix("- cmd: ") {
+                if let Some((time, command)) = entry.take() {
+                    self.push(command, time, None);
+                }
+                entry = Some((None, fish_unescape(command)));
+            } else if let Some(time) = line.trim_start().strip_prefix("when: ") {
+                if let Some((ref mut when, _)) = entry {
+                    *when = time.trim().parse().ok();
+                }
+            }
+        }
+        if let Some((time, command)) = entry {
+            self.push(command, time, None);
+        }
+    }
+
+    /// Writes the history in bash's timestamped format, to a temporary file
+    /// then renamed over `path`, so that a crash leaves the old history or
+    /// the new one. Entries of unknown time get that of the entry before
+    /// them, or of the first known; how long commands ran isn't written.
+    pub fn save<P: AsRef<Path>>(&self, path: P, cipher: Option<&dyn HistoryCipher>) -> io::Result<()> {
+        let mut data = Vec::new();
+        let entries = self.history.iter().take(self.len());
+        let mut time = entries.clone().find_map(|entry| entry.timestamp)
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+        for entry in entries {
+            time = entry.timestamp.unwrap_or(time);
+            writeln!(data, "#{}", time)?;
+            writeln!(data, "{}", entry.command)?;
+        }
+        if let Some(cipher) = cipher {
+            data = cipher.encrypt(&data);
+        }
+        let path = path.as_ref();
+        let mut name = path.file_name().map(|name| name.to_owned()).unwrap_or_default();
+        name.push(".tmp");
+        let temporary = path.with_file_name(name);
+        let written = File::create(&temporary).and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()
+        });
+        match written.and_then(|_| fs::rename(&temporary, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+
+    /// The `count` commands run most often, by their first word, with how
+    /// long they took; the most recent first among those run as often.
+    pub fn top_commands(&self, count: usize) -> Vec<CommandUsage> {
+        let mut usage: BTreeMap<&str, (usize, CommandUsage)> = BTreeMap::new();
+        for (i, entry) in self.history.iter().take(self.len()).enumerate() {
+            let name = match entry.command.split_whitespace().next() {
+                Some(name) => name,
+                None => continue,
+            };
+            let (last, usage) = usage.entry(name)
+                .or_insert_with(|| (0, CommandUsage { name: name.to_owned(), ..Default::default() }));
+            *last = i;
+            usage.count += 1;
+            if let Some(duration) = entry.duration {
+                usage.timed += 1;
+                usage.total += duration;
+            }
+        }
+        let mut usage: Vec<(usize, CommandUsage)> = usage.into_values().collect();
+        usage.sort_by(|(a_last, a), (b_last, b)| b.count.cmp(&a.count).then(b_last.cmp(a_last)));
+        usage.into_iter().take(count).map(|(_, usage)| usage).collect()
+    }
+
     pub fn at_top(&self) -> bool {
         self.history.len() as i32 == self.cur
     }
-}
\ No newline at end of file
+
+    /// The index of the entry being browsed, `len()` or more when none is.
+    pub fn position(&self) -> usize {
+        self.cur as usize
+    }
+
+    /// Moves to the nearest entry before the one browsed, or after it unless
+    /// `backward`, that `matches` accepts, staying put if none does.
+    pub fn search<P>(&mut self, backward: bool, matches: P) -> Option<&String>
+        where P: Fn(&str) -> bool {
+        let cur = self.cur.max(0) as usize;
+        let found = if backward {
+            (0..cur.min(self.len())).rev().find(|&i| matches(&self.history[i].command))
+        } else {
+            (cur + 1..self.len()).find(|&i| matches(&self.history[i].command))
+        };
+        let index = found?;
+        self.cur = index as i32;
+        self.get(index)
+    }
+
+    /// The line being edited when browsing started, which browsing moves
+    /// back to.
+    pub fn back_to_draft(&mut self) -> Option<&String> {
+        if !self.draft {
+            return None;
+        }
+        self.cur = self.history.len() as i32 - 1;
+        self.history.last().map(|e| &e.command)
+    }
+
+    /// Continues browsing from entry `index`.
+    pub fn browse_from(&mut self, index: usize) {
+        self.cur = index.min(self.history.len()) as i32;
+    }
+}
+
+/// The shell a history file to import comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// readline's and bash's: a command per line, or with `#<seconds>`
+    /// lines, everything up to the next one, as `load` reads.
+    Bash,
+    /// zsh's, with or without `EXTENDED_HISTORY`: `: <start>:<seconds>;`
+    /// before commands, a trailing backslash continuing one on the next
+    /// line, and non-ASCII bytes escaped as 0x83 and the byte xor 32.
+    Zsh,
+    /// fish's: `- cmd: <command>` items with a `when: <seconds>` field.
+    Fish,
+}
+
+impl HistoryFormat {
+    /// The format of `data`, read from `path`: fish's if the file is named
+    /// after fish or has a `- cmd:` line, zsh's if it is named after zsh or
+    /// `.histfile`, has an `EXTENDED_HISTORY` line, or is UTF-8 only once
+    /// unescaped, and bash's otherwise. A plain zsh history under another
+    /// name looks like bash's: use `import_as` for it.
+    pub fn detect(path: &Path, data: &[u8]) -> Self {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut lines = data.split(|&b| b == b'\n');
+        if name.contains("fish") || lines.clone().any(|line| line.starts_with(b"- cmd: ")) {
+            return HistoryFormat::Fish;
+        }
+        let metafied = str::from_utf8(data).is_err() && str::from_utf8(&unmetafy(data)).is_ok();
+        if name.contains("zsh") || name == ".histfile" || metafied
+            || lines.any(|line| zsh_entry(&String::from_utf8_lossy(line)).is_some()) {
+            return HistoryFormat::Zsh;
+        }
+        HistoryFormat::Bash
+    }
+}
+
+// the contents of the file at `path`, nothing if it doesn't exist
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => { file.read_to_end(&mut data)?; }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(data)
+}
+
+// the lines of `data`, without those that aren't UTF-8 or hold NUL bytes,
+// as a crash can leave, and how many those were
+fn decode(data: &[u8]) -> (Vec<String>, usize) {
+    let mut skipped = 0;
+    let mut lines = Vec::new();
+    for line in data.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match String::from_utf8(line.to_vec()) {
+            Ok(line) if !line.contains('\0') => lines.push(line),
+            _ => skipped += 1,
+        }
+    }
+    // the newline ending the file doesn't start a line
+    if data.is_empty() || data.ends_with(b"\n") {
+        lines.pop();
+    }
+    (lines, skipped)
+}
+
+// zsh's history with the bytes it escapes, each 0x83 and the byte xor 32,
+// restored
+fn unmetafy(data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            0x83 => ret.extend(bytes.next().map(|b| b ^ 32)),
+            b => ret.push(b),
+        }
+    }
+    ret
+}
+
+// the start time, duration and command of a zsh
+// `: <start>:<seconds>;<command>` line
+fn zsh_entry(line: &str) -> Option<(u64, Duration, &str)> {
+    let (times, command) = line.strip_prefix(": ")?.split_once(';')?;
+    let (start, duration) = times.split_once(':')?;
+    Some((start.parse().ok()?, Duration::from_secs(duration.parse().ok()?), command))
+}
+
+// a fish command, with `\n` and `\\` standing for newlines and backslashes
+fn fish_unescape(command: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                ret.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                ret.push('\\');
+                chars.next();
+            }
+            (c, _) => ret.push(c),
+        }
+    }
+    ret
+}
+
+// the time of a `#<seconds>` line
+fn timestamp(line: &str) -> Option<u64> {
+    line.strip_prefix('#')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::{CommandHistory, HistoryFormat};
+
+    // the commands, times and durations appended from `data`
+    fn import(data: &[u8], format: HistoryFormat) -> Vec<(String, Option<u64>, Option<Duration>)> {
+        let mut history = CommandHistory::default();
+        assert_eq!(history.append(data, format), 0);
+        history.history.into_iter().map(|e| (e.command, e.timestamp, e.duration)).collect()
+    }
+
+    fn detect(name: &str, data: &[u8]) -> HistoryFormat {
+        HistoryFormat::detect(Path::new(name), data)
+    }
+
+    #[test]
+    fn bash() {
+        assert_eq!(import(b"ls\npwd\n", HistoryFormat::Bash), [
+            ("ls".to_owned(), None, None),
+            ("pwd".to_owned(), None, None),
+        ]);
+        assert_eq!(import(b"#100\nfor i in 1 2\ndo echo $i; done\n#200\nexit\n", HistoryFormat::Bash), [
+            ("for i in 1 2\ndo echo $i; done".to_owned(), Some(100), None),
+            ("exit".to_owned(), Some(200), None),
+        ]);
+        assert_eq!(detect(".bash_history", b"#100\nls\n"), HistoryFormat::Bash);
+        assert_eq!(detect("history", b"ls\ncd /tmp\n"), HistoryFormat::Bash);
+    }
+
+    #[test]
+    fn zsh_extended() {
+        let data = b": 1700000000:3;make\n: 1700000010:0;echo a\\\nb\n";
+        assert_eq!(import(data, HistoryFormat::Zsh), [
+            ("make".to_owned(), Some(1700000000), Some(Duration::from_secs(3))),
+            ("echo a\nb".to_owned(), Some(1700000010), Some(Duration::from_secs(0))),
+        ]);
+        // recognized whatever the file is called, even after plain lines
+        assert_eq!(detect("history", b"ls\n: 1700000000:3;make\n"), HistoryFormat::Zsh);
+    }
+
+    #[test]
+    fn zsh_plain() {
+        let data = b"git status\nprint -l a \\\nb\n";
+        assert_eq!(import(data, HistoryFormat::Zsh), [
+            ("git status".to_owned(), None, None),
+            ("print -l a \nb".to_owned(), None, None),
+        ]);
+        assert_eq!(detect(".zsh_history", data), HistoryFormat::Zsh);
+        assert_eq!(detect(".histfile", data), HistoryFormat::Zsh);
+        assert_eq!(detect("history", data), HistoryFormat::Bash);
+    }
+
+    #[test]
+    fn zsh_metafied() {
+        // "café", its last byte 0xa9 escaped as 0x83 0x89
+        let data = b"echo caf\xc3\x83\x89\n";
+        assert_eq!(import(data, HistoryFormat::Zsh), [("echo caf\u{e9}".to_owned(), None, None)]);
+        assert_eq!(detect("history", data), HistoryFormat::Zsh);
+    }
+
+    #[test]
+    fn fish() {
+        let data = b"- cmd: ls\n  when: 1700000000\n- cmd: echo a\\nb \\\\\n  when: 1700000005\n  paths:\n    - b\n";
+        assert_eq!(import(data, HistoryFormat::Fish), [
+            ("ls".to_owned(), Some(1700000000), None),
+            ("echo a\nb \\".to_owned(), Some(1700000005), None),
+        ]);
+        assert_eq!(detect("history", data), HistoryFormat::Fish);
+        assert_eq!(detect("fish_history", b""), HistoryFormat::Fish);
+    }
+}
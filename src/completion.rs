@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::mem;
+use std::str::Chars;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::normalize;
+use super::parse;
+
+/// How candidates are matched against the word being completed.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Matcher {
+    /// The candidate starts with the word.
+    #[default]
+    Prefix,
+    /// The candidate starts with the word, ignoring case.
+    CaseInsensitive,
+    /// The candidate contains the word anywhere, ignoring case.
+    Substring,
+}
+
+impl Matcher {
+    pub fn matches(&self, candidate: &str, word: &str) -> bool {
+        match *self {
+            Matcher::Prefix => candidate.starts_with(word),
+            Matcher::CaseInsensitive => candidate.to_lowercase().starts_with(&word.to_lowercase()),
+            Matcher::Substring => candidate.to_lowercase().contains(&word.to_lowercase()),
+        }
+    }
+}
+
+/// What Tab does when several candidates match.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Policy {
+    /// List the candidates right away.
+    #[default]
+    List,
+    /// Like bash: insert the candidates' common prefix and ring the bell,
+    /// list them only on a second consecutive Tab.
+    TwoStage,
+}
+
+/// Longest common prefix of the candidates.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let first = match candidates.first() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first.char_indices()
+            .zip(candidate.chars())
+            .take_while(|&((i, a), b)| i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+    first[..len].to_owned()
+}
+
+/// Supplies completion candidates on Tab.
+///
+/// `line` is the text before the cursor and `word` its last word, the one
+/// being completed. Candidates need not be filtered: the terminal keeps the
+/// ones the `Matcher` accepts. Closures `Fn(&str, &str) -> Vec<String>`
+/// implement this trait.
+pub trait Completer {
+    fn complete(&self, line: &str, word: &str) -> Vec<String>;
+
+    /// Overrides the terminal's matcher for this completer's candidates.
+    fn matcher(&self) -> Option<Matcher> {
+        None
+    }
+}
+
+impl<F> Completer for F
+    where F: Fn(&str, &str) -> Vec<String> {
+    fn complete(&self, line: &str, word: &str) -> Vec<String> {
+        self(line, word)
+    }
+}
+
+/// Wraps a completer to match its candidates with `matcher`.
+pub struct WithMatcher<C> {
+    completer: C,
+    matcher: Matcher,
+}
+
+pub fn with_matcher<C: Completer>(matcher: Matcher, completer: C) -> WithMatcher<C> {
+    WithMatcher { completer, matcher }
+}
+
+impl<C: Completer> Completer for WithMatcher<C> {
+    fn complete(&self, line: &str, word: &str) -> Vec<String> {
+        self.completer.complete(line, word)
+    }
+
+    fn matcher(&self) -> Option<Matcher> {
+        Some(self.matcher)
+    }
+}
+
+/// Arguments previously given to the command on `line` at the position being
+/// completed, most recent first, taken from `history`.
+pub fn history_arguments<'a, I>(history: I, line: &str, word: &str) -> Vec<String>
+    where I: DoubleEndedIterator<Item = &'a String> {
+    let context = match parse::split(&line[..line.len() - word.len()]) {
+        Ok(context) => context,
+        Err(_) => return Vec::new(),
+    };
+    if context.is_empty() {
+        return Vec::new();
+    }
+    let mut ret: Vec<String> = Vec::new();
+    for entry in history.rev() {
+        let argv = match parse::split(entry) {
+            Ok(argv) => argv,
+            Err(_) => continue,
+        };
+        if argv.first() == context.first() {
+            if let Some(arg) = argv.get(context.len()) {
+                if !ret.contains(arg) {
+                    ret.push(arg.clone());
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Collects the matching candidates of all completers, sorted and deduplicated.
+pub fn candidates(completers: &[Box<dyn Completer>], matcher: Matcher, line: &str, word: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    for completer in completers {
+        let matcher = completer.matcher().unwrap_or(matcher);
+        // as the line is, e.g. file names macOS gives decomposed
+        ret.extend(completer.complete(line, word).into_iter()
+            .map(normalize::into_nfc)
+            .filter(|c| matcher.matches(c, word)));
+    }
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+struct CacheEntry {
+    candidates: Vec<String>,
+    fetched: Option<Instant>,
+    refreshing: bool,
+}
+
+/// Caches a slow completer's candidates per context (the line before the
+/// word being completed) for `ttl`. Expired or missing entries are fetched
+/// on a background thread; Tab waits at most `timeout` for it and otherwise
+/// falls back to the stale candidates, picking up the fresh ones on the
+/// next Tab. The wrapped completer should therefore not filter by `word`.
+pub struct Cached<C> {
+    completer: Arc<C>,
+    cache: Arc<(Mutex<HashMap<String, CacheEntry>>, Condvar)>,
+    ttl: Duration,
+    timeout: Duration,
+}
+
+impl<C> Cached<C>
+    where C: Completer + Send + Sync + 'static {
+    pub fn new(completer: C, ttl: Duration, timeout: Duration) -> Self {
+        Cached {
+            completer: Arc::new(completer),
+            cache: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            ttl,
+            timeout,
+        }
+    }
+
+    fn refresh(&self, context: String, line: &str, word: &str) {
+        let completer = self.completer.clone();
+        let refresh = Refresh { cache: self.cache.clone(), context, candidates: None };
+        let (line, word) = (line.to_owned(), word.to_owned());
+        thread::spawn(move || {
+            let mut refresh = refresh;
+            refresh.candidates = Some(completer.complete(&line, &word));
+        });
+    }
+}
+
+// Stores the candidates a refresh fetched once dropped, or, if the
+// completer panicked before there were any, ends the refresh without them:
+// Tab stops waiting and the next one fetches again.
+struct Refresh {
+    cache: Arc<(Mutex<HashMap<String, CacheEntry>>, Condvar)>,
+    context: String,
+    candidates: Option<Vec<String>>,
+}
+
+impl Drop for Refresh {
+    fn drop(&mut self) {
+        let (ref lock, ref fetched) = *self.cache;
+        let mut entries = lock.lock().unwrap_or_else(|e| e.into_inner());
+        match self.candidates.take() {
+            Some(candidates) => {
+                let entry = CacheEntry { candidates, fetched: Some(Instant::now()), refreshing: false };
+                entries.insert(mem::take(&mut self.context), entry);
+            }
+            None => {
+                if let Some(entry) = entries.get_mut(&self.context) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+        fetched.notify_all();
+    }
+}
+
+impl<C> Completer for Cached<C>
+    where C: Completer + Send + Sync + 'static {
+    fn complete(&self, line: &str, word: &str) -> Vec<String> {
+        let context = line[..line.len() - word.len()].to_owned();
+        let (ref lock, ref fetched) = *self.cache;
+        let mut entries = lock.lock().unwrap();
+        let fresh = entries.get(&context)
+            .and_then(|e| e.fetched)
+            .is_some_and(|t| t.elapsed() < self.ttl);
+        if fresh {
+            return entries[&context].candidates.clone();
+        }
+        let refreshing = entries.get(&context).is_some_and(|e| e.refreshing);
+        if !refreshing {
+            let entry = entries.entry(context.clone())
+                .or_insert(CacheEntry { candidates: Vec::new(), fetched: None, refreshing: false });
+            entry.refreshing = true;
+            self.refresh(context.clone(), line, word);
+        }
+        let deadline = Instant::now() + self.timeout;
+        while entries.get(&context).is_some_and(|e| e.refreshing) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            entries = fetched.wait_timeout(entries, deadline - now).unwrap().0;
+        }
+        entries.get(&context).map(|e| e.candidates.clone()).unwrap_or_default()
+    }
+
+    fn matcher(&self) -> Option<Matcher> {
+        self.completer.matcher()
+    }
+}
+
+// a character of the words in an `Index`, and the words going on from it
+#[derive(Default)]
+struct TrieNode {
+    children: Vec<(char, TrieNode)>,
+    word: bool,
+}
+
+impl TrieNode {
+    fn child(&self, c: char) -> Option<&TrieNode> {
+        self.children.binary_search_by_key(&c, |&(c, _)| c).ok().map(|i| &self.children[i].1)
+    }
+
+    // adds the rest of a word, returning whether it is new
+    fn insert(&mut self, mut chars: Chars) -> bool {
+        match chars.next() {
+            None => !mem::replace(&mut self.word, true),
+            Some(c) => {
+                let i = match self.children.binary_search_by_key(&c, |&(c, _)| c) {
+                    Ok(i) => i,
+                    Err(i) => {
+                        self.children.insert(i, (c, TrieNode::default()));
+                        i
+                    }
+                };
+                self.children[i].1.insert(chars)
+            }
+        }
+    }
+
+    // removes the rest of a word, returning whether it was there, and
+    // prunes the nodes left leading nowhere
+    fn remove(&mut self, mut chars: Chars) -> bool {
+        match chars.next() {
+            None => mem::replace(&mut self.word, false),
+            Some(c) => {
+                let i = match self.children.binary_search_by_key(&c, |&(c, _)| c) {
+                    Ok(i) => i,
+                    Err(_) => return false,
+                };
+                let removed = self.children[i].1.remove(chars);
+                let child = &self.children[i].1;
+                if !child.word && child.children.is_empty() {
+                    self.children.remove(i);
+                }
+                removed
+            }
+        }
+    }
+
+    // pushes the words below, in order, each `prefix` and the rest
+    fn collect(&self, prefix: &mut String, words: &mut Vec<String>) {
+        if self.word {
+            words.push(prefix.clone());
+        }
+        for &(c, ref child) in &self.children {
+            prefix.push(c);
+            child.collect(prefix, words);
+            prefix.pop();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Trie {
+    root: TrieNode,
+    len: usize,
+}
+
+/// A set of candidates looked up by prefix in a trie, for completers with
+/// too many of them to filter on every Tab, such as the symbols of a large
+/// program: lookups take time in the length of the word and of the
+/// candidates found, not of the set.
+///
+/// Clones share the same candidates, so a completer can be registered with
+/// one while another is kept to insert and remove candidates as they change,
+/// from any thread. The index completes every word with its candidates by
+/// prefix, whatever the terminal's matcher.
+///
+/// ```
+/// use term_rs::completion::Index;
+///
+/// let symbols: Index = vec!["parse", "parse_args", "print"].into_iter().collect();
+/// symbols.insert("parser");
+/// assert_eq!(symbols.with_prefix("parse"), ["parse", "parse_args", "parser"]);
+/// ```
+#[derive(Clone, Default)]
+pub struct Index {
+    trie: Arc<RwLock<Trie>>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds `candidate`, returning whether it wasn't there yet.
+    pub fn insert(&self, candidate: &str) -> bool {
+        let mut trie = self.trie.write().unwrap();
+        let added = trie.root.insert(candidate.chars());
+        trie.len += added as usize;
+        added
+    }
+
+    /// Removes `candidate`, returning whether it was there.
+    pub fn remove(&self, candidate: &str) -> bool {
+        let mut trie = self.trie.write().unwrap();
+        let removed = trie.root.remove(candidate.chars());
+        trie.len -= removed as usize;
+        removed
+    }
+
+    pub fn contains(&self, candidate: &str) -> bool {
+        let trie = self.trie.read().unwrap();
+        let mut node = &trie.root;
+        for c in candidate.chars() {
+            match node.child(c) {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        node.word
+    }
+
+    pub fn len(&self) -> usize {
+        self.trie.read().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        *self.trie.write().unwrap() = Trie::default();
+    }
+
+    /// The candidates starting with `prefix`, sorted.
+    pub fn with_prefix(&self, prefix: &str) -> Vec<String> {
+        let trie = self.trie.read().unwrap();
+        let mut node = &trie.root;
+        for c in prefix.chars() {
+            match node.child(c) {
+                Some(child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+        let mut words = Vec::new();
+        node.collect(&mut prefix.to_owned(), &mut words);
+        words
+    }
+}
+
+impl<S: AsRef<str>> Extend<S> for Index {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, candidates: I) {
+        let mut trie = self.trie.write().unwrap();
+        for candidate in candidates {
+            let added = trie.root.insert(candidate.as_ref().chars());
+            trie.len += added as usize;
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for Index {
+    fn from_iter<I: IntoIterator<Item = S>>(candidates: I) -> Self {
+        let mut index = Index::new();
+        index.extend(candidates);
+        index
+    }
+}
+
+impl Completer for Index {
+    fn complete(&self, _line: &str, word: &str) -> Vec<String> {
+        self.with_prefix(word)
+    }
+
+    fn matcher(&self) -> Option<Matcher> {
+        Some(Matcher::Prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use super::{Cached, Completer, Index};
+
+    #[test]
+    fn index_insert_and_remove() {
+        let index = Index::new();
+        assert!(index.is_empty());
+        assert!(index.insert("parse"));
+        assert!(index.insert("parser"));
+        assert!(!index.insert("parse"));
+        assert_eq!(index.len(), 2);
+        assert!(index.contains("parse"));
+        assert!(!index.contains("pars"));
+
+        assert!(index.remove("parse"));
+        assert!(!index.remove("parse"));
+        assert!(!index.remove("pa"));
+        assert_eq!(index.len(), 1);
+        assert!(!index.contains("parse"));
+        assert!(index.contains("parser"));
+        assert!(index.remove("parser"));
+        assert!(index.is_empty());
+        assert_eq!(index.with_prefix(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn index_prefix() {
+        let index: Index = vec!["print", "parse", "parser", "päd", "parse_args"].into_iter().collect();
+        assert_eq!(index.with_prefix("pa"), ["parse", "parse_args", "parser"]);
+        assert_eq!(index.with_prefix("parse"), ["parse", "parse_args", "parser"]);
+        assert_eq!(index.with_prefix("pä"), ["päd"]);
+        assert_eq!(index.with_prefix("x"), Vec::<String>::new());
+        assert_eq!(index.with_prefix("").len(), 5);
+        // clones share the candidates
+        let clone = index.clone();
+        clone.remove("parse");
+        assert_eq!(index.complete("run pa", "pa"), ["parse_args", "parser"]);
+        clone.clear();
+        assert!(index.is_empty());
+    }
+
+    // panics on the first call only
+    struct Flaky(AtomicUsize);
+
+    impl Completer for Flaky {
+        fn complete(&self, _line: &str, _word: &str) -> Vec<String> {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("completer failed");
+            }
+            vec!["ok".to_owned()]
+        }
+    }
+
+    #[test]
+    fn cached_survives_a_panicking_completer() {
+        let cached = Cached::new(Flaky(AtomicUsize::new(0)), Duration::from_secs(60), Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(cached.complete("cmd ", "").is_empty());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(cached.complete("cmd ", ""), ["ok"]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::builder::{Bell, Style, TerminalBuilder, Timestamps, Verbosity};
+use super::completion::{Matcher, Policy};
+
+// a value of the TOML subset configuration files are written in
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+// a `key = value` line, with the table it is in
+struct Setting {
+    line: usize,
+    table: String,
+    key: String,
+    value: Value,
+}
+
+// Reads the configuration file at `path` into `builder`, which keeps the
+// settings it was given instead of the file's: those no longer at their
+// defaults, and the key bindings, which take precedence. Errors are
+// reported at startup; a missing file is not one.
+pub fn load(mut builder: TerminalBuilder, path: &Path) -> TerminalBuilder {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return builder,
+        Err(e) => {
+            builder.config_errors.push(format!("{}: {}", path.display(), e));
+            return builder;
+        }
+    };
+    let settings = match parse(&text) {
+        Ok(settings) => settings,
+        Err(e) => {
+            builder.config_errors.push(format!("{}:{}", path.display(), e));
+            return builder;
+        }
+    };
+    let defaults = TerminalBuilder::default();
+    let mut bindings = Vec::new();
+    let mut imports = Vec::new();
+    for setting in settings {
+        let name = match setting.table.as_str() {
+            "" => setting.key.clone(),
+            table => format!("{}.{}", table, setting.key),
+        };
+        let applied = match (setting.table.as_str(), setting.key.as_str()) {
+            ("keys", keys) => string(&setting.value).map(|action| bindings.push((keys.to_owned(), action.to_owned()))),
+            ("", "prompt") => string(&setting.value).map(|prompt| if builder.prompt == defaults.prompt {
+                builder.prompt = prompt.to_owned();
+            }),
+            ("", "transient_prompt") => string(&setting.value).map(|marker| if builder.transient_prompt.is_none() {
+                builder.transient_prompt = Some(marker.to_owned());
+            }),
+            ("", "verbosity") => {
+                choice(&setting.value, &[("quiet", Verbosity::Quiet), ("normal", Verbosity::Normal),
+                                         ("verbose", Verbosity::Verbose)])
+                    .map(|verbosity| if builder.verbosity == defaults.verbosity {
+                        builder.verbosity = verbosity;
+                    })
+            }
+            ("theme", "banner_style") => {
+                choice(&setting.value, &[("plain", Style::Plain), ("bold", Style::Bold), ("dim", Style::Dim),
+                                         ("underline", Style::Underline), ("reverse", Style::Reverse),
+                                         ("error", Style::Error), ("added", Style::Added),
+                                         ("removed", Style::Removed)])
+                    .map(|style| if builder.banner_style == defaults.banner_style {
+                        builder.banner_style = style;
+                    })
+            }
+            ("theme", "failure_prompt") => boolean(&setting.value).map(|on| if !builder.failure_prompt_style {
+                builder.failure_prompt_style = on;
+            }),
+            ("theme", "output_prefix") => string(&setting.value).map(|prefix| if builder.output_prefix.is_empty() {
+                builder.output_prefix = prefix.to_owned();
+            }),
+            ("theme", "error_prefix") => string(&setting.value).map(|prefix| if builder.error_prefix.is_empty() {
+                builder.error_prefix = prefix.to_owned();
+            }),
+            ("theme", "bell") => {
+                choice(&setting.value, &[("audible", Bell::Audible), ("visible", Bell::Visible), ("off", Bell::Off)])
+                    .map(|bell| if builder.bell == defaults.bell {
+                        builder.bell = bell;
+                    })
+            }
+            ("theme", "timestamps") => {
+                choice(&setting.value, &[("absolute", Some(Timestamps::Absolute)),
+                                         ("relative", Some(Timestamps::Relative)), ("off", None)])
+                    .map(|timestamps| if builder.timestamps.is_none() {
+                        builder.timestamps = timestamps;
+                    })
+            }
+            ("pager", "enabled") => boolean(&setting.value).map(|on| if !builder.paginate {
+                builder.paginate = on;
+            }),
+            ("pager", "lines") => match setting.value {
+                Value::Integer(lines) if lines > 0 => {
+                    if builder.page_lines.is_none() {
+                        builder.page_lines = Some(lines as usize);
+                    }
+                    Ok(())
+                }
+                _ => Err("expected a positive integer".to_owned()),
+            },
+            ("history", "file") => string(&setting.value).map(|file| if builder.history_file.is_none() {
+                builder.history_file = Some(file.into());
+            }),
+            ("history", "import") => string(&setting.value).map(|file| imports.push((file.into(), None))),
+            ("completion", "matcher") => {
+                choice(&setting.value, &[("prefix", Matcher::Prefix), ("case-insensitive", Matcher::CaseInsensitive),
+                                         ("substring", Matcher::Substring)])
+                    .map(|matcher| if builder.matcher == defaults.matcher {
+                        builder.matcher = matcher;
+                    })
+            }
+            ("completion", "policy") => {
+                choice(&setting.value, &[("list", Policy::List), ("two-stage", Policy::TwoStage)])
+                    .map(|policy| if builder.completion_policy == defaults.completion_policy {
+                        builder.completion_policy = policy;
+                    })
+            }
+            ("completion", "from_history") => boolean(&setting.value).map(|on| if !builder.complete_from_history {
+                builder.complete_from_history = on;
+            }),
+            ("editing", "auto_pairs") => boolean(&setting.value).map(|on| if !builder.auto_pairs {
+                builder.auto_pairs = on;
+            }),
+            ("editing", "match_brackets") => boolean(&setting.value).map(|on| if !builder.match_brackets {
+                builder.match_brackets = on;
+            }),
+            ("editing", "multiline") => boolean(&setting.value).map(|on| if !builder.multiline {
+                builder.multiline = on;
+            }),
+            ("editing", "tab_width") => match setting.value {
+                Value::Integer(width) if width > 0 => {
+                    if builder.tab_width == defaults.tab_width {
+                        builder.tab_width = width as usize;
+                    }
+                    Ok(())
+                }
+                _ => Err("expected a positive integer".to_owned()),
+            },
+            ("editing", "confirm_paste") => boolean(&setting.value).map(|on| {
+                if builder.confirm_paste == defaults.confirm_paste {
+                    builder.confirm_paste = on;
+                }
+            }),
+            _ => Err("unknown setting".to_owned()),
+        };
+        if let Err(e) = applied {
+            builder.config_errors.push(format!("{}:{}: {}: {}", path.display(), setting.line, name, e));
+        }
+    }
+    // bound before the builder's own, which replace them
+    bindings.append(&mut builder.config_bindings);
+    builder.config_bindings = bindings;
+    imports.append(&mut builder.history_imports);
+    builder.history_imports = imports;
+    builder
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    match *value {
+        Value::String(ref s) => Ok(s),
+        _ => Err("expected a string".to_owned()),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match *value {
+        Value::Boolean(b) => Ok(b),
+        _ => Err("expected true or false".to_owned()),
+    }
+}
+
+// the option a string value names
+fn choice<T: Copy>(value: &Value, options: &[(&str, T)]) -> Result<T, String> {
+    let name = string(value)?;
+    options.iter().find(|&&(n, _)| n == name).map(|&(_, option)| option).ok_or_else(|| {
+        format!("expected one of {}", options.iter().map(|&(n, _)| n).collect::<Vec<_>>().join(", "))
+    })
+}
+
+// Tables, bare or quoted keys, and single-line strings, integers and
+// booleans: as much TOML as settings need. Errors start with the line.
+fn parse(text: &str) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+    let mut table = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |e: &str| format!("{}: {}", line_number, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let close = header.find(']').ok_or_else(|| error("missing ]"))?;
+            end_of_line(&header[close + 1..]).map_err(|e| error(&e))?;
+            table = header[..close].trim().to_owned();
+            continue;
+        }
+        let (key, rest) = match line.chars().next() {
+            Some('"') => quoted(line).map_err(|e| error(&e))?,
+            _ => {
+                let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(line.len());
+                if end == 0 {
+                    return Err(error("expected a key"));
+                }
+                (line[..end].to_owned(), &line[end..])
+            }
+        };
+        let rest = rest.trim_start().strip_prefix('=').ok_or_else(|| error("expected ="))?.trim_start();
+        let (value, rest) = value(rest).map_err(|e| error(&e))?;
+        end_of_line(rest).map_err(|e| error(&e))?;
+        settings.push(Setting { line: line_number, table: table.clone(), key, value });
+    }
+    Ok(settings)
+}
+
+// the value at the start of `text`, and what follows it
+fn value(text: &str) -> Result<(Value, &str), String> {
+    match text.chars().next() {
+        Some('"') | Some('\'') => quoted(text).map(|(s, rest)| (Value::String(s), rest)),
+        _ => {
+            let end = text.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(text.len());
+            let value = match &text[..end] {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                word => word.replace('_', "").parse().map(Value::Integer)
+                    .map_err(|_| format!("unsupported value {}", word))?,
+            };
+            Ok((value, &text[end..]))
+        }
+    }
+}
+
+// the basic ("...") or literal ('...') string at the start of `text`, and
+// what follows it
+fn quoted(text: &str) -> Result<(String, &str), String> {
+    let quote = text.chars().next().unwrap();
+    let mut ret = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((ret, &text[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some('"') => ret.push('"'),
+                Some('\\') => ret.push('\\'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", hex))?;
+                    ret.push(c);
+                }
+                Some(other) => return Err(format!("invalid escape \\{}", other)),
+                None => break,
+            },
+            c => ret.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+// only a comment may follow a value or table header
+fn end_of_line(rest: &str) -> Result<(), String> {
+    match rest.trim_start() {
+        rest if rest.is_empty() || rest.starts_with('#') => Ok(()),
+        rest => Err(format!("unexpected {}", rest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    // the settings of `text` as (line, table, key, value)
+    fn settings(text: &str) -> Vec<(usize, String, String, Value)> {
+        parse(text).unwrap().into_iter().map(|s| (s.line, s.table, s.key, s.value)).collect()
+    }
+
+    fn error(text: &str) -> String {
+        parse(text).err().unwrap()
+    }
+
+    #[test]
+    fn values() {
+        let text = "# comment\n\nprompt = \"> \"\n[history]\nsize = 10_000 # entries\nshared = true\n";
+        assert_eq!(settings(text), vec![
+            (3, String::new(), "prompt".to_owned(), Value::String("> ".to_owned())),
+            (5, "history".to_owned(), "size".to_owned(), Value::Integer(10000)),
+            (6, "history".to_owned(), "shared".to_owned(), Value::Boolean(true)),
+        ]);
+        assert_eq!(settings("[ keys ]\n\"C-x e\" = 'edit'")[0].1, "keys");
+        assert_eq!(settings("offset = -3")[0].3, Value::Integer(-3));
+    }
+
+    #[test]
+    fn quoting() {
+        let value = |text: &str| settings(&format!("key = {}", text)).remove(0).3;
+        assert_eq!(value(r#""tab\tquote\"backslash\\""#), Value::String("tab\tquote\"backslash\\".to_owned()));
+        assert_eq!(value(r#""line\nbreak""#), Value::String("line\nbreak".to_owned()));
+        assert_eq!(value(r#""caf\u00e9""#), Value::String("caf\u{e9}".to_owned()));
+        assert_eq!(value(r"'C:\path\n'"), Value::String(r"C:\path\n".to_owned()));
+        assert_eq!(value("\"# not a comment\" # a comment"), Value::String("# not a comment".to_owned()));
+        assert_eq!(settings("\"C-x e\" = 1")[0].2, "C-x e");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("[history"), "1: missing ]");
+        assert_eq!(error("[history] size = 1"), "1: unexpected size = 1");
+        assert_eq!(error("ok = 1\n= 2"), "2: expected a key");
+        assert_eq!(error("key 2"), "1: expected =");
+        assert_eq!(error("key = \"open"), "1: unterminated string");
+        assert_eq!(error("key = 'open"), "1: unterminated string");
+        assert_eq!(error("key = \"ends in \\"), "1: unterminated string");
+        assert_eq!(error(r#"key = "\q""#), "1: invalid escape \\q");
+        assert_eq!(error(r#"key = "\u12""#), "1: invalid escape \\u12\"");
+        assert_eq!(error("key = yes"), "1: unsupported value yes");
+        assert_eq!(error("key = 1 2"), "1: unexpected 2");
+        assert_eq!(error("key = \"a\" \"b\""), "1: unexpected \"b\"");
+        // literal strings have no escapes, not even for their quote
+        assert_eq!(error("key = 'it''s'"), "1: unexpected 's'");
+    }
+}
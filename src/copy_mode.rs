@@ -0,0 +1,140 @@
+use pancurses::{newwin, Input, Window, A_REVERSE};
+
+use super::messages::Messages;
+
+#[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
+struct Point {
+    line: usize,
+    column: usize,
+}
+
+struct CopyMode<'a> {
+    lines: &'a [String],
+    window: Window,
+    rows: usize,
+    top: usize,
+    cursor: Point,
+    anchor: Option<Point>,
+    messages: &'a dyn Messages,
+}
+
+/// Browses `lines` full screen, tmux style: arrows or h/j/k/l move, PageUp
+/// and PageDown (Ctrl+B, Ctrl+F) scroll, g and G go to the top and bottom,
+/// 0 and $ to the ends of the line, Space starts a selection and Enter
+/// returns it. q or Esc leave without one.
+pub fn run(lines: &[String], messages: &dyn Messages) -> Option<String> {
+    if lines.is_empty() {
+        return None;
+    }
+    let window = newwin(0, 0, 0, 0);
+    window.keypad(true);
+    let rows = (window.get_max_y() - 1).max(1) as usize;
+    let last = lines.len().saturating_sub(1);
+    let mut mode = CopyMode {
+        lines,
+        window,
+        rows,
+        top: lines.len().saturating_sub(rows),
+        cursor: Point { line: last, column: 0 },
+        anchor: None,
+        messages,
+    };
+    let ret = mode.event_loop();
+    mode.window.delwin();
+    ret
+}
+
+impl<'a> CopyMode<'a> {
+    fn event_loop(&mut self) -> Option<String> {
+        loop {
+            self.draw();
+            let input = self.window.getch()?;
+            let last = self.lines.len().saturating_sub(1);
+            match input {
+                Input::KeyUp | Input::Character('k') => self.move_to(self.cursor.line.saturating_sub(1)),
+                Input::KeyDown | Input::Character('j') => self.move_to((self.cursor.line + 1).min(last)),
+                Input::KeyLeft | Input::Character('h') => {
+                    self.cursor.column = self.cursor.column.saturating_sub(1);
+                }
+                Input::KeyRight | Input::Character('l')
+                    if self.cursor.column + 1 < self.line_len(self.cursor.line) => {
+                    self.cursor.column += 1;
+                }
+                Input::KeyPPage | Input::Character('\u{2}') => self.move_to(self.cursor.line.saturating_sub(self.rows)),
+                Input::KeyNPage | Input::Character('\u{6}') => self.move_to((self.cursor.line + self.rows).min(last)),
+                Input::Character('g') => self.move_to(0),
+                Input::Character('G') => self.move_to(last),
+                Input::Character('0') => { self.cursor.column = 0; }
+                Input::Character('$') => {
+                    self.cursor.column = self.line_len(self.cursor.line).saturating_sub(1);
+                }
+                Input::Character(' ') => { self.anchor = Some(self.cursor); }
+                Input::Character('\n') => return self.selection(),
+                Input::Character('q') | Input::Character('\u{1b}') => return None,
+                _ => {}
+            }
+        }
+    }
+
+    fn line_len(&self, line: usize) -> usize {
+        self.lines.get(line).map_or(0, |l| l.chars().count())
+    }
+
+    fn move_to(&mut self, line: usize) {
+        self.cursor.line = line;
+        self.cursor.column = self.cursor.column.min(self.line_len(line).saturating_sub(1));
+        if line < self.top {
+            self.top = line;
+        } else if line >= self.top + self.rows {
+            self.top = line + 1 - self.rows;
+        }
+    }
+
+    fn selected(&self, point: Point) -> bool {
+        match self.anchor {
+            Some(anchor) => {
+                let (start, end) = if anchor <= self.cursor { (anchor, self.cursor) } else { (self.cursor, anchor) };
+                start <= point && point <= end
+            }
+            None => false,
+        }
+    }
+
+    fn draw(&self) {
+        let columns = self.window.get_max_x() as usize;
+        self.window.erase();
+        for row in 0..self.rows {
+            let line = self.top + row;
+            let text = match self.lines.get(line) {
+                Some(text) => text,
+                None => break,
+            };
+            for (column, c) in text.chars().take(columns).enumerate() {
+                self.window.mvaddch(row as i32, column as i32, c);
+                if self.selected(Point { line, column }) {
+                    self.window.mvchgat(row as i32, column as i32, 1, A_REVERSE, 0);
+                }
+            }
+        }
+        let status = self.messages.copy_mode_status(self.cursor.line + 1, self.lines.len());
+        self.window.mvaddstr(self.rows as i32, 0, &status);
+        self.window.mvchgat(self.rows as i32, 0, status.len() as i32, A_REVERSE, 0);
+        let column = self.cursor.column.min(columns.saturating_sub(1));
+        self.window.mv((self.cursor.line - self.top) as i32, column as i32);
+        self.window.refresh();
+    }
+
+    // the text from the anchor to the cursor, both included
+    fn selection(&self) -> Option<String> {
+        let anchor = self.anchor?;
+        let (start, end) = if anchor <= self.cursor { (anchor, self.cursor) } else { (self.cursor, anchor) };
+        let mut ret = Vec::new();
+        for line in start.line..=end.line {
+            let chars: Vec<char> = self.lines[line].chars().collect();
+            let from = if line == start.line { start.column.min(chars.len()) } else { 0 };
+            let to = if line == end.line { (end.column + 1).min(chars.len()) } else { chars.len() };
+            ret.push(chars[from..to.max(from)].iter().collect::<String>());
+        }
+        Some(ret.join("\n"))
+    }
+}
@@ -0,0 +1,18 @@
+use super::builder::Style;
+use super::markdown::Line;
+
+// Styles a unified diff: file headers in bold, hunk headers dim, added and
+// removed lines in their own colors, and context as it is.
+pub fn render(text: &str) -> Vec<Line> {
+    text.lines().map(|line| {
+        let style = match line {
+            _ if line.starts_with("+++") || line.starts_with("---") => Style::Bold,
+            _ if line.starts_with("diff ") || line.starts_with("index ") => Style::Bold,
+            _ if line.starts_with("@@") => Style::Dim,
+            _ if line.starts_with('+') => Style::Added,
+            _ if line.starts_with('-') => Style::Removed,
+            _ => Style::Plain,
+        };
+        vec![(line.to_owned(), style)]
+    }).collect()
+}
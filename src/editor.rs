@@ -0,0 +1,202 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::builder::Style;
+
+/// The line being edited, as custom actions see and change it and
+/// highlighters see it, see `TerminalBuilder::action` and
+/// `TerminalBuilder::highlighter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditorState {
+    buffer: String,
+    // a byte index into `buffer`, on a character boundary
+    cursor: usize,
+    pub(crate) prompt: String,
+    pub(crate) block: Vec<String>,
+    pub(crate) candidates: Vec<String>,
+    pub(crate) history_entry: Option<usize>,
+    pub(crate) overwrite: bool,
+    accept: bool,
+    refresh_prompt: bool,
+}
+
+impl EditorState {
+    pub(crate) fn new(buffer: String, cursor: usize) -> Self {
+        EditorState { buffer, cursor, ..Default::default() }
+    }
+
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// The byte index of the cursor in `buffer`.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The prompt shown before the buffer, the continuation prompt on the
+    /// lines after the first of a multi-line command.
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// The lines of a multi-line command entered before the buffer.
+    pub fn block(&self) -> &[String] {
+        &self.block
+    }
+
+    /// The candidates the last Tab listed, empty once another key is
+    /// pressed.
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    /// The index of the history entry being browsed, `None` on a new line.
+    pub fn history_entry(&self) -> Option<usize> {
+        self.history_entry
+    }
+
+    /// Whether typing replaces the character under the cursor.
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    /// Replaces the buffer, putting the cursor at its end.
+    pub fn set_buffer(&mut self, buffer: &str) {
+        self.buffer = buffer.to_owned();
+        self.cursor = self.buffer.len();
+    }
+
+    /// Moves the cursor, clamped to the buffer and moved back to the
+    /// closest character boundary.
+    pub fn set_cursor(&mut self, cursor: usize) {
+        let mut cursor = cursor.min(self.buffer.len());
+        while !self.buffer.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+        self.cursor = cursor;
+    }
+
+    /// Inserts `text` at the cursor, leaving the cursor after it.
+    pub fn insert(&mut self, text: &str) {
+        self.buffer.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Runs the line once the action returns, as Enter would.
+    pub fn accept(&mut self) {
+        self.accept = true;
+    }
+
+    /// Computes the prompt again, e.g. after toggling a mode it shows.
+    pub fn refresh_prompt(&mut self) {
+        self.refresh_prompt = true;
+    }
+
+    pub(crate) fn accepted(&self) -> bool {
+        self.accept
+    }
+
+    pub(crate) fn prompt_refreshed(&self) -> bool {
+        self.refresh_prompt
+    }
+}
+
+/// Styles parts of the line as it is typed, see
+/// `TerminalBuilder::highlighter`.
+///
+/// ```
+/// use term_rs::{EditorState, Highlighter, Style};
+///
+/// // the command name in bold
+/// fn highlight(state: &EditorState) -> Vec<(std::ops::Range<usize>, Style)> {
+///     let end = state.buffer().find(' ').unwrap_or(state.buffer().len());
+///     vec![(0..end, Style::Bold)]
+/// }
+///
+/// let _: &dyn Highlighter = &highlight;
+/// ```
+pub trait Highlighter {
+    /// Byte ranges of the buffer and their styles; ranges past the end of
+    /// the buffer are cut.
+    fn highlight(&self, state: &EditorState) -> Vec<(Range<usize>, Style)>;
+}
+
+impl<F> Highlighter for F
+    where F: Fn(&EditorState) -> Vec<(Range<usize>, Style)> {
+    fn highlight(&self, state: &EditorState) -> Vec<(Range<usize>, Style)> {
+        self(state)
+    }
+}
+
+// the styles a highlighter gives the line
+pub(crate) type Highlights = Vec<(Range<usize>, Style)>;
+
+// runs a highlighter on a worker thread once the line has stayed the same
+// for a delay, so that a slow one doesn't hold up typing
+pub(crate) struct Debounced {
+    requests: Sender<(u64, EditorState)>,
+    results: Receiver<(u64, Highlights)>,
+    // the number of the latest request, which only its result answers
+    generation: u64,
+    pending: bool,
+    requested: String,
+    // the last buffer highlighted and its ranges, shown again meanwhile
+    last: Option<(String, Highlights)>,
+}
+
+impl Debounced {
+    pub(crate) fn new(highlighter: Arc<dyn Highlighter + Send + Sync>, delay: Duration) -> Self {
+        let (requests, worker_requests) = mpsc::channel::<(u64, EditorState)>();
+        let (worker_results, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut request) = worker_requests.recv() {
+                // a newer line within the delay replaces this one
+                loop {
+                    match worker_requests.recv_timeout(delay) {
+                        Ok(newer) => request = newer,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                let (generation, state) = request;
+                if worker_results.send((generation, highlighter.highlight(&state))).is_err() {
+                    return;
+                }
+            }
+        });
+        Debounced { requests, results, generation: 0, pending: false, requested: String::new(), last: None }
+    }
+
+    // asks for `state` to be highlighted, returning the ranges of the last
+    // line highlighted if it had the same buffer
+    pub(crate) fn request(&mut self, state: EditorState) -> Option<Highlights> {
+        self.generation += 1;
+        self.pending = true;
+        self.requested = state.buffer.clone();
+        let _ = self.requests.send((self.generation, state));
+        match self.last {
+            Some((ref buffer, ref ranges)) if *buffer == self.requested => Some(ranges.clone()),
+            _ => None,
+        }
+    }
+
+    // the ranges of the latest request, once they are ready
+    pub(crate) fn poll(&mut self) -> Option<Highlights> {
+        while let Ok((generation, ranges)) = self.results.try_recv() {
+            if generation == self.generation {
+                self.pending = false;
+                self.last = Some((self.requested.clone(), ranges.clone()));
+                return Some(ranges);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        self.pending
+    }
+}
@@ -0,0 +1,36 @@
+use std::env;
+use std::ffi::OsString;
+
+use super::builder::TerminalBuilder;
+
+// a history file used instead of the application's
+const HISTFILE: &str = "TERM_RS_HISTFILE";
+// a configuration file, read as by `TerminalBuilder::load_config`
+const CONFIG: &str = "TERM_RS_CONFIG";
+// set to anything but 0 to show errors in bold rather than red
+const NO_COLOR: &str = "TERM_RS_NO_COLOR";
+// set to anything but 0 to print commands instead of running them
+const DRY_RUN: &str = "TERM_RS_DRY_RUN";
+
+// Applies the variables users set to change any application, over what the
+// application itself chose.
+pub fn apply(mut builder: TerminalBuilder) -> TerminalBuilder {
+    if let Some(path) = env::var_os(CONFIG).filter(|path| !path.is_empty()) {
+        builder = builder.load_config(path);
+    }
+    if let Some(path) = env::var_os(HISTFILE).filter(|path| !path.is_empty()) {
+        builder.history_file = Some(path.into());
+    }
+    // NO_COLOR is the convention across programs, see no-color.org
+    if is_set(env::var_os(NO_COLOR)) || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        builder.colors = false;
+    }
+    if is_set(env::var_os(DRY_RUN)) {
+        builder.dry_run = true;
+    }
+    builder
+}
+
+fn is_set(value: Option<OsString>) -> bool {
+    value.is_some_and(|value| !value.is_empty() && value != "0")
+}
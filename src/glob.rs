@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Expands a glob pattern (`*`, `?`, `[a-z]`, `[!a-z]`, `\` escapes) against
+/// the file system. Matches are sorted; hidden files are only matched by
+/// patterns that start with a `.`. Returns nothing if no file matches.
+pub fn expand(pattern: &str) -> Vec<String> {
+    let absolute = pattern.starts_with('/');
+    let mut paths = vec![if absolute { PathBuf::from("/") } else { PathBuf::new() }];
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let chars: Vec<char> = component.chars().collect();
+        if !has_meta(&chars) {
+            let name = unescape(&chars);
+            paths = paths.into_iter().map(|p| p.join(&name)).filter(|p| p.exists()).collect();
+            continue;
+        }
+        let mut next = Vec::new();
+        for dir in &paths {
+            let entries = match fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && chars[0] != '.' {
+                    continue;
+                }
+                if matches(&chars, &name.chars().collect::<Vec<_>>()) {
+                    next.push(dir.join(&name));
+                }
+            }
+        }
+        paths = next;
+    }
+    let mut ret: Vec<String> = paths.into_iter()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    if pattern.ends_with('/') {
+        for p in &mut ret {
+            p.push('/');
+        }
+    }
+    ret.sort();
+    ret
+}
+
+fn has_meta(pattern: &[char]) -> bool {
+    let mut escaped = false;
+    for &c in pattern {
+        match c {
+            _ if escaped => { escaped = false; }
+            '\\' => { escaped = true; }
+            '*' | '?' | '[' => { return true; }
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(pattern: &[char]) -> String {
+    let mut ret = String::new();
+    let mut escaped = false;
+    for &c in pattern {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            ret.push(c);
+            escaped = false;
+        }
+    }
+    ret
+}
+
+/// Matches one path component against one pattern component.
+pub fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| matches(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), class(&pattern[1..], name.first().cloned())) {
+            (Some(_), Some((hit, len))) => hit && matches(&pattern[len + 1..], &name[1..]),
+            (None, Some(_)) => false,
+            // an unterminated `[` matches itself
+            (_, None) => name.first() == Some(&'[') && matches(&pattern[1..], &name[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => name.first() == Some(&pattern[1]) && matches(&pattern[2..], &name[1..]),
+        Some(c) => name.first() == Some(c) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+// tests `c` against the bracket expression following a `[`, returning the
+// result and the number of pattern chars consumed including the closing `]`
+fn class(pattern: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let negate = pattern.first() == Some(&'!') || pattern.first() == Some(&'^');
+    let start = if negate { 1 } else { 0 };
+    // a `]` right after the opening bracket is a literal member
+    let end = pattern.iter().skip(start + 1).position(|&c| c == ']')? + start + 1;
+    let members = &pattern[start..end];
+    let c = match c {
+        Some(c) => c,
+        None => return Some((false, end + 1)),
+    };
+    let mut i = 0;
+    let mut hit = false;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            hit |= members[i] <= c && c <= members[i + 2];
+            i += 3;
+        } else {
+            hit |= members[i] == c;
+            i += 1;
+        }
+    }
+    Some((hit != negate, end + 1))
+}
@@ -0,0 +1,46 @@
+use super::result::{CommandResult, Pending};
+
+struct Job {
+    id: usize,
+    command: String,
+    pending: Pending,
+}
+
+// Commands left running in the background, whose results are shown when
+// they arrive.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Jobs {
+    // adds a job, returning its number
+    pub fn add(&mut self, command: &str, pending: Pending) -> usize {
+        self.next_id += 1;
+        self.jobs.push(Job { id: self.next_id, command: command.to_owned(), pending });
+        self.next_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    // the jobs that have completed since the last call, with their number
+    // and command
+    pub fn finished(&mut self) -> Vec<(usize, String, CommandResult)> {
+        let mut finished = Vec::new();
+        self.jobs.retain(|job| match job.pending.try_take() {
+            Some(result) => {
+                finished.push((job.id, job.command.clone(), result));
+                false
+            }
+            None => true,
+        });
+        finished
+    }
+}
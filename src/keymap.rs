@@ -0,0 +1,368 @@
+use pancurses::Input;
+
+/// An editing action keys can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    AcceptLine,
+    Complete,
+    DeleteBackward,
+    DeleteForward,
+    ClearToStart,
+    ClearLine,
+    MoveToStart,
+    MoveToEnd,
+    MoveLeft,
+    MoveRight,
+    MoveWordLeft,
+    MoveWordRight,
+    PrevCommand,
+    NextCommand,
+    /// Recalls the previous history entry containing the text the line had
+    /// when the search started, anywhere and ignoring case, as zsh's
+    /// history-substring-search does.
+    HistorySubstringSearchUp,
+    /// Recalls the next history entry containing the searched text, or the
+    /// line as it was past the newest.
+    HistorySubstringSearchDown,
+    ShowHelp,
+    /// Switches between inserting typed characters and replacing the one
+    /// under the cursor.
+    ToggleOverwrite,
+    /// Browses the scrollback to copy text from it into the kill ring.
+    CopyMode,
+    /// Inserts the last killed or copied text.
+    Yank,
+    /// Runs the previous command again, replacing the line being edited.
+    RerunLast,
+    /// Runs the line and loads the history entry after it, to replay a
+    /// sequence of commands one key at a time.
+    OperateAndGetNext,
+    /// Replaces the word before the cursor with the snippet it names.
+    ExpandSnippet,
+    /// Clears the notification shown in the status area.
+    AcknowledgeNotification,
+    /// Expands the output folded last, or folds it again.
+    ToggleFold,
+    /// Switches between wrapping output lines wider than the screen and
+    /// truncating them.
+    ToggleWrap,
+    /// Shows or hides the time output lines start with.
+    ToggleTimestamps,
+    /// Runs the action registered under this name with
+    /// `TerminalBuilder::action`.
+    Custom(&'static str),
+    StartMacro,
+    EndMacro,
+    PlayMacro,
+}
+
+/// A key press: what curses reports, or an ESC-prefixed character which
+/// terminals send for Alt (Meta) combinations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Input(Input),
+    Alt(char),
+    // a named key such as Left held with Ctrl
+    Ctrl(Input),
+    // text pasted in bracketed paste mode, kept by the terminal until it is
+    // inserted
+    Paste,
+}
+
+pub enum Lookup {
+    Found(Action),
+    // the keys begin a longer binding
+    Prefix,
+    None,
+}
+
+/// Key sequences and the actions they are bound to.
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap { bindings: Vec::new() };
+        let defaults = [
+            ("Enter", Action::AcceptLine),
+            ("Tab", Action::Complete),
+            ("Backspace", Action::DeleteBackward),
+            ("Ctrl+U", Action::ClearToStart),
+            ("Ctrl+L", Action::ClearLine),
+            ("Ctrl+A", Action::MoveToStart),
+            ("Ctrl+E", Action::MoveToEnd),
+            ("Home", Action::MoveToStart),
+            ("End", Action::MoveToEnd),
+            ("Delete", Action::DeleteForward),
+            ("Left", Action::MoveLeft),
+            ("Right", Action::MoveRight),
+            ("Alt+b", Action::MoveWordLeft),
+            ("Alt+f", Action::MoveWordRight),
+            ("Ctrl+Left", Action::MoveWordLeft),
+            ("Ctrl+Right", Action::MoveWordRight),
+            ("Up", Action::PrevCommand),
+            ("Down", Action::NextCommand),
+            ("PageUp", Action::HistorySubstringSearchUp),
+            ("PageDown", Action::HistorySubstringSearchDown),
+            ("F1", Action::ShowHelp),
+            ("Insert", Action::ToggleOverwrite),
+            ("Ctrl+X [", Action::CopyMode),
+            ("Ctrl+Y", Action::Yank),
+            ("F5", Action::RerunLast),
+            ("Ctrl+O", Action::OperateAndGetNext),
+            ("Ctrl+X s", Action::ExpandSnippet),
+            ("Ctrl+X n", Action::AcknowledgeNotification),
+            ("Ctrl+X o", Action::ToggleFold),
+            ("Ctrl+X w", Action::ToggleWrap),
+            ("Ctrl+X t", Action::ToggleTimestamps),
+            ("Ctrl+X (", Action::StartMacro),
+            ("Ctrl+X )", Action::EndMacro),
+            ("Ctrl+X e", Action::PlayMacro),
+        ];
+        for &(keys, action) in defaults.iter() {
+            keymap.bind(parse(keys).unwrap(), action);
+        }
+        // terminals send either for the backspace key, and the Windows
+        // console a Ctrl+H
+        keymap.bind(vec![Key::Input(Input::Character('\u{7f}'))], Action::DeleteBackward);
+        keymap.bind(vec![Key::Input(Input::Character('\u{8}'))], Action::DeleteBackward);
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Binds `keys`, replacing any binding of the same sequence.
+    pub fn bind(&mut self, keys: Vec<Key>, action: Action) {
+        self.bindings.retain(|(k, _)| *k != keys);
+        self.bindings.push((keys, action));
+    }
+
+    /// The first sequence bound to `action`.
+    pub fn keys_for(&self, action: Action) -> Option<&[Key]> {
+        self.bindings.iter().find(|&&(_, a)| a == action).map(|(keys, _)| keys.as_slice())
+    }
+
+    pub fn get(&self, keys: &[Key]) -> Option<Action> {
+        self.bindings.iter().find(|&(k, _)| k.as_slice() == keys).map(|&(_, action)| action)
+    }
+
+    /// Looks up the keys typed so far. A sequence that is both bound and the
+    /// start of a longer one is a `Prefix`, resolved by the next key or the
+    /// timeout.
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        if self.bindings.iter().any(|(k, _)| k.len() > keys.len() && k.starts_with(keys)) {
+            return Lookup::Prefix;
+        }
+        match self.get(keys) {
+            Some(action) => Lookup::Found(action),
+            None => Lookup::None,
+        }
+    }
+}
+
+/// Parses a space-separated key sequence such as `"Ctrl+X Ctrl+S"` or
+/// `"g g"`. Keys are single characters, `Ctrl+<char>`, `Alt+<char>`, `Enter`,
+/// `Tab`, `Space`, `Backspace`, `Esc`, `Delete`, `Insert`, `Home`, `End`,
+/// `PageUp`, `PageDown`, arrows such as `Up`, and `F1` to `F12`. Arrows,
+/// `Home`, `End` and `Delete` also take a `Ctrl+` prefix; arrows, `Home`,
+/// `End` and `Tab` a `Shift+` one.
+pub fn parse(spec: &str) -> Result<Vec<Key>, String> {
+    let keys = spec.split_whitespace().map(parse_key).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_owned());
+    }
+    Ok(keys)
+}
+
+// the names of the built-in actions in configuration files
+const ACTIONS: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("complete", Action::Complete),
+    ("delete-backward", Action::DeleteBackward),
+    ("delete-forward", Action::DeleteForward),
+    ("clear-to-start", Action::ClearToStart),
+    ("clear-line", Action::ClearLine),
+    ("move-to-start", Action::MoveToStart),
+    ("move-to-end", Action::MoveToEnd),
+    ("move-left", Action::MoveLeft),
+    ("move-right", Action::MoveRight),
+    ("move-word-left", Action::MoveWordLeft),
+    ("move-word-right", Action::MoveWordRight),
+    ("prev-command", Action::PrevCommand),
+    ("next-command", Action::NextCommand),
+    ("history-substring-search-up", Action::HistorySubstringSearchUp),
+    ("history-substring-search-down", Action::HistorySubstringSearchDown),
+    ("show-help", Action::ShowHelp),
+    ("toggle-overwrite", Action::ToggleOverwrite),
+    ("copy-mode", Action::CopyMode),
+    ("yank", Action::Yank),
+    ("rerun-last", Action::RerunLast),
+    ("operate-and-get-next", Action::OperateAndGetNext),
+    ("expand-snippet", Action::ExpandSnippet),
+    ("acknowledge-notification", Action::AcknowledgeNotification),
+    ("toggle-fold", Action::ToggleFold),
+    ("toggle-wrap", Action::ToggleWrap),
+    ("toggle-timestamps", Action::ToggleTimestamps),
+    ("start-macro", Action::StartMacro),
+    ("end-macro", Action::EndMacro),
+    ("play-macro", Action::PlayMacro),
+];
+
+// the built-in action called `name`, e.g. "move-word-left"
+pub fn action_named(name: &str) -> Option<Action> {
+    ACTIONS.iter().find(|&&(n, _)| n == name).map(|&(_, action)| action)
+}
+
+const NAMES: &[(&str, Input)] = &[
+    ("Enter", Input::Character('\n')),
+    ("Tab", Input::Character('\t')),
+    ("Space", Input::Character(' ')),
+    ("Esc", Input::Character('\u{1b}')),
+    ("Backspace", Input::KeyBackspace),
+    ("Delete", Input::KeyDC),
+    ("Insert", Input::KeyIC),
+    ("Home", Input::KeyHome),
+    ("End", Input::KeyEnd),
+    ("PageUp", Input::KeyPPage),
+    ("PageDown", Input::KeyNPage),
+    ("Up", Input::KeyUp),
+    ("Down", Input::KeyDown),
+    ("Left", Input::KeyLeft),
+    ("Right", Input::KeyRight),
+    ("F1", Input::KeyF1),
+    ("F2", Input::KeyF2),
+    ("F3", Input::KeyF3),
+    ("F4", Input::KeyF4),
+    ("F5", Input::KeyF5),
+    ("F6", Input::KeyF6),
+    ("F7", Input::KeyF7),
+    ("F8", Input::KeyF8),
+    ("F9", Input::KeyF9),
+    ("F10", Input::KeyF10),
+    ("F11", Input::KeyF11),
+    ("F12", Input::KeyF12),
+    ("Shift+Up", Input::KeySR),
+    ("Shift+Down", Input::KeySF),
+    ("Shift+Left", Input::KeySLeft),
+    ("Shift+Right", Input::KeySRight),
+    ("Shift+Home", Input::KeySHome),
+    ("Shift+End", Input::KeySEnd),
+    ("Shift+Tab", Input::KeyBTab),
+];
+
+// keys terminals report with a Ctrl modifier
+const CTRL_KEYS: &[Input] = &[
+    Input::KeyUp, Input::KeyDown, Input::KeyLeft, Input::KeyRight,
+    Input::KeyHome, Input::KeyEnd, Input::KeyDC,
+];
+
+fn parse_key(name: &str) -> Result<Key, String> {
+    if let Some(c) = single_char(name) {
+        return Ok(Key::Input(Input::Character(c)));
+    }
+    if let Some(c) = name.strip_prefix("Ctrl+").and_then(single_char) {
+        let c = c.to_ascii_uppercase();
+        if ('@'..='_').contains(&c) {
+            return Ok(Key::Input(Input::Character((c as u8 & 0x1f) as char)));
+        }
+    }
+    if let Some(c) = name.strip_prefix("Alt+").and_then(single_char) {
+        return Ok(Key::Alt(c));
+    }
+    if let Some(key) = name.strip_prefix("Ctrl+").and_then(named) {
+        if CTRL_KEYS.contains(&key) {
+            return Ok(Key::Ctrl(key));
+        }
+    }
+    named(name).map(Key::Input).ok_or_else(|| format!("unknown key `{}`", name))
+}
+
+fn named(name: &str) -> Option<Input> {
+    NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, key)| key)
+}
+
+fn single_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Decodes the parameters and final byte of an `ESC [` key sequence, such
+/// as `1;5D` for Ctrl+Left or the vt220-style `1~` for Home.
+pub fn decode_csi(params: &str, last: char) -> Option<Key> {
+    let mut params = params.split(';');
+    let first = params.next().unwrap_or("");
+    let key = match (last, first) {
+        ('A', _) => Input::KeyUp,
+        ('B', _) => Input::KeyDown,
+        ('C', _) => Input::KeyRight,
+        ('D', _) => Input::KeyLeft,
+        ('H', _) | ('~', "1") | ('~', "7") => Input::KeyHome,
+        ('F', _) | ('~', "4") | ('~', "8") => Input::KeyEnd,
+        ('~', "2") => Input::KeyIC,
+        ('~', "3") => Input::KeyDC,
+        _ => return None,
+    };
+    match (params.next(), key) {
+        (None, key) | (Some("1"), key) => Some(Key::Input(key)),
+        (Some("5"), key) => Some(Key::Ctrl(key)),
+        (Some("2"), Input::KeyUp) => Some(Key::Input(Input::KeySR)),
+        (Some("2"), Input::KeyDown) => Some(Key::Input(Input::KeySF)),
+        (Some("2"), Input::KeyLeft) => Some(Key::Input(Input::KeySLeft)),
+        (Some("2"), Input::KeyRight) => Some(Key::Input(Input::KeySRight)),
+        (Some("2"), Input::KeyHome) => Some(Key::Input(Input::KeySHome)),
+        (Some("2"), Input::KeyEnd) => Some(Key::Input(Input::KeySEnd)),
+        _ => None,
+    }
+}
+
+// where PDCurses puts its own key codes, which pancurses passes on as
+// characters from this private use area
+#[cfg(windows)]
+const PDC_KEY_OFFSET: u32 = 0xec00;
+
+/// Decodes the Alt and Ctrl combinations the Windows console reports as
+/// PDCurses key codes rather than escape sequences: Alt with a letter or
+/// digit, and Ctrl with Left, Right, Home or End.
+#[cfg(windows)]
+pub fn decode_pdcurses(input: Input) -> Option<Key> {
+    let code = match input {
+        Input::Character(c) => (c as u32).checked_sub(PDC_KEY_OFFSET)?,
+        _ => return None,
+    };
+    match code {
+        // ALT_0 to ALT_9, then ALT_A to ALT_Z
+        0x97..=0xa0 => std::char::from_u32('0' as u32 + code - 0x97).map(Key::Alt),
+        0xa1..=0xba => std::char::from_u32('a' as u32 + code - 0xa1).map(Key::Alt),
+        0xbb => Some(Key::Ctrl(Input::KeyLeft)),
+        0xbc => Some(Key::Ctrl(Input::KeyRight)),
+        0xbf => Some(Key::Ctrl(Input::KeyHome)),
+        0xc0 => Some(Key::Ctrl(Input::KeyEnd)),
+        _ => None,
+    }
+}
+
+/// Names `keys` in the notation `parse` accepts.
+pub fn describe(keys: &[Key]) -> String {
+    keys.iter().map(describe_key).collect::<Vec<_>>().join(" ")
+}
+
+fn describe_key(key: &Key) -> String {
+    let key = match *key {
+        Key::Alt(c) => return format!("Alt+{}", c),
+        Key::Ctrl(key) => return format!("Ctrl+{}", describe_key(&Key::Input(key))),
+        Key::Input(key) => key,
+        Key::Paste => return "Paste".to_owned(),
+    };
+    if let Some(&(name, _)) = NAMES.iter().find(|&&(_, k)| k == key) {
+        return name.to_owned();
+    }
+    match key {
+        Input::Character(c) if (c as u32) < 0x20 => format!("Ctrl+{}", ((c as u8) | 0x40) as char),
+        Input::Character(c) => c.to_string(),
+        other => format!("{:?}", other),
+    }
+}
@@ -1,5 +1,65 @@
 extern crate pancurses;
+#[cfg(unix)]
+extern crate libc;
 
 mod terminal;
 mod command;
-pub use self::terminal::Terminal;
\ No newline at end of file
+mod chain;
+mod macros;
+mod abbr;
+mod builder;
+mod brackets;
+mod result;
+mod pipe;
+mod redirect;
+mod keymap;
+mod output;
+mod paste;
+mod scrollback;
+mod copy_mode;
+mod snippet;
+mod jobs;
+mod notify;
+mod editor;
+mod prompt;
+mod messages;
+mod plain;
+mod remote;
+mod websocket;
+mod stats;
+mod limits;
+mod auth;
+mod audit;
+mod plugin;
+mod config;
+mod markdown;
+mod diff;
+mod regex;
+mod normalize;
+mod width;
+mod environment;
+#[cfg(feature = "latency-probe")]
+pub mod probe;
+pub mod glob;
+pub mod workdir;
+pub mod parse;
+pub mod registry;
+pub mod completion;
+pub use self::terminal::{suspend_ui, Terminal};
+pub use self::builder::{Bell, CursorShape, ErrorPolicy, Style, TerminalBuilder, Timestamps, Verbosity};
+pub use self::registry::Registry;
+pub use self::result::{cancel_token, CancelToken, CommandResult, Pending};
+pub use self::command::{CommandHistory, CommandUsage, HistoryCipher, HistoryFormat};
+pub use self::completion::{Completer, Matcher};
+pub use self::keymap::Action;
+pub use self::editor::{EditorState, Highlighter};
+pub use self::scrollback::Scrollback;
+pub use self::prompt::{PromptContext, PromptSegment, PromptSegments};
+pub use self::messages::{English, Messages};
+pub use self::remote::Sessions;
+pub use self::stats::{Histogram, SessionStats};
+pub use self::limits::RateLimit;
+pub use self::notify::{Level, Notification, Notifier};
+pub use self::auth::{current_user, Authenticator};
+pub use self::audit::{AuditEntry, AuditSink, JsonlAudit, Outcome};
+pub use self::plugin::Plugin;
@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::messages::Messages;
+
+/// Limits how fast a session may run commands, and optionally locks it out
+/// for a while after repeated failures of an authentication command.
+///
+/// Sessions of the same user, or of the same peer before logging in, share
+/// one limit, so reconnecting doesn't reset it. Clones share it too.
+#[derive(Clone)]
+pub struct RateLimit {
+    commands: usize,
+    per: Duration,
+    auth_command: Option<String>,
+    max_failures: usize,
+    lockout: Duration,
+    // the state of each user or peer, by `Limiter` key
+    states: Arc<Mutex<HashMap<String, State>>>,
+}
+
+impl RateLimit {
+    /// Allows at most `commands` commands in any `per` period. A
+    /// `commands` of 0 counts as 1.
+    pub fn new(commands: usize, per: Duration) -> Self {
+        RateLimit {
+            commands: commands.max(1),
+            per,
+            auth_command: None,
+            max_failures: 0,
+            lockout: Duration::default(),
+            states: Default::default(),
+        }
+    }
+
+    /// Refuses every command for `lockout` once `auth_command` has failed
+    /// `max_failures` times in a row. A `max_failures` of 0 counts as 1.
+    pub fn lockout(mut self, auth_command: &str, max_failures: usize, lockout: Duration) -> Self {
+        self.auth_command = Some(auth_command.to_owned());
+        self.max_failures = max_failures.max(1);
+        self.lockout = lockout;
+        self
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("commands", &self.commands)
+            .field("per", &self.per)
+            .field("auth_command", &self.auth_command)
+            .field("max_failures", &self.max_failures)
+            .field("lockout", &self.lockout)
+            .finish()
+    }
+}
+
+impl PartialEq for RateLimit {
+    fn eq(&self, other: &Self) -> bool {
+        self.commands == other.commands
+            && self.per == other.per
+            && self.auth_command == other.auth_command
+            && self.max_failures == other.max_failures
+            && self.lockout == other.lockout
+    }
+}
+
+// the rate limit state of one user or peer
+#[derive(Default)]
+struct State {
+    // when the commands of the current period ran
+    recent: VecDeque<Instant>,
+    failures: usize,
+    locked_until: Option<Instant>,
+}
+
+// the rate limit of one session, kept under `key` in the shared states
+#[derive(Default)]
+pub struct Limiter {
+    limit: Option<RateLimit>,
+    key: String,
+}
+
+impl Limiter {
+    pub fn new(limit: Option<RateLimit>, key: String) -> Self {
+        Limiter { limit, key }
+    }
+
+    // counts `command` as run, or refuses it with the message to show
+    pub fn check(&mut self, command: &str, messages: &dyn Messages) -> Result<(), String> {
+        let limit = match self.limit {
+            Some(ref limit) if !command.trim().is_empty() => limit,
+            _ => return Ok(()),
+        };
+        let now = Instant::now();
+        let mut states = limit.states.lock().unwrap();
+        // forget whoever has nothing left to count
+        states.retain(|key, state| {
+            *key == self.key
+                || state.failures > 0
+                || state.locked_until.is_some_and(|until| now < until)
+                || state.recent.back().is_some_and(|&at| now.duration_since(at) < limit.per)
+        });
+        let state = states.entry(self.key.clone()).or_default();
+        if let Some(until) = state.locked_until {
+            if now < until {
+                return Err(messages.locked_out(until - now));
+            }
+            state.locked_until = None;
+        }
+        while state.recent.front().is_some_and(|&at| now.duration_since(at) >= limit.per) {
+            state.recent.pop_front();
+        }
+        if let Some(&oldest) = state.recent.front().filter(|_| state.recent.len() >= limit.commands) {
+            return Err(messages.rate_limited(limit.per - now.duration_since(oldest)));
+        }
+        state.recent.push_back(now);
+        Ok(())
+    }
+
+    pub fn record(&mut self, command: &str, success: bool) {
+        let limit = match self.limit {
+            Some(ref limit) => limit,
+            None => return,
+        };
+        let auth = limit.auth_command.as_deref().is_some_and(|auth| command.split_whitespace().next() == Some(auth));
+        if !auth {
+            return;
+        }
+        let mut states = limit.states.lock().unwrap();
+        let state = states.entry(self.key.clone()).or_default();
+        if success {
+            state.failures = 0;
+            return;
+        }
+        state.failures += 1;
+        if state.failures >= limit.max_failures {
+            state.failures = 0;
+            state.locked_until = Some(Instant::now() + limit.lockout);
+        }
+    }
+}
+
+// the key sessions share a limit under: the user's, else the peer's, else
+// the session's own, with local sessions sharing one
+pub fn key(user: Option<&str>, peer: Option<&str>, session: Option<usize>) -> String {
+    match (user, peer, session) {
+        (Some(user), _, _) => format!("user {}", user),
+        (None, Some(peer), _) => format!("peer {}", peer),
+        (None, None, Some(session)) => format!("session {}", session),
+        (None, None, None) => "local".to_owned(),
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use super::keymap::Key;
+
+/// Keyboard macros recorded during the session.
+#[derive(Default)]
+pub struct Macros {
+    recording: Option<Vec<Key>>,
+    last: Vec<Key>,
+    named: BTreeMap<String, Vec<Key>>,
+}
+
+impl Macros {
+    pub fn start(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(keys) = self.recording.take() {
+            self.last = keys;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn record(&mut self, key: Key) {
+        if let Some(ref mut keys) = self.recording {
+            keys.push(key);
+        }
+    }
+
+    pub fn last(&self) -> &[Key] {
+        &self.last
+    }
+
+    /// Saves the last recorded macro under `name`.
+    pub fn save(&mut self, name: &str) -> bool {
+        if self.last.is_empty() {
+            return false;
+        }
+        self.named.insert(name.to_owned(), self.last.clone());
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[Key]> {
+        self.named.get(name).map(|keys| keys.as_slice())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.named.remove(name).is_some()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.named.keys()
+    }
+}
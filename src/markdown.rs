@@ -0,0 +1,154 @@
+use super::builder::Style;
+
+// a rendered line: pieces of text and their styles
+pub type Line = Vec<(String, Style)>;
+
+// Renders the Markdown most output uses, `width` columns wide: headings,
+// bullet and numbered lists, quotes, fenced code, rules, tables and inline
+// **bold**, *emphasis* and `code`. Anything else is shown as it is.
+pub fn render(text: &str, width: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut table: Vec<Vec<String>> = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if !in_code && trimmed.starts_with('|') {
+            // the row under the header only separates it
+            if !trimmed.chars().all(|c| "|-: ".contains(c)) {
+                table.push(cells(trimmed));
+            }
+            continue;
+        }
+        if !table.is_empty() {
+            lines.extend(render_table(&table));
+            table.clear();
+        }
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(vec![(format!("    {}", line), Style::Plain)]);
+            continue;
+        }
+        let indent = &line[..line.len() - trimmed.len()];
+        let heading = trimmed.len() - trimmed.trim_start_matches('#').len();
+        let line = match trimmed {
+            _ if (1..=6).contains(&heading) && trimmed[heading..].starts_with(' ') => {
+                vec![(trimmed[heading..].trim().to_owned(), Style::Bold)]
+            }
+            _ if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '-' || c == '*' || c == '_') => {
+                // a full row would be followed by an empty one
+                vec![("\u{2500}".repeat(width.saturating_sub(1).min(80)), Style::Dim)]
+            }
+            _ if trimmed.starts_with("- ") || trimmed.starts_with("* ") || trimmed.starts_with("+ ") => {
+                let mut line = vec![(format!("{}\u{2022} ", indent), Style::Plain)];
+                line.extend(inline(&trimmed[2..]));
+                line
+            }
+            _ if trimmed.starts_with('>') => {
+                let quote = trimmed[1..].trim_start();
+                vec![(format!("{}\u{2502} {}", indent, quote), Style::Dim)]
+            }
+            _ => {
+                let mut line = vec![(indent.to_owned(), Style::Plain)];
+                line.extend(inline(trimmed));
+                line
+            }
+        };
+        lines.push(line);
+    }
+    if !table.is_empty() {
+        lines.extend(render_table(&table));
+    }
+    lines
+}
+
+// the rendered lines without their styles
+pub fn text(lines: &[Line]) -> String {
+    lines.iter()
+        .map(|line| line.iter().map(|(text, _)| text.as_str()).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// `line` cut to `columns` characters, ending with an ellipsis if it is
+// longer
+pub fn truncate(line: &Line, columns: usize) -> Line {
+    if line.iter().map(|(text, _)| text.chars().count()).sum::<usize>() <= columns {
+        return line.clone();
+    }
+    let mut ret = Vec::new();
+    let mut left = columns.saturating_sub(1);
+    for (text, style) in line {
+        let piece: String = text.chars().take(left).collect();
+        left -= piece.chars().count();
+        ret.push((piece, *style));
+    }
+    ret.push(("\u{2026}".to_owned(), Style::Plain));
+    ret
+}
+
+// the cells of a `| a | b |` row
+fn cells(row: &str) -> Vec<String> {
+    let row = row.trim().trim_start_matches('|');
+    let row = row.strip_suffix('|').unwrap_or(row);
+    row.split('|').map(|cell| cell.trim().replace('`', "").replace("**", "")).collect()
+}
+
+// the rows with their columns aligned, the first as a bold header
+fn render_table(rows: &[Vec<String>]) -> Vec<Line> {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| rows.iter().filter_map(|row| row.get(i)).map(|cell| cell.chars().count()).max().unwrap_or(0))
+        .collect();
+    let mut lines = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let text = widths.iter().enumerate()
+            .map(|(j, &width)| format!("{:width$}", row.get(j).map_or("", |cell| cell.as_str()), width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        lines.push(vec![(text.trim_end().to_owned(), if i == 0 { Style::Bold } else { Style::Plain })]);
+        if i == 0 && rows.len() > 1 {
+            let rule = widths.iter().map(|&width| "\u{2500}".repeat(width)).collect::<Vec<_>>().join("  ");
+            lines.push(vec![(rule, Style::Dim)]);
+        }
+    }
+    lines
+}
+
+// **bold**, *emphasis* or _emphasis_, and `code`
+fn inline(text: &str) -> Line {
+    let mut line = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (marker, style) = match c {
+            '`' => ("`", Style::Reverse),
+            '*' if rest.starts_with("**") => ("**", Style::Bold),
+            '*' => ("*", Style::Underline),
+            // not within a word, as in snake_case
+            '_' if !plain.ends_with(|p: char| p.is_alphanumeric()) => ("_", Style::Underline),
+            _ => ("", Style::Plain),
+        };
+        let inner = &rest[marker.len()..];
+        match inner.find(marker) {
+            Some(end) if !marker.is_empty() && end > 0 && !inner.starts_with(' ') => {
+                if !plain.is_empty() {
+                    line.push((plain.clone(), Style::Plain));
+                    plain.clear();
+                }
+                line.push((inner[..end].to_owned(), style));
+                rest = &inner[end + marker.len()..];
+            }
+            _ => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        line.push((plain, Style::Plain));
+    }
+    line
+}
@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use super::notify::Level;
+
+/// The text the crate itself shows: errors, usage lines, notices. Each
+/// method returns the English text by default; implement the ones to
+/// translate and pass the implementation to `TerminalBuilder::messages` and
+/// `Registry::messages`.
+///
+/// ```
+/// use term_rs::Messages;
+///
+/// struct German;
+///
+/// impl Messages for German {
+///     fn command_not_found(&self, name: &str) -> String {
+///         format!("{}: Befehl nicht gefunden", name)
+///     }
+/// }
+/// ```
+pub trait Messages {
+    fn command_not_found(&self, name: &str) -> String {
+        format!("{}: command not found", name)
+    }
+
+    /// A command the session's role doesn't allow.
+    fn permission_denied(&self, name: &str) -> String {
+        format!("{}: permission denied", name)
+    }
+
+    fn no_help(&self, name: &str) -> String {
+        format!("{}: no help available", name)
+    }
+
+    fn parse_error(&self, error: &str) -> String {
+        format!("parse error: {}", error)
+    }
+
+    /// A glob pattern that matched no file.
+    fn no_match(&self, pattern: &str) -> String {
+        format!("warning: no match for {}", pattern)
+    }
+
+    /// An error from `source`, e.g. a built-in or the history file.
+    fn error(&self, source: &str, error: &str) -> String {
+        format!("{}: {}", source, error)
+    }
+
+    /// `count` unreadable lines of the history file were skipped.
+    fn history_lines_skipped(&self, count: usize) -> String {
+        format!("skipped {} unreadable line{}", count, if count == 1 { "" } else { "s" })
+    }
+
+    /// The usage line of built-in `command`.
+    fn usage(&self, command: &str) -> String {
+        let usage = match command {
+            "abbr" => "abbr add <name> <expansion> | abbr erase <name> | abbr list",
+            "macro" => "macro save|play|erase <name> | macro list",
+            "cd" => "cd [dir]",
+            "grep" => "grep [-i] <pattern>",
+            "history" => "history stats [count]",
+            "save-output" => "save-output [-l] <file>",
+            "set" => "set output quiet|normal|verbose",
+            "wizard" => "wizard <command>",
+            other => other,
+        };
+        format!("usage: {}", usage)
+    }
+
+    /// `name`, given to built-in `command`, doesn't exist.
+    fn not_found(&self, command: &str, name: &str) -> String {
+        format!("{}: {}: not found", command, name)
+    }
+
+    fn no_history_entry(&self, entry: &str) -> String {
+        format!("fc: {}: no such history entry", entry)
+    }
+
+    fn history_empty(&self) -> String {
+        "fc: history is empty".to_owned()
+    }
+
+    /// How `wizard` asks for param `name`.
+    fn wizard_field(&self, name: &str, help: &str) -> String {
+        if help.is_empty() { format!("{}: ", name) } else { format!("{} ({}): ", name, help) }
+    }
+
+    fn wizard_started(&self, command: &str) -> String {
+        format!("[{}: enter each argument, an empty one cancels]", command)
+    }
+
+    fn wizard_cancelled(&self) -> String {
+        "wizard: cancelled".to_owned()
+    }
+
+    fn macro_saved(&self, name: &str) -> String {
+        format!("macro {} saved", name)
+    }
+
+    fn nothing_recorded(&self) -> String {
+        "macro: nothing recorded, use ctrl+X ( and ctrl+X ) first".to_owned()
+    }
+
+    /// A line read in plain mode longer than `TerminalBuilder::max_input`.
+    fn input_too_long(&self, max: usize) -> String {
+        format!("input too long, at most {} characters", max)
+    }
+
+    /// Shown instead of output longer than `TerminalBuilder::fold_output`,
+    /// `key` expanding it.
+    fn output_folded(&self, lines: usize, key: Option<&str>) -> String {
+        match key {
+            Some(key) => format!("[\u{2026} {} lines, press {} to expand]", lines, key),
+            None => format!("[\u{2026} {} lines folded]", lines),
+        }
+    }
+
+    /// Line `number` of batch input, `line`, failed.
+    fn line_failed(&self, number: usize, line: &str) -> String {
+        format!("line {}: {}: failed", number, line)
+    }
+
+    /// Asks whether to go on with batch input after a line failed.
+    fn continue_after_error(&self) -> String {
+        "continue? [y/N] ".to_owned()
+    }
+
+    /// How a dry run shows `command` instead of running it.
+    fn dry_run(&self, command: &str) -> String {
+        format!("+ {}", command)
+    }
+
+    /// Asks whether to run the `count` lines of a paste, shown above.
+    fn confirm_paste(&self, count: usize) -> String {
+        format!("run {} pasted line{}? [y/N]", count, if count == 1 { "" } else { "s" })
+    }
+
+    /// Stands for the last `count` lines of a paste, too many to show.
+    fn paste_lines_hidden(&self, count: usize) -> String {
+        format!("\u{2026} {} more line{}", count, if count == 1 { "" } else { "s" })
+    }
+
+    /// Asks whether to show more of output shown a page at a time.
+    fn more(&self) -> String {
+        "-- More --".to_owned()
+    }
+
+    fn output_interrupted(&self) -> String {
+        "[output interrupted]".to_owned()
+    }
+
+    fn output_truncated(&self, shown: usize, total: usize) -> String {
+        format!("[output truncated: {} of {} bytes shown]", shown, total)
+    }
+
+    /// The line verbose mode shows after each command.
+    fn command_status(&self, command: &str, success: bool, elapsed: Duration) -> String {
+        format!("[{}: {} in {:?}]", command, if success { "ok" } else { "failed" }, elapsed)
+    }
+
+    /// A notification shown above the prompt, `clock` being its local time.
+    fn notification(&self, level: Level, clock: &str, message: &str) -> String {
+        let level = match level {
+            Level::Info => "info",
+            Level::Warn => "warning",
+            Level::Error => "error",
+        };
+        format!("[{}] {}: {}", clock, level, message)
+    }
+
+    /// Shown when a command takes longer than its timeout.
+    fn command_timed_out(&self, elapsed: Duration) -> String {
+        format!("[running for {}s: w to wait, b for background, c to cancel]", elapsed.as_secs())
+    }
+
+    fn command_cancelled(&self) -> String {
+        "[cancelled]".to_owned()
+    }
+
+    /// Starts the prompt while `count` background jobs run.
+    fn jobs_running(&self, count: usize) -> String {
+        format!("[{}+] ", count)
+    }
+
+    fn job_started(&self, id: usize) -> String {
+        format!("[#{} running in the background]", id)
+    }
+
+    fn job_completed(&self, id: usize) -> String {
+        format!("[#{} completed]", id)
+    }
+
+    /// A secret the `Authenticator` refused.
+    fn login_failed(&self) -> String {
+        "login incorrect".to_owned()
+    }
+
+    fn too_many_attempts(&self) -> String {
+        "too many failed login attempts".to_owned()
+    }
+
+    /// Shown on the cleared screen of a terminal locked by
+    /// `TerminalBuilder::idle_lock`, `any_key` when any key unlocks it.
+    fn locked(&self, any_key: bool) -> String {
+        match any_key {
+            true => "locked, press any key".to_owned(),
+            false => "locked".to_owned(),
+        }
+    }
+
+    /// A command refused for exceeding the rate limit.
+    fn rate_limited(&self, retry_after: Duration) -> String {
+        format!("rate limit exceeded, retry in {}s", retry_after.as_secs() + 1)
+    }
+
+    /// A command refused after repeated authentication failures.
+    fn locked_out(&self, remaining: Duration) -> String {
+        format!("too many failed attempts, locked for {}s", remaining.as_secs() + 1)
+    }
+
+    /// Shown to remote clients on connecting.
+    fn session_started(&self, id: usize) -> String {
+        format!("[session {}, `detach` to leave it and `attach <id>` to resume one]", id)
+    }
+
+    /// The answer to `sessions`, listing the detached remote sessions.
+    fn detached_sessions(&self, ids: &[usize]) -> String {
+        if ids.is_empty() {
+            "no detached sessions".to_owned()
+        } else {
+            format!("detached sessions: {}", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(" "))
+        }
+    }
+
+    fn copy_mode_status(&self, line: usize, lines: usize) -> String {
+        format!("[copy mode] line {}/{}", line, lines)
+    }
+}
+
+/// The default messages.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct English;
+
+impl Messages for English {}
@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How serious a notification is, which sets its style.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub level: Level,
+    pub message: String,
+    pub time: SystemTime,
+}
+
+/// Sends notifications to the terminal from any thread, shown above the
+/// prompt as they come. Clones share the same queue, so an application
+/// keeps one and passes another to `TerminalBuilder::notifier`.
+///
+/// ```no_run
+/// use std::thread;
+/// use term_rs::{Level, Notifier, Terminal};
+///
+/// let notifier = Notifier::default();
+/// let disk = notifier.clone();
+/// thread::spawn(move || disk.notify(Level::Warn, "disk nearly full"));
+/// Terminal::builder().notifier(notifier).run(|command| command);
+/// ```
+#[derive(Clone, Default)]
+pub struct Notifier {
+    queue: Arc<Mutex<VecDeque<Notification>>>,
+}
+
+impl Notifier {
+    pub fn notify(&self, level: Level, message: &str) {
+        let notification = Notification { level, message: message.to_owned(), time: SystemTime::now() };
+        self.queue.lock().unwrap().push_back(notification);
+    }
+
+    // the notifications not shown yet
+    pub(crate) fn take(&self) -> Vec<Notification> {
+        self.queue.lock().unwrap().drain(..).collect()
+    }
+}
+
+// the local time of day, HH:MM:SS
+pub fn clock(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let secs_of_day = local_offset(secs).map_or(secs as i64, |offset| secs as i64 + offset).rem_euclid(86400);
+    format!("{:02}:{:02}:{:02}", secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+// seconds east of UTC at `secs` since the epoch
+#[cfg(unix)]
+fn local_offset(secs: u64) -> Option<i64> {
+    let time = secs as ::libc::time_t;
+    let mut tm: ::libc::tm = unsafe { ::std::mem::zeroed() };
+    if unsafe { ::libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm.tm_gmtoff as i64)
+}
+
+#[cfg(not(unix))]
+fn local_offset(_: u64) -> Option<i64> {
+    None
+}
@@ -0,0 +1,155 @@
+use std::time::{Instant, SystemTime};
+
+use super::builder::Timestamps;
+use super::notify;
+
+/// Replaces tabs with spaces up to the next multiple of `width` columns,
+/// counting from the start of each line.
+pub fn expand_tabs(text: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut ret = String::with_capacity(text.len());
+    let mut column = 0;
+    for c in text.chars() {
+        match c {
+            '\t' => {
+                let spaces = width - column % width;
+                ret.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            }
+            '\n' => {
+                ret.push(c);
+                column = 0;
+            }
+            _ => {
+                ret.push(c);
+                column += 1;
+            }
+        }
+    }
+    ret
+}
+
+/// Turns `\r\n` into `\n`. A bare `\r` also ends the line, unless
+/// `overwrite` is set: then, as on a terminal, the text after it overwrites
+/// the line from its start, leaving e.g. only the last state of a progress
+/// line.
+pub fn normalize_line_endings(text: &str, overwrite: bool) -> String {
+    let text = text.replace("\r\n", "\n");
+    if !overwrite {
+        return text.replace('\r', "\n");
+    }
+    text.split('\n').map(overlay).collect::<Vec<_>>().join("\n")
+}
+
+fn overlay(line: &str) -> String {
+    let mut ret: Vec<char> = Vec::new();
+    for segment in line.split('\r') {
+        for (i, c) in segment.chars().enumerate() {
+            if i < ret.len() {
+                ret[i] = c;
+            } else {
+                ret.push(c);
+            }
+        }
+    }
+    ret.into_iter().collect()
+}
+
+/// The longest prefix of `text` no longer than `max` bytes that ends on a
+/// character boundary.
+pub fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Splits `text` into pieces of about `size` bytes, on character boundaries.
+pub fn chunks(mut text: &str, size: usize) -> Vec<&str> {
+    let mut ret = Vec::new();
+    while !text.is_empty() {
+        let mut chunk = truncate(text, size.max(4));
+        if chunk.is_empty() {
+            chunk = text;
+        }
+        ret.push(chunk);
+        text = &text[chunk.len()..];
+    }
+    ret
+}
+
+/// Starts every line of `text` with `prefix`, wrapping lines longer than
+/// the `columns` left beside it so that wrapped rows get one too. Wrapped
+/// rows fill the screen width, so they aren't separated by newlines.
+pub fn prefix_lines(text: &str, prefix: &str, columns: usize) -> String {
+    let width = columns.saturating_sub(prefix.chars().count()).max(1);
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let chars: Vec<char> = line.chars().collect();
+        let mut wrapped = String::from(prefix);
+        for (i, row) in chars.chunks(width).enumerate() {
+            if i > 0 {
+                wrapped.push_str(prefix);
+            }
+            wrapped.extend(row);
+        }
+        lines.push(wrapped);
+    }
+    lines.join("\n")
+}
+
+/// Cuts lines of `text` longer than `columns` characters, ending them with
+/// an ellipsis.
+pub fn truncate_lines(text: &str, columns: usize) -> String {
+    text.split('\n').map(|line| ellipsize(line, columns)).collect::<Vec<_>>().join("\n")
+}
+
+/// `line`, cut to `columns` characters with an ellipsis if it is longer.
+pub fn ellipsize(line: &str, columns: usize) -> String {
+    if line.chars().count() <= columns {
+        return line.to_owned();
+    }
+    let mut ret: String = line.chars().take(columns.saturating_sub(1)).collect();
+    ret.push('\u{2026}');
+    ret
+}
+
+/// The time an output line starts with, and the space after it. Relative
+/// times count from `start`.
+pub fn timestamp(timestamps: Timestamps, start: Instant) -> String {
+    match timestamps {
+        Timestamps::Absolute => format!("{} ", notify::clock(SystemTime::now())),
+        Timestamps::Relative => format!("+{:.3}s ", start.elapsed().as_secs_f64()),
+    }
+}
+
+/// Wraps lines of `text` longer than `columns` at spaces where possible.
+pub fn wrap_words(text: &str, columns: usize) -> String {
+    let columns = columns.max(1);
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut current = String::new();
+        for (i, word) in line.split(' ').enumerate() {
+            let len = current.chars().count();
+            if i > 0 && len > 0 && len + 1 + word.chars().count() > columns {
+                lines.push(current);
+                current = String::new();
+            } else if i > 0 {
+                current.push(' ');
+            }
+            current.push_str(word);
+            // words longer than a row are broken anywhere
+            while current.chars().count() > columns {
+                let head: String = current.chars().take(columns).collect();
+                current = current.chars().skip(columns).collect();
+                lines.push(head);
+            }
+        }
+        lines.push(current);
+    }
+    lines.join("\n")
+}
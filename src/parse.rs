@@ -0,0 +1,465 @@
+use std::env;
+use std::error::Error;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::iter::Peekable;
+#[cfg(unix)]
+use std::{mem, ptr};
+use std::str::CharIndices;
+
+use super::result::CommandResult;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseError {
+    UnterminatedQuote(char),
+    TrailingBackslash,
+    UnterminatedVariable,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnterminatedQuote(q) => write!(f, "unterminated {} quote", q),
+            ParseError::TrailingBackslash => write!(f, "trailing backslash"),
+            ParseError::UnterminatedVariable => write!(f, "unterminated ${{"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// An argument together with the glob pattern it was typed as: quoted and
+/// escaped characters are escaped in `pattern` so they match literally.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub pattern: Option<String>,
+}
+
+#[derive(Default)]
+struct Builder {
+    text: String,
+    pattern: String,
+    glob: bool,
+}
+
+impl Builder {
+    fn raw(&mut self, c: char) {
+        if c == '*' || c == '?' || c == '[' {
+            self.glob = true;
+        }
+        self.text.push(c);
+        self.pattern.push(c);
+    }
+
+    fn literal(&mut self, c: char) {
+        if c == '*' || c == '?' || c == '[' || c == ']' || c == '\\' {
+            self.pattern.push('\\');
+        }
+        self.text.push(c);
+        self.pattern.push(c);
+    }
+
+    fn literal_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.literal(c);
+        }
+    }
+
+    fn finish(&mut self) -> Word {
+        let builder = ::std::mem::take(self);
+        Word { text: builder.text, pattern: if builder.glob { Some(builder.pattern) } else { None } }
+    }
+}
+
+/// Quotes `arg` so `split` returns it as one argument unchanged.
+pub fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Splits a command line into arguments the way a POSIX shell would:
+/// whitespace separates arguments, `'…'` is taken literally, `"…"` allows
+/// `\"`, `\$` and `\\` escapes, and a bare `\` escapes the next character.
+/// A bare `--` is kept as an argument.
+pub fn split(line: &str) -> Result<Vec<String>, ParseError> {
+    Ok(words(line, false)?.into_iter().map(|w| w.text).collect())
+}
+
+/// Like `split`, but also expands `$VAR`, `${VAR}`, `${VAR:-default}` and a
+/// leading `~` from the environment, and a leading `~user` to the home of
+/// that user where there is one. Single quotes and backslashes suppress the
+/// expansion; unset variables expand to nothing, and `:-` also replaces an
+/// empty value. Nothing after a bare
+/// `--` is expanded, though quoting works there as usual.
+pub fn split_expanded(line: &str) -> Result<Vec<String>, ParseError> {
+    Ok(words(line, true)?.into_iter().map(|w| w.text).collect())
+}
+
+/// Splits the line into `Word`s, keeping the information needed for glob
+/// expansion of unquoted `*`, `?` and `[…]`. Words after a bare `--` are
+/// operands taken literally: neither expanded nor glob patterns.
+pub fn words(line: &str, mut expand: bool) -> Result<Vec<Word>, ParseError> {
+    let mut args = Vec::new();
+    let mut cur = Builder::default();
+    let mut in_arg = false;
+    let mut bare = true;
+    let mut operands = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    if bare && cur.text == "--" {
+                        operands = true;
+                        expand = false;
+                    }
+                    args.push(cur.finish());
+                    in_arg = false;
+                    bare = true;
+                }
+            }
+            '\'' => {
+                in_arg = true;
+                bare = false;
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => cur.literal(c),
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                bare = false;
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) if c == '"' || c == '\\' || c == '$' => cur.literal(c),
+                            Some((_, c)) => {
+                                cur.literal('\\');
+                                cur.literal(c);
+                            }
+                            None => return Err(ParseError::UnterminatedQuote('"')),
+                        },
+                        Some((_, '$')) if expand => cur.literal_str(&variable(&mut chars)?),
+                        Some((_, c)) => cur.literal(c),
+                        None => return Err(ParseError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                bare = false;
+                match chars.next() {
+                    Some((_, c)) => cur.literal(c),
+                    None => return Err(ParseError::TrailingBackslash),
+                }
+            }
+            '$' if expand => {
+                in_arg = true;
+                bare = false;
+                cur.literal_str(&variable(&mut chars)?);
+            }
+            '~' if expand && !in_arg => {
+                in_arg = true;
+                match tilde(&mut chars) {
+                    Some(home) => {
+                        bare = false;
+                        cur.literal_str(&home);
+                    }
+                    None => cur.raw('~'),
+                }
+            }
+            c if operands => {
+                in_arg = true;
+                cur.literal(c);
+            }
+            c => {
+                in_arg = true;
+                cur.raw(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(cur.finish());
+    }
+    Ok(args)
+}
+
+// called after the `$` has been consumed
+fn variable(chars: &mut Peekable<CharIndices>) -> Result<String, ParseError> {
+    if let Some(&(_, '{')) = chars.peek() {
+        chars.next();
+        let mut body = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => body.push(c),
+                None => return Err(ParseError::UnterminatedVariable),
+            }
+        }
+        let mut parts = body.splitn(2, ":-");
+        let name = parts.next().unwrap_or("");
+        let value = env::var(name).unwrap_or_default();
+        return Ok(match parts.next() {
+            Some(default) if value.is_empty() => default.to_owned(),
+            _ => value,
+        });
+    }
+    let mut name = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            name.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    if name.is_empty() {
+        Ok("$".to_owned())
+    } else {
+        Ok(env::var(&name).unwrap_or_default())
+    }
+}
+
+/// Replaces `$name` and `${name}` in `line` with `value`, quoted so that it
+/// stays one word, the way `split` would read it: single-quoted outside
+/// quotes, escaped within double quotes. Single quotes and backslashes
+/// suppress the substitution as they do expansion.
+pub fn substitute(line: &str, name: &str, value: &str) -> String {
+    let mut ret = String::with_capacity(line.len());
+    let mut quote = None;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let len = match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => {
+                quote = None;
+                1
+            }
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                1
+            }
+            (Some('\''), _) => c.len_utf8(),
+            (_, '\\') => 1 + rest[1..].chars().next().map_or(0, |c| c.len_utf8()),
+            (_, '$') => match reference(&rest[1..], name) {
+                Some(len) => {
+                    match quote {
+                        Some(_) => ret.extend(value.chars().flat_map(|c| match c {
+                            '"' | '\\' | '$' => vec!['\\', c],
+                            c => vec![c],
+                        })),
+                        None => ret.push_str(&format!("'{}'", value.replace('\'', "'\\''"))),
+                    }
+                    rest = &rest[1 + len..];
+                    continue;
+                }
+                None => 1,
+            },
+            _ => c.len_utf8(),
+        };
+        ret.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    ret
+}
+
+// the length of `name` or `{name}` at the start of `text`, if it is there
+// as a whole word
+fn reference(text: &str, name: &str) -> Option<usize> {
+    if text.starts_with('{') && text[1..].starts_with(name) && text[1 + name.len()..].starts_with('}') {
+        return Some(name.len() + 2);
+    }
+    let after = text.strip_prefix(name)?;
+    match after.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => None,
+        _ => Some(name.len()),
+    }
+}
+
+// called after a leading `~`: the home directory it stands for, alone or
+// with the user name that follows, which is then consumed; None if there is
+// no such user, the `~` being kept as typed
+fn tilde(chars: &mut Peekable<CharIndices>) -> Option<String> {
+    let mut ahead = chars.clone();
+    let mut user = String::new();
+    while let Some(&(_, c)) = ahead.peek() {
+        match c {
+            c if c == '/' || c.is_whitespace() => break,
+            c if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' => user.push(c),
+            _ => return None,
+        }
+        ahead.next();
+    }
+    let home = if user.is_empty() { home() } else { user_home(&user)? };
+    *chars = ahead;
+    Some(home)
+}
+
+fn home() -> String {
+    env::var("HOME")
+        .or_else(|_| env::var("USERPROFILE"))
+        .unwrap_or_else(|_| "~".to_owned())
+}
+
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut entry = unsafe { mem::zeroed::<libc::passwd>() };
+    let mut buf = vec![0; 16 * 1024];
+    let mut found = ptr::null_mut();
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if status != 0 || found.is_null() || entry.pw_dir.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(entry.pw_dir) }.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<String> {
+    None
+}
+
+/// Adapts an argument-based processor for `Terminal::run`, so the
+/// application receives the parsed arguments instead of the raw line.
+/// Lines that fail to parse are answered with the parse error.
+pub fn args<F, R>(process: F) -> impl Fn(String) -> CommandResult
+    where F: Fn(Vec<String>) -> R,
+          R: Into<CommandResult> {
+    move |line: String| match split(&line) {
+        Ok(args) => process(args).into(),
+        Err(e) => CommandResult::err(format!("parse error: {}", e)),
+    }
+}
+
+/// Like `args`, with environment and tilde expansion applied first.
+pub fn args_expanded<F, R>(process: F) -> impl Fn(String) -> CommandResult
+    where F: Fn(Vec<String>) -> R,
+          R: Into<CommandResult> {
+    move |line: String| match split_expanded(&line) {
+        Ok(args) => process(args).into(),
+        Err(e) => CommandResult::err(format!("parse error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{args, quote, split, split_expanded, substitute, user_home, words, ParseError, Word};
+
+    fn split_ok(line: &str) -> Vec<String> {
+        split(line).unwrap()
+    }
+
+    #[test]
+    fn whitespace_and_nested_quotes() {
+        assert_eq!(split_ok("  a \t b  "), ["a", "b"]);
+        assert_eq!(split_ok(r#""it's" 'say "hi"'"#), ["it's", "say \"hi\""]);
+        assert_eq!(split_ok(r#"a"b c"'d e'f"#), ["ab cd ef"]);
+        assert_eq!(split_ok(r#""a\"b\\c\$d\e" 'a\b'"#), [r#"a"b\c$d\e"#, r"a\b"]);
+        assert_eq!(split_ok(r"a\ b \'c"), ["a b", "'c"]);
+        assert_eq!(split_ok(r#""" ''"#), ["", ""]);
+        assert_eq!(split_ok(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(split(r"echo \"), Err(ParseError::TrailingBackslash));
+        assert_eq!(split("echo 'abc"), Err(ParseError::UnterminatedQuote('\'')));
+        assert_eq!(split(r#"echo "abc"#), Err(ParseError::UnterminatedQuote('"')));
+        assert_eq!(split(r#"echo "abc\"#), Err(ParseError::UnterminatedQuote('"')));
+        assert_eq!(split_expanded("echo ${HOME"), Err(ParseError::UnterminatedVariable));
+    }
+
+    #[test]
+    fn double_dash() {
+        assert_eq!(split_ok("rm -- -f '--'"), ["rm", "--", "-f", "--"]);
+        // only a bare -- starts the operands
+        let words = words("ls '--' *.rs -- *.rs", true).unwrap();
+        let patterns: Vec<_> = words.iter().map(|w| w.pattern.as_deref()).collect();
+        assert_eq!(patterns, [None, None, Some("*.rs"), None, None]);
+        assert_eq!(split_expanded("echo $HOME -- $HOME '$HOME' a\\ b").unwrap()[2..], ["--", "$HOME", "$HOME", "a b"]);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert_eq!(words(r#"*.rs "*".rs \?a [ab]"#, false).unwrap(), [
+            Word { text: "*.rs".to_owned(), pattern: Some("*.rs".to_owned()) },
+            Word { text: "*.rs".to_owned(), pattern: None },
+            Word { text: "?a".to_owned(), pattern: None },
+            Word { text: "[ab]".to_owned(), pattern: Some("[ab]".to_owned()) },
+        ]);
+    }
+
+    #[test]
+    fn quote_round_trip() {
+        for arg in ["plain", "", "two words", "it's", "\"$HOME\"", "a\\b", "--", "tab\there", "é~*"] {
+            assert_eq!(split_ok(&quote(arg)), [arg], "{}", quote(arg));
+        }
+        assert_eq!(quote("a-b_c/d.e"), "a-b_c/d.e");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn processors() {
+        let process = args(|args: Vec<String>| args.join(","));
+        let result = process("a 'b c'".to_owned());
+        assert!(result.success);
+        assert_eq!(result.output, "a,b c");
+        let result = process("a 'b c".to_owned());
+        assert!(!result.success);
+        assert_eq!(result.output, "parse error: unterminated ' quote");
+    }
+
+    #[test]
+    fn variables() {
+        env::set_var("TERM_RS_PARSE_SET", "a b");
+        env::set_var("TERM_RS_PARSE_EMPTY", "");
+        env::remove_var("TERM_RS_PARSE_UNSET");
+        let expanded = |line: &str| split_expanded(line).unwrap();
+        assert_eq!(expanded("$TERM_RS_PARSE_SET ${TERM_RS_PARSE_SET}x"), ["a b", "a bx"]);
+        assert_eq!(expanded("${TERM_RS_PARSE_SET:-d} ${TERM_RS_PARSE_EMPTY:-d} ${TERM_RS_PARSE_UNSET:-d}"),
+                   ["a b", "d", "d"]);
+        assert_eq!(expanded("${TERM_RS_PARSE_UNSET:-two words} ${TERM_RS_PARSE_UNSET:-}"), ["two words", ""]);
+        // empty and unset alike expand to nothing, still within an argument
+        assert_eq!(expanded("x$TERM_RS_PARSE_EMPTY $TERM_RS_PARSE_UNSET \"$TERM_RS_PARSE_UNSET\""), ["x", "", ""]);
+        assert_eq!(expanded(r#""$TERM_RS_PARSE_SET" '$TERM_RS_PARSE_SET' \$TERM_RS_PARSE_SET "\$x""#),
+                   ["a b", "$TERM_RS_PARSE_SET", "$TERM_RS_PARSE_SET", "$x"]);
+        assert_eq!(expanded("$ a$ $-"), ["$", "a$", "$-"]);
+        // a value is taken literally, never as a glob pattern
+        env::set_var("TERM_RS_PARSE_GLOB", "*");
+        assert_eq!(words("$TERM_RS_PARSE_GLOB", true).unwrap()[0].pattern, None);
+        assert_eq!(split("$TERM_RS_PARSE_SET").unwrap(), ["$TERM_RS_PARSE_SET"]);
+    }
+
+    #[test]
+    fn tilde() {
+        let home = env::var("HOME").unwrap();
+        let expanded = |line: &str| split_expanded(line).unwrap();
+        assert_eq!(expanded("~ ~/a"), [home.clone(), format!("{}/a", home)]);
+        assert_eq!(expanded("a~ '~' \\~ \"~\" ~+ a=~"), ["a~", "~", "~", "~", "~+", "a=~"]);
+        assert_eq!(expanded("~term-rs-no-such-user/a"), ["~term-rs-no-such-user/a"]);
+        assert_eq!(words("~*", true).unwrap()[0].pattern.as_deref(), Some("~*"));
+        if let Some(root) = user_home("root") {
+            assert_eq!(expanded("~root ~root/a"), [root.clone(), format!("{}/a", root)]);
+        }
+        assert_eq!(split("~").unwrap(), ["~"]);
+    }
+
+    #[test]
+    fn substitution() {
+        assert_eq!(substitute("echo $x ${x}y $xy", "x", "it's"), r"echo 'it'\''s' 'it'\''s'y $xy");
+        assert_eq!(substitute(r#"echo "$x" '$x' \$x"#, "x", r#"a"$b"#), r#"echo "a\"\$b" '$x' \$x"#);
+        let line = substitute("echo $x \"($x)\"", "x", "a 'b\" c");
+        assert_eq!(split(&line).unwrap(), ["echo", "a 'b\" c", "(a 'b\" c)"]);
+    }
+}
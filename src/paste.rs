@@ -0,0 +1,59 @@
+// Text pasted into the editor, which terminals in bracketed paste mode send
+// between `ESC [200~` and `ESC [201~` instead of as typed keys, so that an
+// escape sequence or control character in it can't act as a key binding or
+// reach the screen.
+
+use super::output;
+
+/// The CSI parameter starting a paste.
+pub const START: &str = "200";
+/// The sequence ending a paste.
+pub const END: &str = "\u{1b}[201~";
+
+/// Makes pasted text safe to insert: line endings become `\n` and tabs
+/// spaces up to the next multiple of `tab_width`, while other control
+/// characters, ESC included, are dropped or, with `escape`, shown in caret
+/// notation as `cat -v` does, e.g. `^[` for ESC.
+pub fn sanitize(text: &str, tab_width: usize, escape: bool) -> String {
+    let text = output::normalize_line_endings(text, false);
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c as u32 {
+            _ if c == '\n' || c == '\t' || !c.is_control() => ret.push(c),
+            _ if !escape => {}
+            code @ 0x80..=0x9f => {
+                ret.push_str("M-^");
+                ret.push((code - 0x40) as u8 as char);
+            }
+            0x7f => ret.push_str("^?"),
+            code => {
+                ret.push('^');
+                ret.push((code as u8 | 0x40) as char);
+            }
+        }
+    }
+    output::expand_tabs(&ret, tab_width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize;
+
+    #[test]
+    fn keeps_percent_signs_and_drops_nul() {
+        assert_eq!(sanitize("printf %s%n\0x", 8, false), "printf %s%nx");
+        assert_eq!(sanitize("printf %s%n\0x", 8, true), "printf %s%n^@x");
+    }
+
+    #[test]
+    fn escapes_controls() {
+        assert_eq!(sanitize("a\u{1b}[31mb\r\nc\u{7f}\u{9b}", 8, false), "a[31mb\nc");
+        assert_eq!(sanitize("a\u{1b}[31mb\r\nc\u{7f}\u{9b}", 8, true), "a^[[31mb\nc^?M-^[");
+    }
+
+    #[test]
+    fn expands_tabs_after_escaping() {
+        assert_eq!(sanitize("\u{1}\tx", 4, true), "^A  x");
+        assert_eq!(sanitize("\u{1}\tx", 4, false), "    x");
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Splits `command | program` at the first unquoted `|`.
+pub fn split(line: &str) -> (&str, Option<&str>) {
+    match find_unquoted(line, "|") {
+        Some(i) => (&line[..i], Some(line[i + 1..].trim())),
+        None => (line, None),
+    }
+}
+
+/// Byte offset of the first `target` outside quotes and not escaped. As in
+/// the shell, a backslash escapes nothing within single quotes.
+pub fn find_unquoted(line: &str, target: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\\') => {}
+            (_, '\\') => { escaped = true; }
+            (None, '\'') | (None, '"') => { quote = Some(c); }
+            (Some(q), c) if q == c => { quote = None; }
+            (None, _) if line[i..].starts_with(target) => { return Some(i); }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Feeds `input` to `program` through the shell and returns what it printed.
+pub fn filter(input: &str, program: &str) -> Result<String, String> {
+    if program.is_empty() {
+        return Err("pipe: missing program".to_owned());
+    }
+    let mut child = shell(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("pipe: {}: {}", program, e))?;
+
+    // write on a separate thread so a program that fills its stdout pipe
+    // before draining stdin can't deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let data = input.to_owned();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(data.as_bytes());
+    });
+    let output = child.wait_with_output().map_err(|e| format!("pipe: {}: {}", program, e))?;
+    let _ = writer.join();
+
+    let mut ret = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() && !output.stderr.is_empty() {
+        ret.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+    if ret.ends_with('\n') {
+        ret.pop();
+    }
+    Ok(ret)
+}
+
+#[cfg(windows)]
+fn shell(program: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(program);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell(program: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(program);
+    command
+}
@@ -0,0 +1,525 @@
+use std::env;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::mem;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use super::audit::{AuditEntry, Outcome};
+use super::auth::{self, Authenticator};
+use super::builder::{ErrorPolicy, TerminalBuilder, Verbosity};
+use super::chain::{self, Op};
+use super::command::CommandHistory;
+use super::limits::{self, Limiter};
+use super::markdown;
+use super::normalize;
+use super::notify;
+use super::output;
+use super::parse;
+use super::pipe;
+use super::prompt::PromptContext;
+use super::redirect;
+use super::result::{CommandResult, Format};
+use super::workdir;
+
+// set to anything but 0 to run terminals in plain mode
+const PLAIN_ENV: &str = "TERM_RS_PLAIN";
+
+// the width Markdown rules are drawn to, the terminal's being unknown
+const MARKDOWN_WIDTH: usize = 80;
+
+pub fn requested() -> bool {
+    env::var(PLAIN_ENV).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+// whether curses can't drive the terminal: input or output isn't one, as
+// in IDE run panes and CI logs, or TERM names none it knows, as in Emacs
+// shell buffers
+#[cfg(unix)]
+pub fn needed() -> bool {
+    let tty = unsafe { ::libc::isatty(0) == 1 && ::libc::isatty(1) == 1 };
+    match env::var("TERM") {
+        Ok(ref term) if tty && !term.is_empty() && term != "dumb" => !has_terminfo(term),
+        _ => true,
+    }
+}
+
+// PDCurses doesn't depend on the terminal
+#[cfg(not(unix))]
+pub fn needed() -> bool {
+    false
+}
+
+// looks for the terminfo entry of `term` where ncurses does
+#[cfg(unix)]
+fn has_terminfo(term: &str) -> bool {
+    use std::path::PathBuf;
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(dir) = env::var_os("TERMINFO") {
+        dirs.push(dir.into());
+    }
+    if let Some(home) = env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Some(list) = env::var_os("TERMINFO_DIRS") {
+        dirs.extend(env::split_paths(&list).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo",
+                 "/usr/local/share/terminfo"].iter().map(PathBuf::from));
+    let first = match term.chars().next() {
+        Some(first) => first,
+        None => return false,
+    };
+    // entries are filed by first letter, or its hex code on macOS
+    dirs.iter().any(|dir| {
+        dir.join(first.to_string()).join(term).exists() || dir.join(format!("{:x}", first as u32)).join(term).exists()
+    })
+}
+
+// The command loop without curses: the prompt is printed once per line,
+// input is read a line at a time with the terminal's own echo and editing,
+// and output is only ever appended, never redrawn. Editing features and
+// the built-ins that depend on them are not available.
+pub fn run<F, R>(builder: TerminalBuilder, process: F)
+    where F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let user = match builder.authenticator {
+        Some(ref authenticator) => match login(&builder, authenticator.as_ref(), &mut input) {
+            Some(user) => Some(user),
+            None => return,
+        },
+        None => None,
+    };
+    let mut out = io::stdout();
+    let mut err = io::stderr();
+    let _ = Session::start(&builder, user, None, None, &mut out, &mut err)
+        .and_then(|mut session| session.read_lines(&builder, &process, input, &mut out, &mut err));
+}
+
+// prints the output as it comes until every sender is dropped
+pub fn view(builder: TerminalBuilder, output: Receiver<String>) {
+    let mut out = io::stdout();
+    if let Some(ref banner) = builder.banner {
+        let _ = writeln!(out, "{}", banner().trim_end_matches('\n'));
+    }
+    let start = Instant::now();
+    for text in output {
+        let _ = writeln!(out, "{}", format(&builder, &text, false, start)).and_then(|_| out.flush());
+    }
+}
+
+// asks for the secret with the terminal's echo turned off
+fn login<I: BufRead>(builder: &TerminalBuilder, authenticator: &dyn Authenticator, input: &mut I) -> Option<String> {
+    for _ in 0..builder.login_attempts {
+        print!("{}", authenticator.prompt());
+        let _ = io::stdout().flush();
+        let echo = set_echo(false);
+        let mut secret = String::new();
+        let read = input.read_line(&mut secret);
+        if echo {
+            set_echo(true);
+        }
+        println!();
+        match read {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        if let Some(user) = authenticator.authenticate(secret.trim_end_matches(['\n', '\r'])) {
+            return Some(user);
+        }
+        eprintln!("{}", builder.messages.login_failed());
+    }
+    eprintln!("{}", builder.messages.too_many_attempts());
+    None
+}
+
+// asks the controlling terminal, input being the batch itself, whether to
+// go on; no if there is none
+#[cfg(unix)]
+fn confirm(question: &str, err: &mut dyn Write) -> bool {
+    let tty = match fs::File::open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => return false,
+    };
+    if write!(err, "{}", question).and_then(|_| err.flush()).is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    io::BufReader::new(tty).read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+#[cfg(not(unix))]
+fn confirm(_question: &str, _err: &mut dyn Write) -> bool {
+    false
+}
+
+// turns the echo of the terminal on stdin on or off, returning whether it
+// was on
+#[cfg(unix)]
+fn set_echo(echo: bool) -> bool {
+    unsafe {
+        let mut termios = mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return false;
+        }
+        let was_on = termios.c_lflag & libc::ECHO != 0;
+        if echo {
+            termios.c_lflag |= libc::ECHO;
+        } else {
+            termios.c_lflag &= !libc::ECHO;
+        }
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+        was_on
+    }
+}
+
+#[cfg(not(unix))]
+fn set_echo(_echo: bool) -> bool {
+    false
+}
+
+/// Runs the plain command loop on `input` until it ends, writing output to
+/// `out` and errors to `err`.
+pub fn session<F, R, I>(builder: &TerminalBuilder, process: &F, input: I, out: &mut dyn Write, err: &mut dyn Write)
+    -> io::Result<()>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult>,
+          I: BufRead {
+    Session::start(builder, None, None, None, out, err)?.read_lines(builder, process, input, out, err)
+}
+
+/// The state of one plain session between lines.
+pub struct Session {
+    history: CommandHistory,
+    status: PromptContext,
+    limiter: Limiter,
+    // the working directory, if the session keeps one
+    cwd: Option<PathBuf>,
+}
+
+impl Session {
+    /// Loads the history and shows the banner for `user`, who has logged in
+    /// if there is an authenticator, in remote session `session` of `peer`.
+    pub fn start(builder: &TerminalBuilder, user: Option<String>, session: Option<usize>, peer: Option<&str>,
+                 out: &mut dyn Write, err: &mut dyn Write) -> io::Result<Self> {
+        for e in &builder.config_errors {
+            writeln!(err, "{}", builder.messages.error("config", e))?;
+        }
+        let mut history = CommandHistory::default();
+        if let Some(ref path) = builder.history_file {
+            match history.load(path, builder.history_cipher.as_deref()) {
+                Ok(0) => {}
+                Ok(skipped) => writeln!(err, "{}", builder.messages.error("history", &builder.messages.history_lines_skipped(skipped)))?,
+                Err(e) => writeln!(err, "{}", builder.messages.error("history", &e.to_string()))?,
+            }
+        }
+        if let Some(ref banner) = builder.banner {
+            writeln!(out, "{}", banner().trim_end_matches('\n'))?;
+        }
+        let limiter = Limiter::new(builder.rate_limit.clone(), limits::key(user.as_deref(), peer, session));
+        let status = PromptContext { last_success: true, commands: 0, user, session };
+        for plugin in &builder.plugins {
+            plugin.on_start(&status);
+        }
+        let cwd = if builder.working_directory { Some(env::current_dir().unwrap_or_default()) } else { None };
+        Ok(Session { history, status, limiter, cwd })
+    }
+
+    fn read_lines<F, R, I>(&mut self, builder: &TerminalBuilder, process: &F, mut input: I, out: &mut dyn Write,
+                           err: &mut dyn Write) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult>,
+              I: BufRead {
+        let mut number = 0;
+        loop {
+            for notification in builder.notifier.iter().flat_map(|notifier| notifier.take()) {
+                writeln!(out, "{}", builder.messages.notification(notification.level, &notify::clock(notification.time),
+                                                                  &notification.message))?;
+            }
+            write!(out, "{}", self.prompt(builder))?;
+            out.flush()?;
+            let mut line = Vec::new();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                self.end(builder);
+                return Ok(());
+            }
+            number += 1;
+            let line = String::from_utf8_lossy(&line);
+            let commands = self.status.commands;
+            self.run_line(builder, process, &line, out, err)?;
+            if self.status.commands == commands || self.status.last_success || builder.on_error == ErrorPolicy::Continue {
+                continue;
+            }
+            writeln!(err, "{}", builder.messages.line_failed(number, line.trim_end_matches(['\n', '\r'])))?;
+            if builder.on_error == ErrorPolicy::Stop || !confirm(&builder.messages.continue_after_error(), err) {
+                self.end(builder);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Tells the plugins the session is over.
+    pub fn end(&self, builder: &TerminalBuilder) {
+        for plugin in &builder.plugins {
+            plugin.on_exit(&self.status);
+        }
+    }
+
+    /// The user logged in to the session, if there is an authenticator.
+    pub fn user(&self) -> Option<&str> {
+        self.status.user.as_deref()
+    }
+
+    pub fn prompt(&self, builder: &TerminalBuilder) -> String {
+        match builder.prompt_fn {
+            Some(ref prompt) => prompt(&self.status),
+            None => builder.prompt.clone(),
+        }
+    }
+
+    /// Records `line` in the history and runs it.
+    pub fn run_line<F, R>(&mut self, builder: &TerminalBuilder, process: &F, line: &str, out: &mut dyn Write,
+                          err: &mut dyn Write) -> io::Result<()>
+        where F: Fn(String) -> R,
+              R: Into<CommandResult> {
+        let line = normalize::nfc(line.trim_end_matches(['\n', '\r']));
+        let line = line.as_ref();
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        if let Some(max) = builder.max_input.filter(|&max| line.chars().count() > max) {
+            return writeln!(err, "{}", builder.messages.input_too_long(max));
+        }
+        if builder.dry_run {
+            for (_, command) in chain::split(line) {
+                if !command.trim().is_empty() {
+                    writeln!(out, "{}", builder.messages.dry_run(&expanded(command.trim())))?;
+                }
+            }
+            return Ok(());
+        }
+        let excluded = builder.history_exclude.as_ref().is_some_and(|exclude| exclude(line));
+        if !excluded {
+            self.history.add_command(line.to_owned());
+        }
+        for plugin in &builder.plugins {
+            plugin.on_submit(line);
+        }
+        let line_start = Instant::now();
+        let mut success = true;
+        for (op, command) in chain::split(line) {
+            if op == Op::IfSuccess && !success {
+                continue;
+            }
+            if !self.admit(builder, command, err)? {
+                success = false;
+                continue;
+            }
+            let start = Instant::now();
+            success = match self.directory_builtin(builder, command) {
+                Some(result) => {
+                    match result.output.as_str() {
+                        "" => {}
+                        output if result.success => writeln!(out, "{}", format(builder, output, false, Instant::now()))?,
+                        output => writeln!(err, "{}", format(builder, output, true, Instant::now()))?,
+                    }
+                    result.success
+                }
+                None => {
+                    let (user, cwd) = (self.status.user.as_deref(), self.cwd.as_deref());
+                    workdir::in_dir(cwd, || auth::as_user(user, || run_command(builder, process, command, start, out, err)))?
+                }
+            };
+            let outcome = if success { Outcome::Success } else { Outcome::Failure };
+            self.audit(builder, command, outcome, start.elapsed(), err)?;
+            self.limiter.record(command, success);
+            builder.stats.record(command, success, start.elapsed());
+            if builder.verbosity == Verbosity::Verbose && !command.trim().is_empty() {
+                writeln!(out, "{}", builder.messages.command_status(command.trim(), success, start.elapsed()))?;
+            }
+        }
+        if !excluded {
+            self.history.set_duration(line_start.elapsed());
+            if let Some(ref path) = builder.history_file {
+                if let Err(e) = self.history.save(path, builder.history_cipher.as_deref()) {
+                    writeln!(err, "{}", builder.messages.error("history", &e.to_string()))?;
+                }
+            }
+        }
+        self.status.last_success = success;
+        self.status.commands += 1;
+        if let Some(ref hook) = builder.after_command {
+            hook(line, success);
+        }
+        Ok(())
+    }
+
+    // `cd [dir]` and `pwd`, for sessions that keep a working directory
+    fn directory_builtin(&mut self, builder: &TerminalBuilder, command: &str) -> Option<CommandResult> {
+        let cwd = self.cwd.clone()?;
+        let argv = parse::split(command).unwrap_or_default();
+        let argv: Vec<&str> = argv.iter().map(|s| s.as_str()).collect();
+        let target = match argv.as_slice() {
+            ["pwd"] => return Some(CommandResult::ok(cwd.display().to_string())),
+            ["cd"] => None,
+            ["cd", dir] => Some(*dir),
+            ["cd", ..] => return Some(CommandResult::err(builder.messages.usage("cd"))),
+            _ => return None,
+        };
+        Some(match workdir::change(&cwd, target) {
+            Ok(dir) => {
+                self.cwd = Some(dir);
+                CommandResult::ok("")
+            }
+            Err(e) => CommandResult::err(builder.messages.error(&format!("cd: {}", target.unwrap_or("~")), &e.to_string())),
+        })
+    }
+
+    /// Counts `command` against the rate limit, or refuses it, showing why
+    /// on `err`. Returns whether it may run.
+    pub fn admit(&mut self, builder: &TerminalBuilder, command: &str, err: &mut dyn Write) -> io::Result<bool> {
+        match self.limiter.check(command, builder.messages.as_ref()) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                writeln!(err, "{}", format(builder, &e, true, Instant::now()))?;
+                self.audit(builder, command, Outcome::Refused, Duration::default(), err)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Records `command` with the audit sink, if any, showing on `err` why
+    /// it couldn't.
+    pub fn audit(&self, builder: &TerminalBuilder, command: &str, outcome: Outcome, elapsed: Duration,
+             err: &mut dyn Write) -> io::Result<()> {
+        let sink = match builder.audit {
+            Some(ref sink) if !command.trim().is_empty() => sink,
+            _ => return Ok(()),
+        };
+        if let Err(e) = sink.record(&AuditEntry::new(&self.status, command, outcome, elapsed)) {
+            writeln!(err, "{}", builder.messages.error("audit", &e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+// `command` with its variables and `~` expanded as `parse::split_expanded`
+// does, and its words quoted again where needed; a pipe or redirection
+// after them is kept as written, and a command that doesn't parse as is
+fn expanded(command: &str) -> String {
+    let (stage, _) = pipe::split(command);
+    let (words, _) = redirect::split(stage);
+    let words = words.trim_end();
+    let rest = &command[words.len()..];
+    match parse::split_expanded(words) {
+        Ok(args) => args.iter().map(|arg| parse::quote(arg)).collect::<Vec<_>>().join(" ") + rest,
+        Err(_) => command.to_owned(),
+    }
+}
+
+fn run_command<F, R>(builder: &TerminalBuilder, process: &F, line: &str, start: Instant, out: &mut dyn Write,
+                     err: &mut dyn Write) -> io::Result<bool>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    // each stage of a pipe has its own redirection: the command's is ours,
+    // leaving the program no input, and the program's the shell's
+    let (stage, program) = pipe::split(line);
+    let (command, target) = redirect::split(stage);
+    let command = if program.is_some() { command.trim_end() } else { command };
+    let result: CommandResult = process(command.to_owned()).into();
+    let mut result = result.resolve();
+    let written = target.map(|target| redirect::write(target, &result.output));
+    if let Some(program) = program {
+        let input = if written.is_some() { "" } else { result.output.as_str() };
+        result = match pipe::filter(input, program) {
+            Ok(output) => CommandResult { output, ..result },
+            Err(e) => CommandResult::err(e),
+        };
+    }
+    for plugin in &builder.plugins {
+        plugin.on_output(command, &result);
+    }
+    if !result.error.is_empty() {
+        writeln!(err, "{}", format(builder, result.error.trim_end_matches('\n'), true, start))?;
+    }
+    let success = match written {
+        Some(Err(e)) => {
+            writeln!(err, "{}", e)?;
+            false
+        }
+        Some(Ok(())) if program.is_none() => result.success,
+        _ if result.output.is_empty() && (!result.error.is_empty() || builder.verbosity == Verbosity::Quiet) => {
+            result.success
+        }
+        _ if result.success => {
+            writeln!(out, "{}", format(builder, &shown(&result), false, start))?;
+            true
+        }
+        _ => {
+            writeln!(err, "{}", format(builder, &shown(&result), true, start))?;
+            false
+        }
+    };
+    Ok(success)
+}
+
+// the output of `result` as text
+fn shown(result: &CommandResult) -> String {
+    match result.format {
+        Format::Plain => result.output.clone(),
+        Format::Markdown => markdown::text(&markdown::render(&result.output, MARKDOWN_WIDTH)),
+        Format::Diff => result.output.clone(),
+    }
+}
+
+// the output pipeline of the curses terminal, minus anything that moves
+// the cursor: carriage returns always start a new line. Relative
+// timestamps count from `start`.
+fn format(builder: &TerminalBuilder, text: &str, error: bool, start: Instant) -> String {
+    let kept = match builder.max_output {
+        Some(max) => output::truncate(text, max),
+        None => text,
+    };
+    let mut shown = output::normalize_line_endings(kept, false);
+    if builder.expand_tabs || builder.tab_width != 8 {
+        shown = output::expand_tabs(&shown, builder.tab_width);
+    }
+    let mut prefix = if error { format!("{}{}", builder.output_prefix, builder.error_prefix) } else { builder.output_prefix.clone() };
+    if let Some(timestamps) = builder.timestamps {
+        prefix.insert_str(builder.output_prefix.len(), &output::timestamp(timestamps, start));
+    }
+    if !prefix.is_empty() {
+        shown = shown.split('\n').map(|line| format!("{}{}", prefix, line)).collect::<Vec<_>>().join("\n");
+    }
+    if kept.len() < text.len() {
+        shown.push('\n');
+        shown.push_str(&builder.messages.output_truncated(kept.len(), text.len()));
+    }
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::expanded;
+
+    #[test]
+    fn dry_run_expands_variables() {
+        env::set_var("TERM_RS_DRY_RUN_TEST", "two words");
+        env::remove_var("TERM_RS_DRY_RUN_UNSET");
+        // a variable expands to one argument, as in `parse`
+        assert_eq!(expanded("echo $TERM_RS_DRY_RUN_TEST"), "echo 'two words'");
+        assert_eq!(expanded("echo \"$TERM_RS_DRY_RUN_TEST\" '$HOME'"), "echo 'two words' '$HOME'");
+        assert_eq!(expanded("echo ${TERM_RS_DRY_RUN_UNSET:-x} -- $HOME"), "echo x -- '$HOME'");
+        assert_eq!(expanded("echo $TERM_RS_DRY_RUN_TEST > out.txt"), "echo 'two words' > out.txt");
+        assert_eq!(expanded("ls ~/a | grep $x"), format!("ls {}/a | grep $x", env::var("HOME").unwrap()));
+        assert_eq!(expanded("echo 'unterminated"), "echo 'unterminated");
+    }
+}
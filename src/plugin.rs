@@ -0,0 +1,44 @@
+use super::editor::EditorState;
+use super::prompt::PromptContext;
+use super::result::CommandResult;
+
+/// A feature hooked into every session, such as telemetry or a widget
+/// drawn from key presses, see `TerminalBuilder::plugin`. Each method does
+/// nothing by default.
+///
+/// ```
+/// use std::cell::Cell;
+/// use term_rs::{CommandResult, Plugin};
+///
+/// // counts the commands that failed
+/// #[derive(Default)]
+/// struct Failures(Cell<usize>);
+///
+/// impl Plugin for Failures {
+///     fn on_output(&self, _command: &str, result: &CommandResult) {
+///         if !result.success {
+///             self.0.set(self.0.get() + 1);
+///         }
+///     }
+/// }
+/// ```
+pub trait Plugin {
+    /// A session starts, after the banner.
+    fn on_start(&self, _status: &PromptContext) {}
+
+    /// The line editor handled `key`, described as in key bindings, e.g.
+    /// `"Ctrl+X"`, leaving the line in `state`. Plain and remote sessions
+    /// read whole lines and don't call it.
+    fn on_key(&self, _key: &str, _state: &EditorState) {}
+
+    /// A non-empty command line is about to run.
+    fn on_submit(&self, _line: &str) {}
+
+    /// `command`, one of those chained in a line, produced `result`.
+    fn on_output(&self, _command: &str, _result: &CommandResult) {}
+
+    /// A session ends: its input ended, or it was detached and then
+    /// dropped. The curses terminal runs until the process exits and
+    /// doesn't call it.
+    fn on_exit(&self, _status: &PromptContext) {}
+}
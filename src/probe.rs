@@ -0,0 +1,31 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use super::stats::{self, SessionStats};
+
+/// Lines at least this many bytes long count as `long-line`.
+pub const LONG_LINE: usize = 256;
+
+/// How long the input loop took from reading a key to drawing the line
+/// again, by what the key did: `history` when it recalled a history entry,
+/// `paste` when it was read as soon as the previous key was drawn, as with
+/// pasted text, `long-line` when the line is `LONG_LINE` bytes or more,
+/// and `key` otherwise. Only recorded with the `latency-probe` feature;
+/// the `stats` built-in shows them under the command counts.
+pub fn latency() -> SessionStats {
+    static LATENCY: OnceLock<SessionStats> = OnceLock::new();
+    LATENCY.get_or_init(SessionStats::default).clone()
+}
+
+pub(crate) fn record(kind: &str, elapsed: Duration) {
+    latency().record(kind, true, elapsed);
+}
+
+// the table `stats` adds, if anything was recorded
+pub(crate) fn summary() -> Option<String> {
+    let latency = latency().latency();
+    if latency.is_empty() {
+        return None;
+    }
+    Some(stats::table("input", &latency).join("\n"))
+}
@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+
+/// What a dynamic prompt can show about the session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PromptContext {
+    /// Whether the last command line succeeded; true before the first.
+    pub last_success: bool,
+    /// Command lines run so far.
+    pub commands: usize,
+    /// Who logged in, when there is an `Authenticator`.
+    pub user: Option<String>,
+    /// The id of the remote session, `None` locally.
+    pub session: Option<usize>,
+}
+
+pub type PromptFn = Box<dyn Fn(&PromptContext) -> String>;
+
+pub type CommandHook = Box<dyn Fn(&str, bool)>;
+
+/// A part of the prompt, such as a mode or a connection status, rendered
+/// again only when stale, see `PromptSegments`.
+pub trait PromptSegment {
+    fn render(&self, context: &PromptContext) -> String;
+
+    /// Whether the text rendered for `rendered_for` is out of date for
+    /// `context`, besides `PromptSegments::invalidate`. By default it is
+    /// whenever the context changed; segments that don't show the context
+    /// return false and are invalidated by the application.
+    fn is_stale(&self, rendered_for: &PromptContext, context: &PromptContext) -> bool {
+        rendered_for != context
+    }
+}
+
+impl<F> PromptSegment for F
+    where F: Fn(&PromptContext) -> String {
+    fn render(&self, context: &PromptContext) -> String {
+        self(context)
+    }
+}
+
+struct Segment {
+    name: String,
+    segment: Box<dyn PromptSegment + Send>,
+    // the last rendering and the context it was for, `None` once invalidated
+    cached: Option<(PromptContext, String)>,
+}
+
+#[derive(Default)]
+struct Segments {
+    segments: Vec<Segment>,
+    separator: String,
+}
+
+/// A prompt composed of named segments, each rendered again only when it
+/// is stale, see `TerminalBuilder::prompt_segments`.
+///
+/// Clones share the segments, so an application can keep one to invalidate
+/// a segment, from any thread, when what it shows changes.
+///
+/// ```no_run
+/// use term_rs::{PromptContext, PromptSegments, Terminal};
+///
+/// let segments = PromptSegments::new()
+///     .push("status", |context: &PromptContext| if context.last_success { "ok" } else { "failed" }.to_owned())
+///     .push("prompt", |_: &PromptContext| "> ".to_owned())
+///     .separator(" ");
+/// Terminal::builder().prompt_segments(segments.clone()).run(|command| command);
+/// ```
+#[derive(Clone, Default)]
+pub struct PromptSegments {
+    segments: Arc<Mutex<Segments>>,
+}
+
+impl PromptSegments {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Appends `segment`, replacing any segment of the same name.
+    pub fn push<S>(self, name: &str, segment: S) -> Self
+        where S: PromptSegment + Send + 'static {
+        {
+            let mut segments = self.segments.lock().unwrap();
+            let segment = Segment { name: name.to_owned(), segment: Box::new(segment), cached: None };
+            match segments.segments.iter().position(|s| s.name == name) {
+                Some(i) => segments.segments[i] = segment,
+                None => segments.segments.push(segment),
+            }
+        }
+        self
+    }
+
+    /// Put between segments; empty segments are left out.
+    pub fn separator(self, separator: &str) -> Self {
+        self.segments.lock().unwrap().separator = separator.to_owned();
+        self
+    }
+
+    /// Renders segment `name` again for the next prompt.
+    pub fn invalidate(&self, name: &str) {
+        for segment in self.segments.lock().unwrap().segments.iter_mut().filter(|s| s.name == name) {
+            segment.cached = None;
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        for segment in self.segments.lock().unwrap().segments.iter_mut() {
+            segment.cached = None;
+        }
+    }
+
+    /// The prompt for `context`, rendering only the stale segments.
+    pub fn render(&self, context: &PromptContext) -> String {
+        let mut segments = self.segments.lock().unwrap();
+        let mut parts = Vec::new();
+        for segment in segments.segments.iter_mut() {
+            let fresh = match segment.cached {
+                Some((ref rendered_for, _)) => !segment.segment.is_stale(rendered_for, context),
+                None => false,
+            };
+            if !fresh {
+                segment.cached = Some((context.clone(), segment.segment.render(context)));
+            }
+            if let Some((_, ref text)) = segment.cached {
+                if !text.is_empty() {
+                    parts.push(text.clone());
+                }
+            }
+        }
+        parts.join(&segments.separator)
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use super::pipe::find_unquoted;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Redirect<'a> {
+    Write(&'a str),
+    Append(&'a str),
+}
+
+/// Strips a trailing `> file` or `>> file` from the line.
+pub fn split(line: &str) -> (&str, Option<Redirect<'_>>) {
+    match find_unquoted(line, ">") {
+        Some(i) => {
+            let rest = &line[i + 1..];
+            let redirect = match rest.strip_prefix('>') {
+                Some(path) => Redirect::Append(unquote(path.trim())),
+                None => Redirect::Write(unquote(rest.trim())),
+            };
+            (line[..i].trim_end(), Some(redirect))
+        }
+        None => (line, None),
+    }
+}
+
+fn unquote(path: &str) -> &str {
+    let quoted = path.len() >= 2
+        && ((path.starts_with('"') && path.ends_with('"')) || (path.starts_with('\'') && path.ends_with('\'')));
+    if quoted { &path[1..path.len() - 1] } else { path }
+}
+
+/// Writes `output` to the redirect target, one trailing newline included.
+pub fn write(redirect: Redirect, output: &str) -> Result<(), String> {
+    let (path, append) = match redirect {
+        Redirect::Write(path) => (path, false),
+        Redirect::Append(path) => (path, true),
+    };
+    if path.is_empty() {
+        return Err("redirect: missing file name".to_owned());
+    }
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", output))
+        .map_err(|e| format!("redirect: {}: {}", path, e))
+}
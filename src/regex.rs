@@ -0,0 +1,364 @@
+/// A regular expression: literals, `.`, `[a-z]` and `[^a-z]` classes,
+/// `\d`, `\w`, `\s` and their negations, `*`, `+` and `?`, `^` and `$`
+/// anchors, `(...)` groups and `|` alternatives. Matching runs every way
+/// through the pattern at once, so it takes time proportional to the length
+/// of the pattern times that of the text, whatever the pattern.
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+// groups nest at most this deep, keeping compiling them off the end of the
+// stack
+const MAX_NESTING: usize = 100;
+
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<Member>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+// a step of the compiled pattern, as an automaton whose states are the
+// indices of the steps
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<Member>, bool),
+    Start,
+    End,
+    // goes on at both
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// a member of a bracket expression
+enum Member {
+    Char(char),
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl Regex {
+    /// Compiles `pattern`, matching regardless of case if `ignore_case`.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pos = 0;
+        let alternatives = alternatives(&chars, &mut pos, 0)?;
+        if pos < chars.len() {
+            return Err("unmatched )".to_owned());
+        }
+        let mut program = Vec::new();
+        compile_alternatives(alternatives, &mut program);
+        program.push(Inst::Match);
+        Ok(Regex { program, ignore_case })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        // the states reached before each character, and when each state
+        // was last added, to add it once per position
+        let (mut current, mut next) = (Vec::new(), Vec::new());
+        let mut added = vec![usize::MAX; self.program.len()];
+        for i in 0..=text.len() {
+            // a match may start at any position
+            if self.add(&mut current, &mut added, 0, i, text.len()) {
+                return true;
+            }
+            let c = match text.get(i) {
+                Some(&c) => c,
+                None => break,
+            };
+            for &state in &current {
+                let matched = match self.program[state] {
+                    Inst::Char(expected) => self.same(c, expected),
+                    Inst::Any => true,
+                    Inst::Class(ref members, negate) => members.iter().any(|member| self.member(member, c)) != negate,
+                    _ => false,
+                };
+                if matched && self.add(&mut next, &mut added, state + 1, i + 1, text.len()) {
+                    return true;
+                }
+            }
+            current.clear();
+            ::std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    // adds `state` and those reached from it without reading a character
+    // at position `i` to `states`, returning whether that ends a match
+    fn add(&self, states: &mut Vec<usize>, added: &mut [usize], state: usize, i: usize, len: usize) -> bool {
+        let mut pending = vec![state];
+        while let Some(state) = pending.pop() {
+            if added[state] == i {
+                continue;
+            }
+            added[state] = i;
+            match self.program[state] {
+                Inst::Split(a, b) => pending.extend([b, a]),
+                Inst::Jump(to) => pending.push(to),
+                Inst::Start if i == 0 => pending.push(state + 1),
+                Inst::End if i == len => pending.push(state + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Match => return true,
+                _ => states.push(state),
+            }
+        }
+        false
+    }
+
+    fn member(&self, member: &Member, c: char) -> bool {
+        match *member {
+            Member::Char(expected) => self.same(c, expected),
+            Member::Range(from, to) => {
+                let within = |c: char| from <= c && c <= to;
+                within(c) || self.ignore_case && (c.to_lowercase().any(within) || c.to_uppercase().any(within))
+            }
+            Member::Digit(is) => c.is_ascii_digit() == is,
+            Member::Word(is) => (c.is_alphanumeric() || c == '_') == is,
+            Member::Space(is) => c.is_whitespace() == is,
+        }
+    }
+
+    fn same(&self, c: char, expected: char) -> bool {
+        c == expected || self.ignore_case && c.to_lowercase().eq(expected.to_lowercase())
+    }
+}
+
+// `a|b|c` up to a closing `)` or the end of the pattern, within `depth`
+// groups
+fn alternatives(chars: &[char], pos: &mut usize, depth: usize) -> Result<Vec<Vec<Node>>, String> {
+    let mut ret = vec![Vec::new()];
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        *pos += 1;
+        let node = match c {
+            ')' => {
+                *pos -= 1;
+                break;
+            }
+            '|' => {
+                ret.push(Vec::new());
+                continue;
+            }
+            '(' if depth == MAX_NESTING => return Err("too many nested groups".to_owned()),
+            '(' => {
+                let group = alternatives(chars, pos, depth + 1)?;
+                if chars.get(*pos) != Some(&')') {
+                    return Err("unmatched (".to_owned());
+                }
+                *pos += 1;
+                Node::Group(group)
+            }
+            '*' | '+' | '?' => {
+                let (min, max) = match c {
+                    '*' => (0, None),
+                    '+' => (1, None),
+                    _ => (0, Some(1)),
+                };
+                let seq = ret.last_mut().unwrap();
+                match seq.pop() {
+                    Some(Node::Start) | Some(Node::End) | Some(Node::Repeat(..)) | None => {
+                        return Err(format!("nothing to repeat before {}", c));
+                    }
+                    Some(node) => seq.push(Node::Repeat(Box::new(node), min, max)),
+                }
+                continue;
+            }
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => class(chars, pos)?,
+            '\\' => {
+                let escaped = *chars.get(*pos).ok_or("trailing \\")?;
+                *pos += 1;
+                match escape(escaped) {
+                    Some(member) => Node::Class(vec![member], false),
+                    None => Node::Char(escaped),
+                }
+            }
+            c => Node::Char(c),
+        };
+        ret.last_mut().unwrap().push(node);
+    }
+    Ok(ret)
+}
+
+// the bracket expression following a `[`
+fn class(chars: &[char], pos: &mut usize) -> Result<Node, String> {
+    let negate = chars.get(*pos) == Some(&'^');
+    if negate {
+        *pos += 1;
+    }
+    let mut members = Vec::new();
+    // a `]` right after the opening bracket is a literal member
+    let start = *pos;
+    loop {
+        let c = *chars.get(*pos).ok_or("unmatched [")?;
+        *pos += 1;
+        let member = match c {
+            ']' if *pos - 1 > start => break,
+            '\\' => {
+                let escaped = *chars.get(*pos).ok_or("unmatched [")?;
+                *pos += 1;
+                escape(escaped).unwrap_or(Member::Char(escaped))
+            }
+            c if chars.get(*pos) == Some(&'-') && chars.get(*pos + 1).is_some_and(|&end| end != ']') => {
+                let end = chars[*pos + 1];
+                *pos += 2;
+                Member::Range(c, end)
+            }
+            c => Member::Char(c),
+        };
+        members.push(member);
+    }
+    Ok(Node::Class(members, negate))
+}
+
+// the class `\c` stands for, if any
+fn escape(c: char) -> Option<Member> {
+    match c {
+        'd' | 'D' => Some(Member::Digit(c == 'd')),
+        'w' | 'W' => Some(Member::Word(c == 'w')),
+        's' | 'S' => Some(Member::Space(c == 's')),
+        _ => None,
+    }
+}
+
+// appends the steps matching any of `alternatives`
+fn compile_alternatives(alternatives: Vec<Vec<Node>>, program: &mut Vec<Inst>) {
+    let count = alternatives.len();
+    let mut jumps = Vec::new();
+    for (n, seq) in alternatives.into_iter().enumerate() {
+        if n + 1 == count {
+            compile_seq(seq, program);
+            break;
+        }
+        let split = program.len();
+        program.push(Inst::Split(split + 1, 0));
+        compile_seq(seq, program);
+        jumps.push(program.len());
+        program.push(Inst::Jump(0));
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+    let end = program.len();
+    for jump in jumps {
+        program[jump] = Inst::Jump(end);
+    }
+}
+
+fn compile_seq(seq: Vec<Node>, program: &mut Vec<Inst>) {
+    for node in seq {
+        compile(node, program);
+    }
+}
+
+fn compile(node: Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(c) => program.push(Inst::Char(c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(members, negate) => program.push(Inst::Class(members, negate)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(alternatives) => compile_alternatives(alternatives, program),
+        // `x+` is `x` then `x*`, which loops back before `x`
+        Node::Repeat(node, min, max) => {
+            let start = program.len();
+            if min > 0 {
+                compile(*node, program);
+                program.push(Inst::Split(start, program.len() + 1));
+                return;
+            }
+            program.push(Inst::Split(start + 1, 0));
+            compile(*node, program);
+            if max.is_none() {
+                program.push(Inst::Jump(start));
+            }
+            program[start] = Inst::Split(start + 1, program.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Regex;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[a-c]x", "zbx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches(r"\d\s\w", "1 a"));
+        assert!(!matches(r"\D", "123"));
+        assert!(matches("a.c", "abc"));
+        assert!(Regex::new("[A-C]", true).unwrap().is_match("b"));
+        assert!(Regex::new("é", true).unwrap().is_match("CAFÉ"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("^ab", "abc"));
+        assert!(!matches("^bc", "abc"));
+        assert!(matches("bc$", "abc"));
+        assert!(!matches("ab$", "abc"));
+        assert!(matches("^$", ""));
+        assert!(!matches("a^b", "ab"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(matches("cat|dog", "hotdog"));
+        assert!(!matches("cat|dog", "cow"));
+        assert!(matches("^(error|warn):", "warn: disk"));
+        assert!(!matches("^(error|warn):", "info: warn:"));
+        assert!(matches("a(|b)c", "ac"));
+    }
+
+    #[test]
+    fn repetition() {
+        assert!(matches("^ab*c$", "ac"));
+        assert!(matches("^ab*c$", "abbbc"));
+        assert!(!matches("^ab+c$", "ac"));
+        assert!(matches("^ab+c$", "abc"));
+        assert!(matches("^colou?r$", "color"));
+        assert!(!matches("^colou?r$", "colouur"));
+        assert!(matches("^(ab)+$", "ababab"));
+        assert!(!matches("^(ab)+$", "ababa"));
+        assert!(matches("^(a*)*$", "aaa"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Regex::new("(a", false).is_err());
+        assert!(Regex::new("a)", false).is_err());
+        assert!(Regex::new("[a", false).is_err());
+        assert!(Regex::new("*a", false).is_err());
+        assert!(Regex::new("a**", false).is_err());
+        assert!(Regex::new(r"a\", false).is_err());
+        assert!(Regex::new(&"(".repeat(100_000), false).is_err());
+    }
+
+    #[test]
+    fn no_exponential_backtracking() {
+        let start = Instant::now();
+        let text = "a".repeat(5000);
+        assert!(!matches("^(a*)*b$", &text));
+        assert!(!matches(&format!("{}{}", "a?".repeat(30), "a".repeat(31)), &"a".repeat(30)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use super::auth;
+use super::glob;
+use super::messages::{English, Messages};
+use super::parse::{self, Word};
+use super::result::CommandResult;
+
+type Handler = Box<dyn Fn(&[String]) -> CommandResult>;
+type DeniedHook = Box<dyn Fn(Option<&str>, &str)>;
+type Validator = Rc<dyn Fn(&str) -> Result<(), String>>;
+type FieldCompleter = Rc<dyn Fn(&str) -> Vec<String>>;
+
+/// An argument of a command, which `wizard <command>` asks for.
+#[derive(Clone)]
+pub struct Param {
+    name: String,
+    help: String,
+    validator: Option<Validator>,
+    completer: Option<FieldCompleter>,
+}
+
+impl Param {
+    pub fn new(name: &str) -> Self {
+        Param { name: name.to_owned(), help: String::new(), validator: None, completer: None }
+    }
+
+    /// Shown next to the name when asking for the value.
+    pub fn help(mut self, help: &str) -> Self {
+        self.help = help.to_owned();
+        self
+    }
+
+    /// Checks a value, asking again with the error if it is refused.
+    pub fn validate<F>(mut self, validator: F) -> Self
+        where F: Fn(&str) -> Result<(), String> + 'static {
+        self.validator = Some(Rc::new(validator));
+        self
+    }
+
+    /// Completes the value typed so far on Tab.
+    pub fn complete<F>(mut self, completer: F) -> Self
+        where F: Fn(&str) -> Vec<String> + 'static {
+        self.completer = Some(Rc::new(completer));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn help_text(&self) -> &str {
+        &self.help
+    }
+
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        match self.validator {
+            Some(ref validator) => validator(value),
+            None => Ok(()),
+        }
+    }
+
+    pub fn candidates(&self, value: &str) -> Vec<String> {
+        match self.completer {
+            Some(ref completer) => completer(value),
+            None => Vec::new(),
+        }
+    }
+}
+
+pub struct Command {
+    name: String,
+    help: String,
+    handler: Handler,
+    // parser-backed commands handle `--help` themselves
+    own_help: bool,
+    glob: bool,
+    paginate: Option<bool>,
+    permission: Option<String>,
+    params: Vec<Param>,
+}
+
+impl Command {
+    pub fn help(&mut self, help: &str) -> &mut Self {
+        self.help = help.to_owned();
+        self
+    }
+
+    /// Expands unquoted glob patterns in this command's arguments into the
+    /// matching file names. Patterns matching nothing are passed through
+    /// unchanged with a warning.
+    pub fn glob(&mut self, glob: bool) -> &mut Self {
+        self.glob = glob;
+        self
+    }
+
+    /// Overrides `TerminalBuilder::paginate` for this command, e.g. to let
+    /// one that streams on purpose print without stopping.
+    pub fn paginate(&mut self, paginate: bool) -> &mut Self {
+        self.paginate = Some(paginate);
+        self
+    }
+
+    /// Restricts this command to sessions whose role grants `permission`,
+    /// see `Registry::role`. Commands without a permission are open to all.
+    pub fn permission(&mut self, permission: &str) -> &mut Self {
+        self.permission = Some(permission.to_owned());
+        self
+    }
+
+    /// Declares the next argument, for `wizard` to ask for.
+    pub fn param(&mut self, param: Param) -> &mut Self {
+        self.params.push(param);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn help_text(&self) -> &str {
+        &self.help
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    pub fn paginated(&self) -> Option<bool> {
+        self.paginate
+    }
+}
+
+/// A table of named commands dispatched on the first argument of the line.
+pub struct Registry {
+    commands: BTreeMap<String, Command>,
+    expand: bool,
+    messages: Box<dyn Messages>,
+    // permissions by role, and roles by user
+    roles: BTreeMap<String, BTreeSet<String>>,
+    users: BTreeMap<String, String>,
+    default_role: Option<String>,
+    on_denied: Option<DeniedHook>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            commands: BTreeMap::new(),
+            expand: false,
+            messages: Box::new(English),
+            roles: BTreeMap::new(),
+            users: BTreeMap::new(),
+            default_role: None,
+            on_denied: None,
+        }
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Enables `$VAR` and `~` expansion of arguments, see `parse::split_expanded`.
+    pub fn expand_vars(&mut self, expand: bool) -> &mut Self {
+        self.expand = expand;
+        self
+    }
+
+    /// Replaces the messages for unknown commands, parse errors and the
+    /// like, see `TerminalBuilder::messages`.
+    pub fn messages<M>(&mut self, messages: M) -> &mut Self
+        where M: Messages + 'static {
+        self.messages = Box::new(messages);
+        self
+    }
+
+    /// Defines `role` as granting `permissions`, see `Command::permission`.
+    ///
+    /// ```
+    /// let mut registry = term_rs::Registry::new();
+    /// registry.command("status", |_| "all good");
+    /// registry.command("restart", |_| "restarting").permission("admin");
+    /// registry.role("operator", &["admin"]).assign("alice", "operator");
+    /// ```
+    pub fn role(&mut self, role: &str, permissions: &[&str]) -> &mut Self {
+        self.roles.entry(role.to_owned()).or_default().extend(permissions.iter().map(|p| (*p).to_owned()));
+        self
+    }
+
+    /// Gives the sessions `user` logs in to `role`, the user being the name
+    /// returned by the `Authenticator`.
+    pub fn assign(&mut self, user: &str, role: &str) -> &mut Self {
+        self.users.insert(user.to_owned(), role.to_owned());
+        self
+    }
+
+    /// The role of sessions whose user has none assigned, including
+    /// sessions without a login.
+    pub fn default_role(&mut self, role: &str) -> &mut Self {
+        self.default_role = Some(role.to_owned());
+        self
+    }
+
+    /// Calls `hook` with the user and the command line whenever a session
+    /// runs, or asks for help on, a command its role doesn't allow.
+    pub fn on_denied<F>(&mut self, hook: F) -> &mut Self
+        where F: Fn(Option<&str>, &str) + 'static {
+        self.on_denied = Some(Box::new(hook));
+        self
+    }
+
+    /// Registers a command receiving the arguments after its name.
+    /// `name --help` prints the command's help text.
+    pub fn command<F, R>(&mut self, name: &str, handler: F) -> &mut Command
+        where F: Fn(Vec<String>) -> R + 'static,
+              R: Into<CommandResult> {
+        self.insert(name, false, Box::new(move |argv: &[String]| handler(argv[1..].to_vec()).into()))
+    }
+
+    /// Registers a command whose arguments are first run through `parser`,
+    /// which receives the full argv (command name first). A parse error is
+    /// printed in the terminal instead of reaching the handler, so parsers
+    /// that would normally exit the process can be used through their
+    /// fallible variants.
+    ///
+    /// This is a generic hook, not a clap integration: there is no clap
+    /// feature, and term-rs doesn't depend on clap. An application using
+    /// clap writes the parser itself, calling `try_get_matches_from` and
+    /// rendering the error with `render()`, which `parse` stands in for
+    /// here:
+    ///
+    /// ```
+    /// fn parse(argv: &[String]) -> Result<String, String> {
+    ///     match argv {
+    ///         [_, host] if host != "--help" => Ok(host.clone()),
+    ///         _ => Err(format!("Usage: {} <host>\n", argv[0])),
+    ///     }
+    /// }
+    ///
+    /// let mut registry = term_rs::Registry::new();
+    /// registry.command_with_parser("connect", parse, |host| format!("connecting to {}", host));
+    ///
+    /// let result = registry.dispatch(vec!["connect".into(), "db1".into()]);
+    /// assert!(result.success);
+    /// assert_eq!(result.output, "connecting to db1");
+    /// let result = registry.dispatch(vec!["connect".into()]);
+    /// assert!(!result.success);
+    /// assert_eq!(result.output, "Usage: connect <host>");
+    /// ```
+    pub fn command_with_parser<T, P, F, R>(&mut self, name: &str, parser: P, handler: F) -> &mut Command
+        where P: Fn(&[String]) -> Result<T, String> + 'static,
+              F: Fn(T) -> R + 'static,
+              R: Into<CommandResult> {
+        self.insert(name, true, Box::new(move |argv: &[String]| {
+            match parser(argv) {
+                Ok(parsed) => handler(parsed).into(),
+                Err(e) => CommandResult::err(e.trim_end()),
+            }
+        }))
+    }
+
+    fn insert(&mut self, name: &str, own_help: bool, handler: Handler) -> &mut Command {
+        let command = Command { name: name.to_owned(), help: String::new(), handler, own_help, glob: false,
+                                 paginate: None, permission: None, params: Vec::new() };
+        self.commands.insert(name.to_owned(), command);
+        self.commands.get_mut(name).unwrap()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        self.commands.values()
+    }
+
+    /// Whether `user`, or a session without a login for `None`, may run
+    /// `command`.
+    pub fn allows(&self, user: Option<&str>, command: &Command) -> bool {
+        let permission = match command.permission {
+            Some(ref permission) => permission,
+            None => return true,
+        };
+        let role = user.and_then(|user| self.users.get(user)).or(self.default_role.as_ref());
+        role.and_then(|role| self.roles.get(role)).is_some_and(|granted| granted.contains(permission))
+    }
+
+    /// Runs the command named by `argv[0]` as the current session's user,
+    /// see `current_user`.
+    pub fn dispatch(&self, argv: Vec<String>) -> CommandResult {
+        let name = match argv.first() {
+            Some(name) => name.as_str(),
+            None => return CommandResult::ok(""),
+        };
+        if name == "help" {
+            return self.help(&argv, argv.get(1).map(|s| s.as_str()));
+        }
+        match self.authorize(&argv, name) {
+            Ok(command) if !command.own_help && argv.len() == 2 && argv[1] == "--help" => {
+                self.help(&argv, Some(name))
+            }
+            Ok(command) => (command.handler)(&argv),
+            Err(e) => e,
+        }
+    }
+
+    // the command `name` if it exists and the current user may run it
+    fn authorize(&self, argv: &[String], name: &str) -> Result<&Command, CommandResult> {
+        let command = match self.commands.get(name) {
+            Some(command) => command,
+            None => return Err(CommandResult::err(self.messages.command_not_found(name))),
+        };
+        let user = auth::current_user();
+        if self.allows(user.as_deref(), command) {
+            return Ok(command);
+        }
+        if let Some(ref hook) = self.on_denied {
+            hook(user.as_deref(), &argv.join(" "));
+        }
+        Err(CommandResult::err(self.messages.permission_denied(name)))
+    }
+
+    fn help(&self, argv: &[String], name: Option<&str>) -> CommandResult {
+        match name {
+            Some(name) => match self.authorize(argv, name) {
+                Ok(command) if command.help.is_empty() => CommandResult::ok(self.messages.no_help(name)),
+                Ok(command) => CommandResult::ok(command.help.clone()),
+                Err(e) => e,
+            },
+            None => {
+                let user = auth::current_user();
+                let allowed = self.commands.values().filter(|c| self.allows(user.as_deref(), c)).collect::<Vec<_>>();
+                let width = allowed.iter().map(|c| c.name.len()).max().unwrap_or(0);
+                CommandResult::ok(allowed.into_iter()
+                    .map(|c| format!("{:width$}  {}", c.name, c.help.lines().next().unwrap_or(""), width = width))
+                    .map(|l| l.trim_end().to_owned())
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+        }
+    }
+
+    /// Turns the registry into a processor for `Terminal::run`.
+    pub fn into_process(self) -> impl Fn(String) -> CommandResult {
+        move |line: String| match parse::words(&line, self.expand) {
+            Ok(words) => {
+                let mut warnings = String::new();
+                let argv = self.glob_words(words, &mut warnings);
+                let mut result = self.dispatch(argv);
+                result.error.insert_str(0, &warnings);
+                result
+            }
+            Err(e) => CommandResult::err(self.messages.parse_error(&e.to_string())),
+        }
+    }
+
+    fn glob_words(&self, words: Vec<Word>, warnings: &mut String) -> Vec<String> {
+        let glob = words.first()
+            .and_then(|w| self.commands.get(&w.text))
+            .is_some_and(|c| c.glob);
+        let mut argv = Vec::new();
+        for (i, word) in words.into_iter().enumerate() {
+            match word.pattern {
+                Some(ref pattern) if glob && i > 0 => {
+                    let matched = glob::expand(pattern);
+                    if matched.is_empty() {
+                        warnings.push_str(&self.messages.no_match(&word.text));
+                        warnings.push('\n');
+                        argv.push(word.text);
+                    } else {
+                        argv.extend(matched);
+                    }
+                }
+                _ => argv.push(word.text),
+            }
+        }
+        argv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::Registry;
+
+    fn argv(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| (*word).to_owned()).collect()
+    }
+
+    // a parser like clap's, failing with its usage, and with its help for
+    // `--help`, instead of exiting
+    fn parse(argv: &[String]) -> Result<u16, String> {
+        match argv {
+            [_, flag] if flag == "--help" => Err("Opens a connection.\n\nUsage: connect <port>\n".to_owned()),
+            [_, port] => port.parse().map_err(|e| format!("error: invalid port '{}': {}\n", port, e)),
+            _ => Err("error: missing <port>\n\nUsage: connect <port>\n".to_owned()),
+        }
+    }
+
+    #[test]
+    fn parse_errors_never_reach_the_handler() {
+        let calls = Rc::new(Cell::new(0));
+        let mut registry = Registry::new();
+        let counted = calls.clone();
+        registry.command_with_parser("connect", parse, move |port| {
+            counted.set(counted.get() + 1);
+            format!("connected on {}", port)
+        });
+
+        let result = registry.dispatch(argv(&["connect", "http"]));
+        assert!(!result.success);
+        assert!(result.output.starts_with("error: invalid port 'http'"));
+        let result = registry.dispatch(argv(&["connect"]));
+        assert!(!result.success);
+        assert_eq!(result.output, "error: missing <port>\n\nUsage: connect <port>");
+        assert_eq!(calls.get(), 0);
+
+        let result = registry.dispatch(argv(&["connect", "8080"]));
+        assert!(result.success);
+        assert_eq!(result.output, "connected on 8080");
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn help_goes_to_the_parser() {
+        let mut registry = Registry::new();
+        registry.command_with_parser("connect", parse, |port| format!("connected on {}", port))
+            .help("not shown");
+        let result = registry.dispatch(argv(&["connect", "--help"]));
+        assert_eq!(result.output, "Opens a connection.\n\nUsage: connect <port>");
+    }
+}
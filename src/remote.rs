@@ -0,0 +1,599 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::audit::Outcome;
+use super::builder::TerminalBuilder;
+use super::plain::{self, Session};
+use super::result::CommandResult;
+use super::scrollback::Scrollback;
+
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DONT: u8 = 254;
+const ECHO: u8 = 1;
+
+// the longest line a client may send, newline included; a client going on
+// past it is dropped
+const MAX_LINE: usize = 64 * 1024;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Data,
+    Command,
+    // the option byte after WILL, WONT, DO or DONT
+    Option,
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+// drops the telnet commands clients interleave with the typed text
+struct Telnet<R> {
+    inner: R,
+    state: State,
+}
+
+impl<R: Read> Read for Telnet<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.inner.read(buf)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            let mut kept = 0;
+            for i in 0..read {
+                let byte = buf[i];
+                self.state = match (self.state, byte) {
+                    (State::Data, IAC) => State::Command,
+                    (State::Data, _) => {
+                        buf[kept] = byte;
+                        kept += 1;
+                        State::Data
+                    }
+                    // an escaped 255 data byte
+                    (State::Command, IAC) => {
+                        buf[kept] = byte;
+                        kept += 1;
+                        State::Data
+                    }
+                    (State::Command, SB) => State::Subnegotiation,
+                    (State::Command, WILL..=DONT) => State::Option,
+                    (State::Command, _) | (State::Option, _) => State::Data,
+                    (State::Subnegotiation, IAC) => State::SubnegotiationCommand,
+                    (State::Subnegotiation, _) => State::Subnegotiation,
+                    (State::SubnegotiationCommand, SE) => State::Data,
+                    (State::SubnegotiationCommand, _) => State::Subnegotiation,
+                };
+            }
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+// ends lines with CR LF, as telnet clients expect
+struct CrLf<W> {
+    inner: W,
+}
+
+impl<W: Write> Write for CrLf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, line) in buf.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                self.inner.write_all(b"\r\n")?;
+            }
+            self.inner.write_all(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// one writer taking both output and errors
+struct Shared<W> {
+    inner: Rc<RefCell<W>>,
+}
+
+impl<W: Write> Write for Shared<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+// the writing end of a client's connection
+pub trait Connection: Write + Send {
+    fn close(&self);
+
+    // stops showing what the client types, for secrets
+    fn hide_input(&mut self, hide: bool);
+
+    // the address of the client, which sessions of no user share a rate
+    // limit by
+    fn peer(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+
+    // a telnet client stops echoing locally when the server offers to echo
+    fn hide_input(&mut self, hide: bool) {
+        let _ = self.write_all(&[IAC, if hide { WILL } else { WONT }, ECHO]);
+    }
+
+    fn peer(&self) -> Option<String> {
+        self.peer_addr().ok().map(|addr| addr.ip().to_string())
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+
+    fn hide_input(&mut self, hide: bool) {
+        let _ = self.write_all(&[IAC, if hide { WILL } else { WONT }, ECHO]);
+    }
+}
+
+enum Event {
+    Connected(usize, Box<dyn Connection>),
+    Line(usize, String),
+    Closed(usize),
+    Broadcast(String),
+    Failed(io::Error),
+}
+
+#[derive(Default)]
+struct Clients {
+    events: Option<Sender<Event>>,
+    count: usize,
+}
+
+/// The clients connected to a server started with
+/// `TerminalBuilder::serve_tcp`. Clones share the same clients, so one can
+/// be kept to message them from any thread.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl Sessions {
+    /// Shows `message` to every connected client, followed by a fresh
+    /// prompt.
+    pub fn broadcast(&self, message: &str) {
+        if let Some(ref events) = self.clients.lock().unwrap().events {
+            let _ = events.send(Event::Broadcast(message.to_owned()));
+        }
+    }
+
+    /// The number of connected clients.
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn set_count(&self, count: usize) {
+        self.clients.lock().unwrap().count = count;
+    }
+}
+
+// a client's input and the connection writing to it
+pub type Accepted = (Box<dyn BufRead + Send>, Box<dyn Connection>);
+
+pub trait Listener: Send + 'static {
+    fn accept_client(&self) -> io::Result<Accepted>;
+}
+
+impl Listener for TcpListener {
+    fn accept_client(&self) -> io::Result<Accepted> {
+        let (stream, _) = self.accept()?;
+        let connection = stream.try_clone()?;
+        Ok((Box::new(BufReader::new(Telnet { inner: stream, state: State::Data })), Box::new(connection)))
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    fn accept_client(&self) -> io::Result<Accepted> {
+        let (stream, _) = self.accept()?;
+        let connection = stream.try_clone()?;
+        Ok((Box::new(BufReader::new(Telnet { inner: stream, state: State::Data })), Box::new(connection)))
+    }
+}
+
+// detached sessions kept for reattaching, the oldest being dropped first
+const DETACHED_SESSIONS: usize = 16;
+
+// a session of `serve` and the text it has shown, to be replayed when it
+// is reattached
+struct Detached {
+    session: Session,
+    scrollback: Scrollback,
+}
+
+struct Client {
+    // sessions are numbered after the connection that started them
+    id: usize,
+    session: Session,
+    scrollback: Scrollback,
+    connection: CrLf<Box<dyn Connection>>,
+}
+
+impl Client {
+    // runs `run` with output and errors going to the client and its
+    // scrollback, then shows the prompt
+    fn send<S>(&mut self, builder: &TerminalBuilder, run: S) -> io::Result<()>
+        where S: FnOnce(&mut Session, &mut dyn Write, &mut dyn Write) -> io::Result<()> {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        run(&mut self.session, &mut Shared { inner: buffer.clone() }, &mut Shared { inner: buffer.clone() })?;
+        let text = String::from_utf8_lossy(&buffer.borrow()).into_owned();
+        if !text.is_empty() {
+            self.scrollback.push(text.trim_end_matches('\n'));
+        }
+        write!(self.connection, "{}{}", text, self.session.prompt(builder))?;
+        self.connection.flush()
+    }
+
+    fn detach(self) -> (usize, Detached) {
+        self.connection.inner.close();
+        (self.id, Detached { session: self.session, scrollback: self.scrollback })
+    }
+}
+
+// a connection asking for the secret of an authenticator
+struct Login {
+    connection: CrLf<Box<dyn Connection>>,
+    failures: usize,
+}
+
+fn connect(builder: &TerminalBuilder, id: usize, connection: CrLf<Box<dyn Connection>>, user: Option<String>)
+    -> Option<Client> {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let peer = connection.inner.peer();
+    let session = Session::start(builder, user, Some(id), peer.as_deref(), &mut Shared { inner: buffer.clone() },
+                                 &mut Shared { inner: buffer.clone() }).ok()?;
+    let mut client = Client { id, session, scrollback: Scrollback::default(), connection };
+    let banner = String::from_utf8_lossy(&buffer.borrow()).into_owned();
+    let sent = client.send(builder, |_, out, _| {
+        writeln!(out, "{}", builder.messages.session_started(id))?;
+        write!(out, "{}", banner)
+    });
+    sent.ok().map(|_| client)
+}
+
+// Each connection gets a thread reading its lines, while the commands of
+// all of them run one at a time on the calling thread, which owns the
+// processor: the application state it reaches needs no locking of its own.
+//
+// Clients are dropped when they can't be written to, which their reader
+// thread notices as the end of input. A session outlives its connection:
+// `detach`, or losing the connection, keeps it to be taken over with
+// `attach <id>` from any connection of the same user.
+pub fn serve<L, F, R>(builder: TerminalBuilder, listener: L, process: F) -> io::Result<()>
+    where L: Listener,
+          F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    let (events, received) = mpsc::channel();
+    builder.sessions.clients.lock().unwrap().events = Some(events.clone());
+    thread::spawn(move || accept(listener, events));
+    let mut clients: BTreeMap<usize, Client> = BTreeMap::new();
+    let mut detached: BTreeMap<usize, Detached> = BTreeMap::new();
+    let mut logins: BTreeMap<usize, Login> = BTreeMap::new();
+    let ret = loop {
+        match received.recv() {
+            Ok(Event::Connected(id, connection)) => {
+                let mut connection = CrLf { inner: connection };
+                match builder.authenticator {
+                    Some(ref authenticator) => {
+                        connection.inner.hide_input(true);
+                        if write!(connection, "{}", authenticator.prompt()).and_then(|_| connection.flush()).is_ok() {
+                            logins.insert(id, Login { connection, failures: 0 });
+                        }
+                    }
+                    None => {
+                        if let Some(client) = connect(&builder, id, connection, None) {
+                            clients.insert(id, client);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Line(id, line)) if logins.contains_key(&id) => {
+                let mut login = logins.remove(&id).unwrap();
+                let authenticator = builder.authenticator.as_ref().unwrap();
+                let _ = writeln!(login.connection);
+                match authenticator.authenticate(line.trim_end_matches(['\n', '\r'])) {
+                    Some(user) => {
+                        login.connection.inner.hide_input(false);
+                        if let Some(client) = connect(&builder, id, login.connection, Some(user)) {
+                            clients.insert(id, client);
+                        }
+                    }
+                    None => {
+                        login.failures += 1;
+                        let _ = writeln!(login.connection, "{}", builder.messages.login_failed());
+                        if login.failures >= builder.login_attempts {
+                            let _ = writeln!(login.connection, "{}", builder.messages.too_many_attempts());
+                            login.connection.inner.close();
+                        } else if write!(login.connection, "{}", authenticator.prompt()).is_ok() {
+                            logins.insert(id, login);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Line(id, line)) => {
+                let mut client = match clients.remove(&id) {
+                    Some(client) => client,
+                    None => continue,
+                };
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words != ["detach"] {
+                    let prompt = client.session.prompt(&builder);
+                    client.scrollback.push(&format!("{}{}", prompt, line.trim_end_matches(['\n', '\r'])));
+                }
+                // built-ins count against the rate limit like any command
+                let builtin = matches!(words.as_slice(), ["detach"] | ["attach", _] | ["sessions"] | ["stats"]);
+                // and are audited, with what that shows kept for their output
+                let mut notes = Vec::new();
+                let admitted = !builtin || client.session.admit(&builder, &line, &mut notes).unwrap_or(false);
+                let sent = match words.as_slice() {
+                    _ if !admitted => client.send(&builder, |_, _, err| err.write_all(&notes)),
+                    ["detach"] => {
+                        // with the connection gone, an audit error has nowhere to go
+                        let _ = client.session.audit(&builder, &line, Outcome::Success, Duration::default(), &mut notes);
+                        let (session_id, session) = client.detach();
+                        detached.insert(session_id, session);
+                        continue;
+                    }
+                    ["attach", session_id] => {
+                        // only the user who left a session can take it over
+                        let found = session_id.parse().ok()
+                            .filter(|session_id| {
+                                detached.get(session_id).is_some_and(|d| d.session.user() == client.session.user())
+                            })
+                            .and_then(|session_id| detached.remove(&session_id).map(|session| (session_id, session)));
+                        let outcome = if found.is_some() { Outcome::Success } else { Outcome::Failure };
+                        let _ = client.session.audit(&builder, &line, outcome, Duration::default(), &mut notes);
+                        match found {
+                            Some((session_id, Detached { session, scrollback })) => {
+                                let previous = Detached {
+                                    session: mem::replace(&mut client.session, session),
+                                    scrollback: mem::replace(&mut client.scrollback, scrollback),
+                                };
+                                detached.insert(mem::replace(&mut client.id, session_id), previous);
+                                let replay = client.scrollback.lines().join("\n");
+                                client.send(&builder, |_, out, err| {
+                                    err.write_all(&notes)?;
+                                    writeln!(out, "{}", replay)
+                                })
+                            }
+                            None => client.send(&builder, |_, _, err| {
+                                err.write_all(&notes)?;
+                                writeln!(err, "{}", builder.messages.not_found("attach", session_id))
+                            }),
+                        }
+                    }
+                    ["sessions"] => {
+                        let ids: Vec<usize> = detached.iter()
+                            .filter(|&(_, d)| d.session.user() == client.session.user())
+                            .map(|(&id, _)| id)
+                            .collect();
+                        let _ = client.session.audit(&builder, &line, Outcome::Success, Duration::default(), &mut notes);
+                        client.send(&builder, |_, out, err| {
+                            err.write_all(&notes)?;
+                            writeln!(out, "{}", builder.messages.detached_sessions(&ids))
+                        })
+                    }
+                    ["stats"] => {
+                        let _ = client.session.audit(&builder, &line, Outcome::Success, Duration::default(), &mut notes);
+                        client.send(&builder, |_, out, err| {
+                            err.write_all(&notes)?;
+                            writeln!(out, "{}", builder.stats.summary())
+                        })
+                    }
+                    _ => client.send(&builder, |session, out, err| {
+                        session.run_line(&builder, &process, &line, out, err)
+                    }),
+                };
+                if sent.is_ok() {
+                    clients.insert(id, client);
+                } else {
+                    let (session_id, session) = client.detach();
+                    detached.insert(session_id, session);
+                }
+            }
+            Ok(Event::Closed(id)) => {
+                logins.remove(&id);
+                if let Some(client) = clients.remove(&id) {
+                    let (session_id, session) = client.detach();
+                    detached.insert(session_id, session);
+                }
+            }
+            Ok(Event::Broadcast(message)) => {
+                let message = message.trim_end_matches('\n');
+                let ids: Vec<usize> = clients.keys().cloned().collect();
+                for id in ids {
+                    let sent = clients.get_mut(&id).unwrap().send(&builder, |_, out, _| writeln!(out, "\n{}", message));
+                    if sent.is_err() {
+                        let (session_id, session) = clients.remove(&id).unwrap().detach();
+                        detached.insert(session_id, session);
+                    }
+                }
+            }
+            Ok(Event::Failed(e)) => break Err(e),
+            Err(_) => break Ok(()),
+        }
+        while detached.len() > DETACHED_SESSIONS {
+            let oldest = *detached.keys().next().unwrap();
+            detached.remove(&oldest).unwrap().session.end(&builder);
+        }
+        builder.sessions.set_count(clients.len());
+    };
+    for session in clients.values().map(|client| &client.session).chain(detached.values().map(|d| &d.session)) {
+        session.end(&builder);
+    }
+    builder.sessions.clients.lock().unwrap().events = None;
+    builder.sessions.set_count(0);
+    ret
+}
+
+fn accept<L: Listener>(listener: L, events: Sender<Event>) {
+    for id in 0.. {
+        let (mut input, connection) = match listener.accept_client() {
+            Ok(client) => client,
+            Err(e) => {
+                let _ = events.send(Event::Failed(e));
+                return;
+            }
+        };
+        if events.send(Event::Connected(id, connection)).is_err() {
+            return;
+        }
+        let events = events.clone();
+        thread::spawn(move || {
+            while let Some(line) = next_line(&mut input) {
+                if events.send(Event::Line(id, line)).is_err() {
+                    return;
+                }
+            }
+            let _ = events.send(Event::Closed(id));
+        });
+    }
+}
+
+// the next line of a client, None once it has closed the connection or
+// sent more than `MAX_LINE` without a newline
+fn next_line(input: &mut dyn BufRead) -> Option<String> {
+    let mut line = Vec::new();
+    match input.take(MAX_LINE as u64 + 1).read_until(b'\n', &mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(read) if read > MAX_LINE => None,
+        Ok(_) => Some(String::from_utf8_lossy(&line).into_owned()),
+    }
+}
+
+/// Serves the command loop to clients of `listener`, see
+/// `TerminalBuilder::serve_tcp`.
+pub fn serve_tcp<F, R>(builder: TerminalBuilder, listener: TcpListener, process: F) -> io::Result<()>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    serve(builder, listener, process)
+}
+
+#[cfg(unix)]
+pub fn serve_unix<F, R>(builder: TerminalBuilder, listener: UnixListener, process: F) -> io::Result<()>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    serve(builder, listener, process)
+}
+
+/// Runs one session on `input` and `output`, see
+/// `TerminalBuilder::serve_stream`.
+pub fn serve_stream<F, R, I, O>(builder: TerminalBuilder, input: I, output: O, process: F) -> io::Result<()>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult>,
+          I: Read,
+          O: Write {
+    let output = Rc::new(RefCell::new(CrLf { inner: output }));
+    let mut out = Shared { inner: output.clone() };
+    let mut err = Shared { inner: output };
+    plain::session(&builder, &process, BufReader::new(input), &mut out, &mut err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self, BufRead, Cursor, Read};
+
+    use super::{next_line, State, Telnet, DONT, ECHO, IAC, MAX_LINE, SB, SE, WILL, WONT};
+
+    const DO: u8 = 253;
+
+    // hands out one chunk per read, as the network might
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut chunk = match self.0.pop_front() {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            };
+            if chunk.len() > buf.len() {
+                self.0.push_front(chunk.split_off(buf.len()));
+            }
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn filtered(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut telnet = Telnet { inner: Chunks(chunks.iter().map(|chunk| chunk.to_vec()).collect()), state: State::Data };
+        let mut data = Vec::new();
+        telnet.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn escaped_iac() {
+        assert_eq!(filtered(&[&[b'a', IAC, IAC, b'b']]), [b'a', IAC, b'b']);
+        assert_eq!(filtered(&[&[b'a', IAC], &[IAC, b'b']]), [b'a', IAC, b'b']);
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(filtered(&[&[IAC, WILL, ECHO, b'l', IAC, DO, 3, IAC, WONT, ECHO, IAC, DONT, 34, b's']]), b"ls");
+        // split after the command and after the IAC, leaving reads with
+        // nothing to keep
+        assert_eq!(filtered(&[&[b'l', IAC, DO], &[ECHO], &[IAC], &[WILL, 3, b's']]), b"ls");
+        // other commands, such as NOP, take no option
+        assert_eq!(filtered(&[&[IAC, 241, b'l', b's']]), b"ls");
+    }
+
+    #[test]
+    fn subnegotiation() {
+        // a window size of 80 by 255, the 255 escaped
+        let naws = [IAC, SB, 31, 0, 80, 0, IAC, IAC, IAC, SE];
+        assert_eq!(filtered(&[b"l", &naws, b"s"]), b"ls");
+        assert_eq!(filtered(&[&[b'l', IAC, SB, 24], &[0, b'x', b't', IAC], &[SE, b's', b'\n']]), b"ls\n");
+        // data before SE is still part of it
+        assert_eq!(filtered(&[&[IAC, SB, 24, b'a', IAC], &[b'b', IAC, SE, b'c']]), b"c");
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut input = Cursor::new(format!("ls\n{}\n{}\nls\n", "x".repeat(MAX_LINE - 1), "y".repeat(MAX_LINE)));
+        let input: &mut dyn BufRead = &mut input;
+        assert_eq!(next_line(input).as_deref(), Some("ls\n"));
+        assert_eq!(next_line(input).map(|line| line.len()), Some(MAX_LINE));
+        assert_eq!(next_line(input), None);
+        assert_eq!(next_line(&mut Cursor::new("last")).as_deref(), Some("last"));
+        assert_eq!(next_line(&mut Cursor::new("")), None);
+    }
+}
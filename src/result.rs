@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// The outcome of a processed command: its output and whether it succeeded.
+///
+/// Processors may keep returning a plain `String` (always a success) or
+/// return a `Result<String, String>`; both convert into a `CommandResult`.
+/// The output of a failed command is shown in the error style, as is
+/// `error`, a separate channel like stderr which pipes and redirections
+/// leave alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandResult {
+    pub output: String,
+    pub error: String,
+    pub success: bool,
+    // the result still to come, which this one stands for, waited for or
+    // else shown once it completes
+    pub(crate) pending: Option<Pending>,
+    pub(crate) deferred: Option<Pending>,
+    pub(crate) format: Format,
+}
+
+// how the output is to be shown
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) enum Format {
+    #[default]
+    Plain,
+    Markdown,
+    Diff,
+}
+
+impl CommandResult {
+    pub fn ok<S: Into<String>>(output: S) -> Self {
+        CommandResult {
+            output: output.into(),
+            error: String::new(),
+            success: true,
+            pending: None,
+            deferred: None,
+            format: Format::Plain,
+        }
+    }
+
+    pub fn err<S: Into<String>>(output: S) -> Self {
+        CommandResult { success: false, ..CommandResult::ok(output) }
+    }
+
+    /// Output written in Markdown, shown styled: headings, lists, quotes,
+    /// code, tables, and inline bold, emphasis and code. Pipes and
+    /// redirections get the Markdown itself.
+    pub fn markdown<S: Into<String>>(output: S) -> Self {
+        CommandResult { format: Format::Markdown, ..CommandResult::ok(output) }
+    }
+
+    /// A unified diff, such as `diff -u` prints, shown with its added and
+    /// removed lines colored and its file and hunk headers set apart.
+    /// Pipes and redirections get the diff itself.
+    pub fn diff<S: Into<String>>(output: S) -> Self {
+        CommandResult { format: Format::Diff, ..CommandResult::ok(output) }
+    }
+
+    pub(crate) fn pending(pending: Pending) -> Self {
+        CommandResult { pending: Some(pending), ..CommandResult::ok("") }
+    }
+
+    // waits for the result this one stands for, if any
+    pub(crate) fn resolve(mut self) -> Self {
+        match self.pending.take().or_else(|| self.deferred.take()) {
+            Some(pending) => pending.wait(),
+            None => self,
+        }
+    }
+
+    /// Adds `error` to the error channel.
+    pub fn with_error<S: Into<String>>(mut self, error: S) -> Self {
+        self.error = error.into();
+        self
+    }
+}
+
+impl From<String> for CommandResult {
+    fn from(output: String) -> Self {
+        CommandResult::ok(output)
+    }
+}
+
+impl<'a> From<&'a str> for CommandResult {
+    fn from(output: &'a str) -> Self {
+        CommandResult::ok(output)
+    }
+}
+
+impl From<Pending> for CommandResult {
+    fn from(pending: Pending) -> Self {
+        CommandResult { deferred: Some(pending), ..CommandResult::ok("") }
+    }
+}
+
+impl From<Result<String, String>> for CommandResult {
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(output) => CommandResult::ok(output),
+            Err(output) => CommandResult::err(output),
+        }
+    }
+}
+
+/// Tells a command running on a worker thread that the user gave up on
+/// it, see `cancel_token`. Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+thread_local! {
+    // the token of the command running on this thread
+    static CANCEL_TOKEN: RefCell<CancelToken> = RefCell::default();
+}
+
+/// The token cancelled when the user cancels the command being processed,
+/// which long-running handlers of `TerminalBuilder::run_threaded` check to
+/// stop early. Outside of those it is never cancelled.
+pub fn cancel_token() -> CancelToken {
+    CANCEL_TOKEN.with(|token| token.borrow().clone())
+}
+
+/// A result completed later, from another thread. A processor returning
+/// one doesn't hold up the terminal, which shows the result above the
+/// prompt when it comes; plain and remote sessions wait for it.
+///
+/// ```no_run
+/// use std::thread;
+/// use term_rs::{CommandResult, Pending, Terminal};
+///
+/// Terminal::run(|command: String| -> CommandResult {
+///     let pending = Pending::new();
+///     let done = pending.clone();
+///     thread::spawn(move || done.complete(format!("{} answered", command)));
+///     pending.into()
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Pending {
+    result: Arc<(Mutex<Option<CommandResult>>, Condvar)>,
+    cancel: CancelToken,
+}
+
+impl Pending {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // makes the token of this result the current thread's
+    pub(crate) fn enter(&self) {
+        CANCEL_TOKEN.with(|token| *token.borrow_mut() = self.cancel.clone());
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub(crate) fn try_take(&self) -> Option<CommandResult> {
+        self.result.0.lock().unwrap().take()
+    }
+
+    pub fn complete<R: Into<CommandResult>>(&self, result: R) {
+        let (ref lock, ref done) = *self.result;
+        *lock.lock().unwrap() = Some(result.into());
+        done.notify_all();
+    }
+
+    pub(crate) fn wait(&self) -> CommandResult {
+        let (ref lock, ref done) = *self.result;
+        let mut result = lock.lock().unwrap();
+        loop {
+            match result.take() {
+                Some(result) => return result,
+                None => result = done.wait(result).unwrap(),
+            }
+        }
+    }
+
+    // the result if it comes within `timeout`
+    pub(crate) fn wait_timeout(&self, timeout: Duration) -> Option<CommandResult> {
+        let (ref lock, ref done) = *self.result;
+        let result = lock.lock().unwrap();
+        let (mut result, _) = done.wait_timeout_while(result, timeout, |result| result.is_none()).unwrap();
+        result.take()
+    }
+}
+
+impl fmt::Debug for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Pending")
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.result, &other.result)
+    }
+}
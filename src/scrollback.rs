@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+struct Buffer {
+    lines: VecDeque<String>,
+    bytes: usize,
+    max_lines: usize,
+    max_bytes: usize,
+}
+
+/// The text the terminal has shown, prompts included, kept in a ring buffer
+/// bounded by line count and size: the oldest lines are dropped first.
+///
+/// Clones share the same buffer, so an application can keep one to inspect
+/// or clear the scrollback of a running terminal.
+#[derive(Clone)]
+pub struct Scrollback {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Scrollback::new(10_000, 4 << 20)
+    }
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize, max_bytes: usize) -> Self {
+        let buffer = Buffer { lines: VecDeque::new(), bytes: 0, max_lines, max_bytes };
+        Scrollback { buffer: Arc::new(Mutex::new(buffer)) }
+    }
+
+    /// Appends `text`, one line per `\n`-separated piece.
+    pub fn push(&self, text: &str) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for line in text.split('\n') {
+            buffer.bytes += line.len();
+            buffer.lines.push_back(line.to_owned());
+        }
+        while buffer.lines.len() > buffer.max_lines || buffer.bytes > buffer.max_bytes {
+            match buffer.lines.pop_front() {
+                Some(line) => buffer.bytes -= line.len(),
+                None => break,
+            }
+        }
+    }
+
+    // removes the last `count` lines
+    pub(crate) fn pop(&self, count: usize) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for _ in 0..count {
+            match buffer.lines.pop_back() {
+                Some(line) => buffer.bytes -= line.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Lines and bytes currently held.
+    pub fn usage(&self) -> (usize, usize) {
+        let buffer = self.buffer.lock().unwrap();
+        (buffer.lines.len(), buffer.bytes)
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.buffer.lock().unwrap().lines.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.lines.clear();
+        buffer.bytes = 0;
+    }
+}
@@ -0,0 +1,53 @@
+// A placeholder of a snippet inserted into the line, as a byte range of
+// the buffer. It shows its name until text is typed into it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub start: usize,
+    pub len: usize,
+    pub filled: bool,
+}
+
+// the text of `template` with each `${name}` replaced by its name, and the
+// fields where the names are
+pub fn expand(template: &str) -> (String, Vec<Field>) {
+    let mut text = String::new();
+    let mut fields = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find("${") {
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+        text.push_str(&rest[..open]);
+        let name = &rest[open + 2..close];
+        fields.push(Field { start: text.len(), len: name.len(), filled: false });
+        text.push_str(name);
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    (text, fields)
+}
+
+// moves the fields after `len` bytes inserted at `at`, which fill the one
+// they are typed into
+pub fn inserted(fields: &mut [Field], at: usize, len: usize) {
+    for field in fields.iter_mut() {
+        if at < field.start {
+            field.start += len;
+        } else if at <= field.start + field.len {
+            field.len += len;
+            field.filled = true;
+        }
+    }
+}
+
+// moves the fields after the byte at `at` is removed
+pub fn removed(fields: &mut [Field], at: usize) {
+    for field in fields.iter_mut() {
+        if at < field.start {
+            field.start -= 1;
+        } else if at < field.start + field.len {
+            field.len -= 1;
+        }
+    }
+}
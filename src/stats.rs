@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, the last one catching the rest.
+pub const LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// How long runs of one command took, counted in `LATENCY_BUCKETS`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    pub buckets: [usize; 6],
+    pub count: usize,
+    pub failures: usize,
+    pub total: Duration,
+}
+
+impl Histogram {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::default()
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    commands: usize,
+    failures: usize,
+    latency: BTreeMap<String, Histogram>,
+}
+
+/// Counts of the commands run in a terminal, and how long they took by
+/// command name.
+///
+/// Clones share the same counts, so an application can keep one to export
+/// them, as the `stats` built-in shows them.
+#[derive(Clone, Default)]
+pub struct SessionStats {
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl SessionStats {
+    pub fn record(&self, command: &str, success: bool, elapsed: Duration) {
+        let name = match command.split_whitespace().next() {
+            Some(name) => name,
+            None => return,
+        };
+        let mut stats = self.stats.lock().unwrap();
+        stats.commands += 1;
+        let histogram = stats.latency.entry(name.to_owned()).or_default();
+        let bucket = LATENCY_BUCKETS.iter().position(|&max| elapsed < max).unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.count += 1;
+        histogram.total += elapsed;
+        if !success {
+            histogram.failures += 1;
+            stats.failures += 1;
+        }
+    }
+
+    pub fn commands(&self) -> usize {
+        self.stats.lock().unwrap().commands
+    }
+
+    pub fn failures(&self) -> usize {
+        self.stats.lock().unwrap().failures
+    }
+
+    /// The share of commands that failed, from 0 to 1.
+    pub fn error_rate(&self) -> f64 {
+        let stats = self.stats.lock().unwrap();
+        if stats.commands == 0 { 0.0 } else { stats.failures as f64 / stats.commands as f64 }
+    }
+
+    /// Latencies by command name.
+    pub fn latency(&self) -> BTreeMap<String, Histogram> {
+        self.stats.lock().unwrap().latency.clone()
+    }
+
+    pub fn clear(&self) {
+        *self.stats.lock().unwrap() = Stats::default();
+    }
+
+    /// A table of the counts, as `stats` shows it.
+    pub fn summary(&self) -> String {
+        let latency = self.latency();
+        let mut lines = vec![format!("commands: {}, failed: {} ({:.1}%)",
+                                     self.commands(), self.failures(), self.error_rate() * 100.0)];
+        if !latency.is_empty() {
+            lines.extend(table("command", &latency));
+        }
+        lines.join("\n")
+    }
+}
+
+// the rows of `latency`, under a header naming its keys `title`
+pub(crate) fn table(title: &str, latency: &BTreeMap<String, Histogram>) -> Vec<String> {
+    let width = latency.keys().map(|name| name.len()).max().unwrap_or(0).max(title.len());
+    let mut lines = vec![format!("{:width$} {:>5} {:>6} {:>5} {:>5} {:>6} {:>4} {:>5} {:>5}  mean",
+                                 title, "count", "failed", "<1ms", "<10ms", "<100ms", "<1s", "<10s", ">10s",
+                                 width = width)];
+    for (name, histogram) in latency {
+        let b = histogram.buckets;
+        lines.push(format!("{:width$} {:>5} {:>6} {:>5} {:>5} {:>6} {:>4} {:>5} {:>5}  {:?}",
+                           name, histogram.count, histogram.failures, b[0], b[1], b[2], b[3], b[4], b[5],
+                           histogram.mean(), width = width));
+    }
+    lines
+}
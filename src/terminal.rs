@@ -1,86 +1,2539 @@
-use pancurses::{Window, initscr, noecho, Input, resize_term};
-use super::command::CommandHistory;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process;
+use std::str;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use pancurses::{Window, beep, chtype, curs_set, endwin, flash, initscr, newwin, noecho, noraw, raw, Input, resize_term};
+use pancurses::{has_colors, start_color, use_default_colors, init_pair, COLOR_PAIR, COLOR_GREEN, COLOR_RED, A_BOLD, A_DIM, A_NORMAL, A_REVERSE, A_UNDERLINE};
+use super::abbr::Abbreviations;
+use super::audit::{AuditEntry, AuditSink, Outcome};
+use super::auth::{self, Authenticator};
+use super::brackets;
+use super::builder::{Bell, CursorShape, Style, TerminalBuilder, Timestamps, Verbosity};
+use super::chain::{self, Op};
+use super::builder::{CommandHelp, CustomAction};
+use super::command::{CommandHistory, HistoryCipher, HistoryFilter};
+use super::completion::{self, Completer, Matcher, Policy};
+use super::copy_mode;
+use super::editor::{Debounced, EditorState, Highlighter, Highlights};
+use super::plugin::Plugin;
+use super::keymap::{self, Action, Key, Keymap, Lookup};
+use super::limits::{self, Limiter};
+use super::macros::Macros;
+use super::messages::Messages;
+use super::notify::{self, Level, Notification, Notifier};
+use super::markdown::{self, Line};
+use super::diff;
+use super::regex::Regex;
+use super::workdir;
+use super::normalize;
+use super::output;
+use super::paste;
+use super::parse;
+use super::pipe;
+use super::prompt::{CommandHook, PromptContext, PromptFn};
+use super::redirect;
+use super::registry::Param;
+use super::jobs::Jobs;
+use super::result::{CommandResult, Format, Pending};
+use super::scrollback::Scrollback;
+use super::snippet::{self, Field};
+#[cfg(feature = "latency-probe")]
+use super::probe;
+use super::stats::SessionStats;
+use super::width;
+
+#[cfg(unix)]
+extern "C" {
+    // from ncurses, which pancurses links
+    fn use_extended_names(enable: u8) -> i32;
+}
 
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
 struct Position(i32, i32);
 
-pub struct Terminal<F> {
-    prompt: String,
-    window: Window,
-    history: CommandHistory,
-    buf: Vec<u8>,
-    pos: i32,
-    process: F,
-}
+// output shown folded, as the summary, or expanded
+struct Fold {
+    text: String,
+    summary: String,
+    error: bool,
+    expanded: bool,
+}
+
+const ERROR_PAIR: i16 = 1;
+const ADDED_PAIR: i16 = 2;
+const REMOVED_PAIR: i16 = 3;
+
+// killed or copied texts kept for yanking
+const KILL_RING_SIZE: usize = 16;
+
+// bytes of output drawn between checks for Ctrl+C
+const OUTPUT_CHUNK: usize = 64 * 1024;
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+// how long a command runs before the spinner shows, and between its frames
+const SPINNER_DELAY: Duration = Duration::from_millis(200);
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+// how often the input loop checks for completed background jobs and
+// notifications
+const JOBS_INTERVAL: Duration = Duration::from_millis(250);
+// how often it checks for the styles of a background highlighter
+const HIGHLIGHT_INTERVAL: Duration = Duration::from_millis(10);
+// how long a paste may pause before it is taken as ended without its end
+// sequence
+const PASTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // whether a processor is running on this thread from the curses
+    // terminal, and whether it has suspended curses since
+    static UI: Cell<bool> = const { Cell::new(false) };
+    static SUSPENDED: Cell<bool> = const { Cell::new(false) };
+    // whether the cursor's shape was changed from the terminal's own
+    static CURSOR_SHAPED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Ends curses while `f` runs, handing it the real terminal, e.g. to run an
+/// interactive child process or a full-screen program, and redraws the
+/// screen once the command returns. Has that effect in processors run on
+/// the terminal's thread; elsewhere, as in plain mode or `run_threaded`,
+/// `f` just runs.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use term_rs::{suspend_ui, Terminal};
+///
+/// Terminal::run(|command: String| match command.as_str() {
+///     "top" => suspend_ui(|| Command::new("top").status()).map(|_| String::new()).map_err(|e| e.to_string()),
+///     _ => Ok(command),
+/// });
+/// ```
+pub fn suspend_ui<F, T>(f: F) -> T
+    where F: FnOnce() -> T {
+    if !UI.with(|ui| ui.get()) {
+        return f();
+    }
+    endwin();
+    if CURSOR_SHAPED.with(|shaped| shaped.get()) {
+        set_cursor_shape(None);
+    }
+    set_editing_modes(false);
+    SUSPENDED.with(|suspended| suspended.set(true));
+    f()
+}
+
+// whether the terminal takes DECSCUSR sequences, going by its name as
+// terminfo's `Ss` capability isn't read, see `use_extended_names`
+fn cursor_shapes_supported() -> bool {
+    let term = env::var("TERM").unwrap_or_default();
+    let known = ["xterm", "rxvt", "tmux", "alacritty", "foot", "kitty", "wezterm", "st-"];
+    env::var_os("VTE_VERSION").is_some() || known.iter().any(|name| term.starts_with(name))
+}
+
+// sets the cursor's shape, `None` for the terminal's own
+fn set_cursor_shape(shape: Option<CursorShape>) {
+    let code = match shape {
+        None => 0,
+        Some(CursorShape::Block) => 2,
+        Some(CursorShape::Underline) => 4,
+        Some(CursorShape::Bar) => 6,
+    };
+    let mut out = io::stdout();
+    let _ = write!(out, "\u{1b}[{} q", code);
+    let _ = out.flush();
+    CURSOR_SHAPED.with(|shaped| shaped.set(shape.is_some()));
+}
+
+// sets the terminal up for editing, or back as it was: turns its own
+// reordering of right-to-left text (ECMA-48 BDSM) off, as the editor places
+// each character in a cell itself, and bracketed paste mode on. Terminals
+// without either ignore them
+fn set_editing_modes(editing: bool) {
+    let mut out = io::stdout();
+    let _ = match editing {
+        true => write!(out, "\u{1b}[8l\u{1b}[?2004h"),
+        false => write!(out, "\u{1b}[8h\u{1b}[?2004l"),
+    };
+    let _ = out.flush();
+}
+
+// the built-ins of `working_directory`
+const DIRECTORY_BUILTINS: &[&str] = &["cd", "pwd"];
+// commands handled by the terminal itself before reaching the processor
+const BUILTINS: &[&str] = &["abbr", "edit", "fc", "grep", "history", "last", "macro", "save-output", "set", "stats",
+                             "wizard"];
+
+pub struct Terminal<F> {
+    prompt: String,
+    primary_prompt: String,
+    // the primary prompt without the job indicator
+    own_prompt: String,
+    job_indicator: bool,
+    window: Window,
+    // a cell of `window` keys are read from: never drawn on, so reading
+    // doesn't update the screen, which `read_key` does once keys typed
+    // ahead have been handled
+    input: Window,
+    history: CommandHistory,
+    buf: Vec<u8>,
+    pos: i32,
+    process: F,
+    macros: Macros,
+    // keys queued by macro playback, consumed before the keyboard
+    pending: VecDeque<Key>,
+    // text placed in the buffer at the next prompt
+    preload: Option<String>,
+    // the history entry to load once the line being accepted has run
+    get_next: Option<usize>,
+    keymap: Keymap,
+    // keys typed so far of an unfinished sequence
+    chord: Vec<Key>,
+    key_timeout: i32,
+    escape_timeout: i32,
+    escape_pasted_controls: bool,
+    confirm_paste: bool,
+    // sanitized text of the last paste not inserted yet, its lines going in
+    // one by one as each is run
+    pasted: String,
+    // screen cells covered by the status indicator
+    status_cells: Vec<(Position, chtype)>,
+    overwrite: bool,
+    bell: Bell,
+    // the cursor's shapes when inserting and overwriting, if the terminal
+    // can show them
+    cursor_shapes: Option<(CursorShape, CursorShape)>,
+    cursor_shape: Option<CursorShape>,
+    tab_width: usize,
+    expand_tabs: bool,
+    carriage_return_overwrites: bool,
+    max_output: Option<usize>,
+    max_input: Option<usize>,
+    fold_output: Option<usize>,
+    truncate_lines: bool,
+    timestamps: Timestamps,
+    show_timestamps: bool,
+    // when the command whose output is printed started
+    command_start: Instant,
+    paginate: bool,
+    page_lines: Option<usize>,
+    pager_keys: (char, char, char),
+    paginated_commands: BTreeMap<String, bool>,
+    // the registry's commands, completed along with the built-ins
+    command_names: Vec<String>,
+    // the session's working directory, if it keeps one
+    cwd: Option<PathBuf>,
+    // whether the output being printed is shown a page at a time
+    paging: bool,
+    // the output shown last, while it can be expanded or folded
+    fold: Option<Fold>,
+    scrollback: Scrollback,
+    stats: SessionStats,
+    limiter: Limiter,
+    audit: Option<Box<dyn AuditSink>>,
+    plugins: Vec<Box<dyn Plugin>>,
+    kill_ring: Vec<String>,
+    // output of the last command that wasn't a built-in
+    last_output: String,
+    transient_prompt: Option<String>,
+    output_prefix: String,
+    error_prefix: String,
+    error_attributes: chtype,
+    added_attributes: chtype,
+    removed_attributes: chtype,
+    prompt_fn: Option<PromptFn>,
+    failure_prompt_style: bool,
+    after_command: Option<CommandHook>,
+    status: PromptContext,
+    verbosity: Verbosity,
+    messages: Box<dyn Messages>,
+    history_file: Option<PathBuf>,
+    history_cipher: Option<Box<dyn HistoryCipher>>,
+    history_exclude: Option<HistoryFilter>,
+    abbreviations: Abbreviations,
+    auto_pairs: bool,
+    match_brackets: bool,
+    highlighter: Option<Box<dyn Highlighter>>,
+    async_highlighter: Option<Debounced>,
+    // the candidates the last Tab listed
+    candidates: Vec<String>,
+    // the rows of the last list of candidates, drawn right above the line
+    // unless output came since
+    listed: Vec<String>,
+    highlighted: Vec<Position>,
+    multiline: bool,
+    // lines already entered of a multi-line command, and the screen rows
+    // they occupy above the line being edited
+    block: Vec<String>,
+    block_rows: i32,
+    auto_indent: bool,
+    indent_after_brace: bool,
+    completers: Vec<Box<dyn Completer>>,
+    snippets: BTreeMap<String, String>,
+    actions: BTreeMap<&'static str, CustomAction>,
+    wizards: BTreeMap<String, Vec<Param>>,
+    // the param `wizard` is asking for, which completes instead of the completers
+    param: Option<Param>,
+    jobs: Jobs,
+    notifier: Option<Notifier>,
+    notifications_in_status: bool,
+    // the warning shown in the status area until acknowledged
+    unacknowledged: Option<Notification>,
+    timeout: Option<Duration>,
+    timeouts: BTreeMap<String, Duration>,
+    // the placeholders of the snippet being filled in, and the current one
+    fields: Vec<Field>,
+    field: usize,
+    matcher: Matcher,
+    complete_from_history: bool,
+    completion_policy: Policy,
+    // whether the previous key was a Tab
+    tabbed: bool,
+    // the text history-substring-search looks for, while it runs
+    substring_search: Option<String>,
+    // where the line being run was added to the history, to be timed; `fc`
+    // goes back through the entries before it
+    recorded: Option<usize>,
+    // only showing output, see `view`
+    read_only: bool,
+    command_help: Option<CommandHelp>,
+    // kept to unlock the terminal after `idle_lock` without a key
+    authenticator: Option<Box<dyn Authenticator>>,
+    login_attempts: usize,
+    idle_lock: Option<Duration>,
+    unlock: Option<Box<dyn Fn() -> bool>>,
+    last_key: Instant,
+}
+
+impl Terminal<()> {
+    pub fn builder() -> TerminalBuilder {
+        TerminalBuilder::new()
+    }
+}
+
+impl<F, R> Terminal<F>
+    where F: Fn(String) -> R,
+          R: Into<CommandResult> {
+    pub fn run(process: F) {
+        TerminalBuilder::new().run(process)
+    }
+
+    pub(crate) fn start(builder: TerminalBuilder, process: F) {
+        let mut t = Terminal::open(builder, process);
+        loop {
+            let line = t.input();
+            t.run_line(&line);
+        }
+    }
+
+    // sets up the screen and shows what comes before the first prompt
+    pub(crate) fn open(builder: TerminalBuilder, process: F) -> Self {
+        let mut abbreviations = Abbreviations::default();
+        let abbreviations_error = match builder.abbreviations_file {
+            Some(ref path) => abbreviations.load(path).err(),
+            None => None,
+        };
+        let mut history = CommandHistory::default();
+        let mut history_errors = Vec::new();
+        let loaded = builder.history_file.iter().map(|path| history.load(path, builder.history_cipher.as_deref()))
+            .collect::<Vec<_>>();
+        let imported = builder.history_imports.iter()
+            .map(|&(ref path, format)| match format {
+                Some(format) => history.import_as(path, format),
+                None => history.import(path),
+            })
+            .collect::<Vec<_>>();
+        for result in loaded.into_iter().chain(imported) {
+            match result {
+                Ok(0) => {}
+                Ok(skipped) => history_errors.push(builder.messages.history_lines_skipped(skipped)),
+                Err(e) => history_errors.push(e.to_string()),
+            }
+        }
+        let mut keymap = Keymap::default();
+        let mut keymap_errors: Vec<String> = builder.config_errors.iter()
+            .map(|e| builder.messages.error("config", e))
+            .collect();
+        let mut bindings = Vec::new();
+        for (keys, name) in builder.config_bindings {
+            let custom = builder.actions.keys().find(|&&n| n == name).map(|&n| Action::Custom(n));
+            match keymap::action_named(&name).or(custom) {
+                Some(action) => bindings.push((keys, action)),
+                None => keymap_errors.push(builder.messages.error(&format!("keymap: {}", keys),
+                                                                  &format!("no action named {}", name))),
+            }
+        }
+        bindings.extend(builder.bindings);
+        for (keys, action) in bindings {
+            if let Action::Custom(name) = action {
+                if !builder.actions.contains_key(name) {
+                    keymap_errors.push(builder.messages.error(&format!("keymap: {}", keys),
+                                                              &format!("no action named {}", name)));
+                    continue;
+                }
+            }
+            match keymap::parse(&keys) {
+                Ok(keys) => keymap.bind(keys, action),
+                Err(e) => keymap_errors.push(builder.messages.error(&format!("keymap: {}", keys), &e)),
+            }
+        }
+        // how long curses waits to tell ESC from the start of a function
+        // key sequence, read when it starts
+        env::set_var("ESCDELAY", builder.escape_timeout.as_millis().to_string());
+        #[cfg(unix)]
+        unsafe {
+            // leaves keys from non-standard terminfo capabilities, which
+            // pancurses can't report, as escape sequences for `read_csi`
+            use_extended_names(0);
+        }
+        let window = initscr();
+        window.keypad(true);
+        window.scrollok(true);
+        window.setscrreg(0, window.get_max_y());
+        noecho();
+        set_editing_modes(true);
+        let input = window.subwin(1, 1, 0, 0).expect("input window");
+        input.keypad(true);
+        input.untouch();
+        let mut t = Terminal {
+            prompt: builder.prompt.clone(),
+            primary_prompt: builder.prompt.clone(),
+            own_prompt: builder.prompt,
+            job_indicator: builder.job_indicator,
+            window,
+            input,
+            history,
+            buf: Vec::new(),
+            pos: 0,
+            process,
+            macros: Macros::default(),
+            pending: VecDeque::new(),
+            preload: None,
+            get_next: None,
+            keymap,
+            chord: Vec::new(),
+            key_timeout: builder.key_timeout.as_millis() as i32,
+            escape_timeout: builder.escape_timeout.as_millis() as i32,
+            escape_pasted_controls: builder.escape_pasted_controls,
+            confirm_paste: builder.confirm_paste,
+            pasted: String::new(),
+            status_cells: Vec::new(),
+            overwrite: false,
+            bell: builder.bell,
+            cursor_shapes: builder.cursor_shapes.filter(|_| cursor_shapes_supported()),
+            cursor_shape: None,
+            tab_width: builder.tab_width,
+            expand_tabs: builder.expand_tabs,
+            carriage_return_overwrites: builder.carriage_return_overwrites,
+            max_output: builder.max_output,
+            max_input: builder.max_input,
+            fold_output: builder.fold_output,
+            truncate_lines: builder.truncate_lines,
+            timestamps: builder.timestamps.unwrap_or_default(),
+            show_timestamps: builder.timestamps.is_some(),
+            command_start: Instant::now(),
+            paginate: builder.paginate,
+            page_lines: builder.page_lines,
+            pager_keys: builder.pager_keys,
+            paginated_commands: builder.paginated_commands,
+            command_names: builder.command_names,
+            cwd: if builder.working_directory { Some(env::current_dir().unwrap_or_default()) } else { None },
+            paging: false,
+            fold: None,
+            scrollback: builder.scrollback,
+            stats: builder.stats,
+            limiter: Limiter::new(builder.rate_limit, limits::key(None, None, None)),
+            audit: builder.audit,
+            plugins: builder.plugins,
+            kill_ring: Vec::new(),
+            last_output: String::new(),
+            transient_prompt: builder.transient_prompt,
+            output_prefix: builder.output_prefix,
+            error_prefix: builder.error_prefix,
+            error_attributes: A_BOLD,
+            added_attributes: A_BOLD,
+            removed_attributes: A_DIM,
+            prompt_fn: builder.prompt_fn,
+            failure_prompt_style: builder.failure_prompt_style,
+            after_command: builder.after_command,
+            status: PromptContext { last_success: true, commands: 0, user: None, session: None },
+            verbosity: builder.verbosity,
+            messages: builder.messages,
+            history_file: builder.history_file,
+            history_cipher: builder.history_cipher,
+            history_exclude: builder.history_exclude,
+            abbreviations,
+            auto_pairs: builder.auto_pairs,
+            match_brackets: builder.match_brackets,
+            highlighter: builder.highlighter,
+            async_highlighter: builder.async_highlighter.map(|(highlighter, delay)| Debounced::new(highlighter, delay)),
+            candidates: Vec::new(),
+            listed: Vec::new(),
+            highlighted: Vec::new(),
+            multiline: builder.multiline,
+            block: Vec::new(),
+            block_rows: 0,
+            auto_indent: builder.auto_indent,
+            indent_after_brace: builder.indent_after_brace,
+            completers: builder.completers,
+            snippets: builder.snippets,
+            actions: builder.actions,
+            wizards: builder.wizards,
+            param: None,
+            jobs: Jobs::default(),
+            notifier: builder.notifier,
+            notifications_in_status: builder.notifications_in_status,
+            unacknowledged: None,
+            timeout: builder.timeout,
+            timeouts: builder.timeouts,
+            fields: Vec::new(),
+            field: 0,
+            matcher: builder.matcher,
+            complete_from_history: builder.complete_from_history,
+            completion_policy: builder.completion_policy,
+            tabbed: false,
+            substring_search: None,
+            recorded: None,
+            read_only: false,
+            command_help: builder.command_help,
+            authenticator: None,
+            login_attempts: builder.login_attempts,
+            idle_lock: builder.idle_lock,
+            unlock: builder.unlock,
+            last_key: Instant::now(),
+        };
+        if builder.colors && has_colors() {
+            start_color();
+            use_default_colors();
+            init_pair(ERROR_PAIR, COLOR_RED, -1);
+            t.error_attributes = COLOR_PAIR(ERROR_PAIR as chtype);
+            init_pair(ADDED_PAIR, COLOR_GREEN, -1);
+            t.added_attributes = COLOR_PAIR(ADDED_PAIR as chtype);
+            init_pair(REMOVED_PAIR, COLOR_RED, -1);
+            t.removed_attributes = COLOR_PAIR(REMOVED_PAIR as chtype);
+        }
+        if let Some(ref authenticator) = builder.authenticator {
+            if !t.login(authenticator.as_ref(), builder.login_attempts) {
+                endwin();
+                set_editing_modes(false);
+                process::exit(1);
+            }
+        }
+        t.authenticator = builder.authenticator;
+        for e in history_errors {
+            let message = t.messages.error("history", &e);
+            t.window.addstr(format!("{}\n", message));
+        }
+        if !builder.history_imports.is_empty() {
+            t.save_history();
+        }
+        if let Some(e) = abbreviations_error {
+            let message = t.messages.error("abbr", &e.to_string());
+            t.window.addstr(format!("{}\n", message));
+        }
+        for e in keymap_errors {
+            t.window.addstr(format!("{}\n", e));
+        }
+        if let Some(banner) = builder.banner {
+            t.print_banner(&banner(), builder.banner_style);
+        }
+        for plugin in &t.plugins {
+            plugin.on_start(&t.status);
+        }
+        t
+    }
+
+    // shows the output as it comes, without a prompt or line editing, until
+    // every sender is dropped; the keys bound to copy mode still open it
+    pub(crate) fn view(mut self, output: Receiver<String>) {
+        self.read_only = true;
+        curs_set(0);
+        loop {
+            loop {
+                match output.try_recv() {
+                    Ok(text) => self.print_output(&text, false),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        curs_set(1);
+                        endwin();
+                        set_editing_modes(false);
+                        return;
+                    }
+                }
+            }
+            self.show_status();
+            let ch = match self.pending.pop_front() {
+                Some(ch) => Some(ch),
+                None => self.read_key(),
+            };
+            self.hide_status();
+            match ch {
+                Some(ch) => { self.handle_input(ch); }
+                None if !self.chord.is_empty() => { self.resolve_chord(true); }
+                None => {}
+            }
+        }
+    }
+
+    fn run_line(&mut self, line: &str) {
+        if !line.trim().is_empty() {
+            for plugin in &self.plugins {
+                plugin.on_submit(line);
+            }
+        }
+        let line_start = Instant::now();
+        let mut success = true;
+        for (op, command) in chain::split(line) {
+            if op == Op::IfSuccess && !success {
+                continue;
+            }
+            if let Err(e) = self.limiter.check(command, self.messages.as_ref()) {
+                self.print_output(&e, true);
+                self.audit(command, Outcome::Refused, Duration::default());
+                success = false;
+                continue;
+            }
+            let start = Instant::now();
+            self.command_start = start;
+            success = self.run_command(command);
+            self.audit(command, if success { Outcome::Success } else { Outcome::Failure }, start.elapsed());
+            self.limiter.record(command, success);
+            self.stats.record(command, success, start.elapsed());
+            if self.verbosity == Verbosity::Verbose && !command.trim().is_empty() {
+                let status = self.messages.command_status(command.trim(), success, start.elapsed());
+                self.fold = None;
+                self.window.attron(A_DIM);
+                self.window.addstr(format!("{}\n", status));
+                self.window.attroff(A_DIM);
+            }
+        }
+        if self.recorded.take().is_some() {
+            self.history.set_duration(line_start.elapsed());
+            self.save_history();
+        }
+        if line.trim().is_empty() {
+            return;
+        }
+        self.status.last_success = success;
+        self.status.commands += 1;
+        if let Some(ref hook) = self.after_command {
+            hook(line, success);
+        }
+    }
+
+    fn audit(&mut self, command: &str, outcome: Outcome, elapsed: Duration) {
+        let sink = match self.audit {
+            Some(ref sink) if !command.trim().is_empty() => sink,
+            _ => return,
+        };
+        if let Err(e) = sink.record(&AuditEntry::new(&self.status, command, outcome, elapsed)) {
+            let message = self.messages.error("audit", &e.to_string());
+            self.print_output(&message, true);
+        }
+    }
+
+    fn run_command(&mut self, line: &str) -> bool {
+        let line = parse::substitute(line, "LAST", &self.last_output);
+        // each stage of a pipe has its own redirection: the command's is
+        // ours, leaving the program no input, and the program's the shell's
+        let (stage, program) = pipe::split(&line);
+        let (command, target) = redirect::split(stage);
+        let command = if program.is_some() { command.trim_end() } else { command };
+        let mut result = self.dispatch(command);
+        let written = target.map(|target| redirect::write(target, &result.output));
+        if let Some(program) = program {
+            let input = if written.is_some() { "" } else { result.output.as_str() };
+            result = match pipe::filter(input, program) {
+                Ok(output) => CommandResult { output, ..result },
+                Err(e) => CommandResult::err(e),
+            };
+        }
+        for plugin in &self.plugins {
+            plugin.on_output(command, &result);
+        }
+        if !self.is_builtin(command) {
+            self.last_output = result.output.clone();
+        }
+        let name = command.split_whitespace().next().unwrap_or("");
+        self.paging = self.paginated_commands.get(name).cloned().unwrap_or(self.paginate);
+        if !result.error.is_empty() {
+            self.print_output(result.error.trim_end_matches('\n'), true);
+        }
+        let success = match written {
+            Some(Err(e)) => {
+                self.print_output(&e, true);
+                false
+            }
+            Some(Ok(())) if program.is_none() => result.success,
+            // built-ins with nothing to report stay silent, as do commands
+            // that only reported errors
+            _ if result.output.is_empty() && (self.is_builtin(command) || !result.error.is_empty()) => result.success,
+            _ if result.output.is_empty() && self.verbosity == Verbosity::Quiet => result.success,
+            _ => {
+                self.show_output(&result);
+                result.success
+            }
+        };
+        self.paging = false;
+        success
+    }
+
+    fn dispatch(&mut self, command: &str) -> CommandResult {
+        let argv = parse::split(command).unwrap_or_default();
+        match argv.first().map(|s| s.as_str()) {
+            Some("macro") => self.macro_builtin(&argv[1..]),
+            Some("abbr") => self.abbr_builtin(&argv[1..]),
+            Some("fc") => self.fc_builtin(&argv[1..], true),
+            Some("edit") => self.fc_builtin(&argv[1..], false),
+            Some("grep") => self.grep_builtin(&argv[1..]),
+            Some("history") => self.history_builtin(&argv[1..]),
+            Some("last") => CommandResult::ok(self.last_output.clone()),
+            Some("save-output") => self.save_output_builtin(&argv[1..]),
+            Some("set") => self.set_builtin(&argv[1..]),
+            Some("stats") => self.stats_builtin(),
+            Some("wizard") => self.wizard_builtin(&argv[1..]),
+            Some("cd") if self.cwd.is_some() => self.cd_builtin(&argv[1..]),
+            Some("pwd") if self.cwd.is_some() => {
+                CommandResult::ok(self.cwd.as_ref().map(|cwd| cwd.display().to_string()).unwrap_or_default())
+            }
+            _ => {
+                UI.with(|ui| ui.set(true));
+                let (user, process) = (self.status.user.as_deref(), &self.process);
+                let result: CommandResult = workdir::in_dir(self.cwd.as_deref(), || {
+                    auth::as_user(user, || process(command.to_owned()).into())
+                });
+                UI.with(|ui| ui.set(false));
+                if SUSPENDED.with(|suspended| suspended.replace(false)) {
+                    self.window.clearok(true);
+                    self.window.refresh();
+                    set_editing_modes(true);
+                }
+                match (&result.pending, &result.deferred) {
+                    (Some(pending), _) => self.wait(command, pending),
+                    (None, Some(deferred)) => {
+                        let id = self.jobs.add(command, deferred.clone());
+                        CommandResult::ok(self.messages.job_started(id))
+                    }
+                    (None, None) => result,
+                }
+            }
+        }
+    }
+
+    // waits for a command running on another thread, animating a spinner
+    // with the time it has taken on the line below the command once it is
+    // slow to come. Past its timeout, the user may keep waiting, move it to
+    // the background or cancel it.
+    fn wait(&mut self, command: &str, pending: &Pending) -> CommandResult {
+        let start = Instant::now();
+        if let Some(result) = pending.wait_timeout(SPINNER_DELAY) {
+            return result;
+        }
+        let timeout = command.split_whitespace().next()
+            .and_then(|name| self.timeouts.get(name))
+            .or(self.timeout.as_ref())
+            .cloned();
+        let mut deadline = timeout.map(|timeout| start + timeout);
+        let y = self.window.get_cur_y();
+        let mut frames = SPINNER.iter().cycle();
+        let result = loop {
+            self.window.mv(y, 0);
+            self.window.clrtobot();
+            if deadline.is_none_or(|deadline| Instant::now() < deadline) {
+                self.window.addstr(format!("{} {:.1}s", frames.next().unwrap(), start.elapsed().as_secs_f64()));
+                self.window.refresh();
+                match pending.wait_timeout(SPINNER_INTERVAL) {
+                    Some(result) => break result,
+                    None => continue,
+                }
+            }
+            // cut to the row, wrapping at the bottom would scroll it away
+            let columns = self.window.get_max_x() as usize - 1;
+            let message: String = self.messages.command_timed_out(start.elapsed()).chars().take(columns).collect();
+            self.window.addstr(message);
+            self.window.timeout(SPINNER_INTERVAL.as_millis() as i32);
+            let key = self.window.getch();
+            if let Some(result) = pending.try_take() {
+                break result;
+            }
+            match key {
+                Some(Input::Character('w')) => deadline = timeout.map(|timeout| Instant::now() + timeout),
+                Some(Input::Character('b')) => {
+                    let id = self.jobs.add(command, pending.clone());
+                    break CommandResult::ok(self.messages.job_started(id));
+                }
+                Some(Input::Character('c')) => {
+                    pending.cancel();
+                    break CommandResult::err(self.messages.command_cancelled());
+                }
+                _ => {}
+            }
+        };
+        self.window.mv(y, 0);
+        self.window.clrtobot();
+        result
+    }
+
+    // prints the results of the background jobs that completed above the
+    // line being edited
+    fn report_jobs(&mut self) {
+        if self.jobs.is_empty() {
+            return;
+        }
+        for (id, _, result) in self.jobs.finished() {
+            self.print_above(|t| {
+                let message = t.messages.job_completed(id);
+                t.window.addstr(format!("{}\n", message));
+                if !result.error.is_empty() {
+                    t.print_output(result.error.trim_end_matches('\n'), true);
+                }
+                if !result.output.is_empty() {
+                    t.show_output(&result);
+                }
+                t.refresh_prompt();
+            });
+        }
+    }
+
+    fn show_notifications(&mut self) {
+        let notifications = match self.notifier {
+            Some(ref notifier) => notifier.take(),
+            None => return,
+        };
+        for notification in notifications {
+            let line = self.messages.notification(notification.level, &notify::clock(notification.time),
+                                                  &notification.message);
+            let attributes = match notification.level {
+                Level::Info => A_NORMAL,
+                Level::Warn => A_BOLD,
+                Level::Error => self.error_attributes,
+            };
+            self.print_above(|t| {
+                t.window.attron(attributes);
+                t.window.addstr(format!("{}\n", line));
+                t.window.attroff(attributes);
+                t.scrollback.push(&line);
+            });
+            if self.notifications_in_status && notification.level >= Level::Warn {
+                self.unacknowledged = Some(notification);
+            }
+        }
+    }
+
+    // prints with `print` on the line being edited, then shows the prompt
+    // and the line again below
+    fn print_above<P>(&mut self, print: P)
+        where P: FnOnce(&mut Self) {
+        self.fold = None;
+        let pos = self.pos as usize;
+        let buf = self.buf.clone();
+        let fields = mem::take(&mut self.fields);
+        self.clear_line();
+        let y = self.window.get_cur_y();
+        self.window.mv(y, 0);
+        self.window.clrtoeol();
+        print(self);
+        self.print_prompt();
+        self.insert(String::from_utf8(buf).unwrap());
+        self.move_to(pos);
+        self.fields = fields;
+    }
+
+    // expands the output folded last, or folds it again, redrawing it in
+    // place of what it replaces
+    fn toggle_fold(&mut self) {
+        let mut fold = match self.fold.take() {
+            Some(fold) if self.block.is_empty() => fold,
+            _ => return self.ring(),
+        };
+        let old = if fold.expanded { &fold.text } else { &fold.summary };
+        let (old_lines, old_rows) = (old.split('\n').count(), self.rows(old));
+        fold.expanded = !fold.expanded;
+        self.scrollback.pop(old_lines);
+        self.scrollback.push(if fold.expanded { &fold.text } else { &fold.summary });
+        self.print_above(|t| {
+            let top = t.window.get_cur_y() - old_rows;
+            if top >= 0 {
+                t.window.mv(top, 0);
+                t.window.clrtobot();
+                t.draw_fold(&fold);
+            } else {
+                // it starts above the screen, whose rows are gone
+                t.repaint_scrollback();
+            }
+        });
+        self.fold = Some(fold);
+    }
+
+    fn draw_fold(&self, fold: &Fold) {
+        let (text, attributes) = match fold.expanded {
+            true if fold.error => (&fold.text, self.error_attributes),
+            true => (&fold.text, A_NORMAL),
+            false => (&fold.summary, A_DIM),
+        };
+        self.window.attron(attributes);
+        self.window.addstr(format!("{}\n", text));
+        self.window.attroff(attributes);
+    }
+
+    // redraws the rows above the cursor from the scrollback, without the
+    // styles they were shown in
+    fn repaint_scrollback(&mut self) {
+        let rows = self.window.get_cur_y();
+        let lines = self.scrollback.lines();
+        let (mut start, mut used) = (lines.len(), 0);
+        while start > 0 && used < rows {
+            start -= 1;
+            used += self.rows(&lines[start]);
+        }
+        self.window.clear();
+        self.window.mv(0, 0);
+        for line in &lines[start..] {
+            self.window.addstr(format!("{}\n", line));
+        }
+    }
+
+    // the rows `text` takes, a line filling a row being followed by an
+    // empty one
+    fn rows(&self, text: &str) -> i32 {
+        let column = self.window.get_max_x() as usize;
+        text.split('\n').map(|line| (line.chars().count() / column + 1) as i32).sum()
+    }
+
+    // `fc [N]` edits history entry N (default: the previous command) in
+    // $VISUAL/$EDITOR, `edit [N]` just loads it; either way the result is
+    // placed in the buffer at the next prompt to be run with Enter.
+    // `fc -l` lists the numbered history.
+    fn fc_builtin(&mut self, args: &[String], use_editor: bool) -> CommandResult {
+        // not the line running fc, nor anything after it
+        let count = self.recorded.unwrap_or(usize::MAX).min(self.history.len());
+        if args.first().map(|s| s.as_str()) == Some("-l") {
+            return CommandResult::ok(self.history.iter().take(count).enumerate()
+                .map(|(i, command)| format!("{:5}  {}", i + 1, command))
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+        let index = match args.first() {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= count => n - 1,
+                _ => return CommandResult::err(self.messages.no_history_entry(n)),
+            },
+            None if count > 0 => count - 1,
+            None => return CommandResult::err(self.messages.history_empty()),
+        };
+        let command = self.history.get(index).cloned().unwrap_or_default();
+        let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).ok();
+        let command = match editor {
+            Some(ref editor) if use_editor => match self.edit_externally(editor, &command) {
+                Ok(edited) => edited,
+                Err(e) => return CommandResult::err(self.messages.error("fc", &e)),
+            },
+            _ => command,
+        };
+        self.preload = Some(command);
+        CommandResult::ok("")
+    }
+
+    fn edit_externally(&mut self, editor: &str, text: &str) -> Result<String, String> {
+        let path = temp_file(text).map_err(|e| e.to_string())?;
+        let argv = parse::split(editor).map_err(|e| e.to_string())?;
+        if argv.is_empty() {
+            return Err("empty editor command".to_owned());
+        }
+        endwin();
+        if CURSOR_SHAPED.with(|shaped| shaped.get()) {
+            set_cursor_shape(None);
+        }
+        set_editing_modes(false);
+        let status = process::Command::new(&argv[0]).args(&argv[1..]).arg(&path).status();
+        self.window.refresh();
+        set_editing_modes(true);
+        let edited = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        match status {
+            Ok(ref status) if status.success() => {}
+            Ok(status) => return Err(format!("{} exited with {}", editor, status)),
+            Err(e) => return Err(format!("{}: {}", editor, e)),
+        }
+        edited.map(|s| s.trim_end_matches('\n').to_owned()).map_err(|e| e.to_string())
+    }
+
+    // `save-output <file>` writes the scrollback to a file, `save-output -l
+    // <file>` just the last command's output
+    fn save_output_builtin(&mut self, args: &[String]) -> CommandResult {
+        let (text, path) = match args {
+            [flag, path] if flag == "-l" => (self.last_output.clone(), path),
+            [path] => (self.scrollback.lines().join("\n"), path),
+            _ => return CommandResult::err(self.messages.usage("save-output")),
+        };
+        match fs::write(path, text + "\n") {
+            Ok(()) => CommandResult::ok(""),
+            Err(e) => CommandResult::err(self.messages.error(&format!("save-output: {}", path), &e.to_string())),
+        }
+    }
+
+    // `grep [-i] <pattern>` prints the lines of the last command's output
+    // the regular expression matches, failing if none does
+    fn grep_builtin(&mut self, args: &[String]) -> CommandResult {
+        let (pattern, ignore_case) = match args {
+            [flag, pattern] if flag == "-i" => (pattern, true),
+            [pattern] => (pattern, false),
+            _ => return CommandResult::err(self.messages.usage("grep")),
+        };
+        let regex = match Regex::new(pattern, ignore_case) {
+            Ok(regex) => regex,
+            Err(e) => return CommandResult::err(self.messages.error(&format!("grep: {}", pattern), &e)),
+        };
+        let lines: Vec<&str> = self.last_output.lines().filter(|line| regex.is_match(line)).collect();
+        match lines.is_empty() {
+            true => CommandResult::err(""),
+            false => CommandResult::ok(lines.join("\n")),
+        }
+    }
+
+    // `history stats [count]` lists the commands run most often, 10 unless
+    // `count` is given, with how long they took on average
+    fn history_builtin(&mut self, args: &[String]) -> CommandResult {
+        let count = match args {
+            [stats] if stats == "stats" => 10,
+            [stats, count] if stats == "stats" => match count.parse() {
+                Ok(count) => count,
+                Err(_) => return CommandResult::err(self.messages.usage("history")),
+            },
+            _ => return CommandResult::err(self.messages.usage("history")),
+        };
+        let usage = self.history.top_commands(count);
+        let width = usage.iter().map(|usage| usage.name.len()).max().unwrap_or(0).max("command".len());
+        let mut lines = vec![format!("{:width$} {:>5}  mean", "command", "count", width = width)];
+        for usage in usage {
+            let mean = if usage.timed == 0 { "-".to_owned() } else { format!("{:?}", usage.mean()) };
+            lines.push(format!("{:width$} {:>5}  {}", usage.name, usage.count, mean, width = width));
+        }
+        CommandResult::ok(lines.join("\n"))
+    }
+
+    fn stats_builtin(&self) -> CommandResult {
+        #[allow(unused_mut)]
+        let mut summary = self.stats.summary();
+        #[cfg(feature = "latency-probe")]
+        {
+            if let Some(input) = probe::summary() {
+                summary = format!("{}\n{}", summary, input);
+            }
+        }
+        CommandResult::ok(summary)
+    }
+
+    fn is_builtin(&self, command: &str) -> bool {
+        command.split_whitespace().next()
+            .is_some_and(|name| BUILTINS.contains(&name) || self.cwd.is_some() && DIRECTORY_BUILTINS.contains(&name))
+    }
+
+    // `cd [dir]` changes the session's working directory, to the home
+    // directory without one
+
+    fn cd_builtin(&mut self, args: &[String]) -> CommandResult {
+        let target = match args {
+            [] => None,
+            [dir] => Some(dir.as_str()),
+            _ => return CommandResult::err(self.messages.usage("cd")),
+        };
+        let cwd = self.cwd.clone().unwrap_or_default();
+        match workdir::change(&cwd, target) {
+            Ok(dir) => {
+                self.cwd = Some(dir);
+                CommandResult::ok("")
+            }
+            Err(e) => CommandResult::err(self.messages.error(&format!("cd: {}", target.unwrap_or("~")), &e.to_string())),
+        }
+    }
+
+    fn set_builtin(&mut self, args: &[String]) -> CommandResult {
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        self.verbosity = match args.as_slice() {
+            ["output", "quiet"] => Verbosity::Quiet,
+            ["output", "normal"] => Verbosity::Normal,
+            ["output", "verbose"] => Verbosity::Verbose,
+            _ => return CommandResult::err(self.messages.usage("set")),
+        };
+        CommandResult::ok("")
+    }
+
+    // `wizard <command>` asks for each param of the command, then loads
+    // the assembled command at the next prompt and runs it
+    fn wizard_builtin(&mut self, args: &[String]) -> CommandResult {
+        let command = match args {
+            [command] => command,
+            _ => return CommandResult::err(self.messages.usage("wizard")),
+        };
+        let params = match self.wizards.get(command) {
+            Some(params) => params.clone(),
+            None => return CommandResult::err(self.messages.not_found("wizard", command)),
+        };
+        let started = self.messages.wizard_started(command);
+        self.window.addstr(format!("{}\n", started));
+        let mut line = parse::quote(command);
+        for param in params {
+            let value = loop {
+                let value = self.read_param(&param);
+                if value.is_empty() {
+                    return CommandResult::err(self.messages.wizard_cancelled());
+                }
+                match param.check(&value) {
+                    Ok(()) => break value,
+                    Err(e) => {
+                        let message = self.messages.error(param.name(), &e);
+                        self.print_output(&message, true);
+                    }
+                }
+            };
+            line.push(' ');
+            line.push_str(&parse::quote(&value));
+        }
+        self.preload = Some(line);
+        self.pending.push_back(Key::Input(Input::Character('\n')));
+        CommandResult::ok("")
+    }
+
+    // reads the value of `param` with the line editor
+    fn read_param(&mut self, param: &Param) -> String {
+        let prompt = mem::replace(&mut self.prompt, self.messages.wizard_field(param.name(), param.help_text()));
+        self.param = Some(param.clone());
+        self.print_prompt();
+        self.pos = 0;
+        self.last_key = Instant::now();
+        let value = loop {
+            self.show_status();
+            let ch = match self.pending.pop_front() {
+                Some(ch) => Some(ch),
+                None => self.read_key(),
+            };
+            self.hide_status();
+            let line = match ch {
+                Some(ch) => {
+                    self.clear_highlights();
+                    self.handle_input(ch)
+                }
+                None if !self.chord.is_empty() => self.resolve_chord(true),
+                None if self.idle() => {
+                    self.lock();
+                    continue;
+                }
+                None => continue,
+            };
+            if let Some(line) = line {
+                break line;
+            }
+        };
+        self.param = None;
+        self.prompt = prompt;
+        value
+    }
+
+    fn abbr_builtin(&mut self, args: &[String]) -> CommandResult {
+        let result = match args.first().map(|s| s.as_str()) {
+            Some("add") if args.len() > 2 => {
+                self.abbreviations.add(&args[1], &args[2..].join(" ")).map(|_| CommandResult::ok(""))
+            }
+            Some("erase") if args.len() == 2 => self.abbreviations.erase(&args[1]).map(|found| {
+                if found {
+                    CommandResult::ok("")
+                } else {
+                    CommandResult::err(self.messages.not_found("abbr", &args[1]))
+                }
+            }),
+            Some("list") => Ok(CommandResult::ok(self.abbreviations.iter()
+                .map(|(name, expansion)| format!("{} {}", name, expansion))
+                .collect::<Vec<_>>()
+                .join("\n"))),
+            _ => Ok(CommandResult::err(self.messages.usage("abbr"))),
+        };
+        result.unwrap_or_else(|e| CommandResult::err(self.messages.error("abbr", &e.to_string())))
+    }
+
+    fn macro_builtin(&mut self, args: &[String]) -> CommandResult {
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        match args.as_slice() {
+            ["save", name] => {
+                if self.macros.save(name) {
+                    CommandResult::ok(self.messages.macro_saved(name))
+                } else {
+                    CommandResult::err(self.messages.nothing_recorded())
+                }
+            }
+            ["play", name] => match self.macros.get(name) {
+                Some(keys) => {
+                    self.pending.extend(keys.iter().cloned());
+                    CommandResult::ok("")
+                }
+                None => CommandResult::err(self.messages.not_found("macro", name)),
+            },
+            ["erase", name] => {
+                if self.macros.remove(name) {
+                    CommandResult::ok("")
+                } else {
+                    CommandResult::err(self.messages.not_found("macro", name))
+                }
+            }
+            ["list"] => CommandResult::ok(self.macros.names().cloned().collect::<Vec<_>>().join("\n")),
+            _ => CommandResult::err(self.messages.usage("macro")),
+        }
+    }
+
+    fn print_output(&mut self, text: &str, error: bool) {
+        self.fold = None;
+        let kept = match self.max_output {
+            Some(max) => output::truncate(text, max),
+            None => text,
+        };
+        // curses clears the rest of the line on \n, so after a \r it would
+        // erase what was printed
+        let mut shown = output::normalize_line_endings(kept, self.carriage_return_overwrites);
+        // curses itself only knows tab stops every 8 columns
+        if self.expand_tabs || self.tab_width != 8 {
+            shown = output::expand_tabs(&shown, self.tab_width);
+        }
+        if error && !self.error_prefix.is_empty() {
+            shown = shown.split('\n').map(|line| format!("{}{}", self.error_prefix, line)).collect::<Vec<_>>().join("\n");
+        }
+        if self.show_timestamps {
+            let stamp = output::timestamp(self.timestamps, self.command_start);
+            shown = shown.split('\n').map(|line| format!("{}{}", stamp, line)).collect::<Vec<_>>().join("\n");
+        }
+        if self.truncate_lines {
+            let columns = (self.window.get_max_x() as usize).saturating_sub(self.output_prefix.chars().count()).max(1);
+            shown = output::truncate_lines(&shown, columns);
+        }
+        if !self.output_prefix.is_empty() {
+            shown = output::prefix_lines(&shown, &self.output_prefix, self.window.get_max_x() as usize);
+        }
+        let lines = shown.split('\n').count();
+        if self.fold_output.is_some_and(|max| lines > max) && kept.len() == text.len() {
+            let key = self.keymap.keys_for(Action::ToggleFold).map(keymap::describe);
+            let summary = self.messages.output_folded(lines, key.as_deref());
+            let fold = Fold { text: shown, summary, error, expanded: false };
+            self.draw_fold(&fold);
+            self.scrollback.push(&fold.summary);
+            self.fold = Some(fold);
+            return;
+        }
+        if error {
+            self.window.attron(self.error_attributes);
+        }
+        let mut interrupted = false;
+        if self.paging && self.rows(&shown) as usize > self.page_rows() {
+            let (drawn, quit) = self.print_paged(&shown, error);
+            self.window.attroff(self.error_attributes);
+            // quitting leaves the cursor on the row after the last shown
+            if !quit {
+                self.window.addstr("\n");
+            }
+            self.scrollback.push(&shown[..drawn]);
+            return;
+        }
+        let chunks = output::chunks(&shown, OUTPUT_CHUNK);
+        let mut drawn = 0;
+        if chunks.len() > 1 {
+            // let Ctrl+C through as a key, and hide the cursor rushing
+            // through the output
+            raw();
+            self.window.timeout(0);
+            curs_set(0);
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            // addstr, as printw would take % in the text for conversions
+            self.window.addstr(chunk);
+            drawn += chunk.len();
+            if i + 1 == chunks.len() {
+                break;
+            }
+            self.window.refresh();
+            while let Some(input) = self.window.getch() {
+                if input == Input::Character('\u{3}') {
+                    interrupted = true;
+                    break;
+                }
+                self.pending.push_back(Key::Input(input));
+            }
+            if interrupted {
+                break;
+            }
+        }
+        if chunks.len() > 1 {
+            noraw();
+            curs_set(1);
+        }
+        self.window.attroff(self.error_attributes);
+        self.window.addstr("\n");
+        self.scrollback.push(&shown[..drawn]);
+        let notice = if interrupted {
+            self.messages.output_interrupted()
+        } else if kept.len() < text.len() {
+            self.messages.output_truncated(kept.len(), text.len())
+        } else {
+            return;
+        };
+        self.window.addstr(format!("{}\n", notice));
+        self.scrollback.push(&notice);
+    }
+
+    // the rows of output paging shows at a time
+    fn page_rows(&self) -> usize {
+        self.page_lines.unwrap_or(self.window.get_max_y() as usize - 1).max(1)
+    }
+
+    // prints `text` a page at a time, returning how much of it was shown
+    // and whether the user quit
+    fn print_paged(&mut self, text: &str, error: bool) -> (usize, bool) {
+        let mut left = self.page_rows();
+        let mut drawn = 0;
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.window.addstr("\n");
+            }
+            if left == 0 {
+                self.window.attroff(self.error_attributes);
+                match self.more() {
+                    Some(rows) => left = rows,
+                    None => return (drawn, true),
+                }
+                if error {
+                    self.window.attron(self.error_attributes);
+                }
+            }
+            self.window.addstr(line);
+            drawn += line.len() + if i > 0 { 1 } else { 0 };
+            left = left.saturating_sub(self.rows(line) as usize);
+        }
+        (drawn, false)
+    }
+
+    // asks for more at the start of a row, which it leaves empty: the rows
+    // to show next, or None to stop
+    fn more(&mut self) -> Option<usize> {
+        let (next_page, next_line, quit) = self.pager_keys;
+        let prompt = self.messages.more();
+        self.window.attron(A_REVERSE);
+        self.window.addstr(&prompt);
+        self.window.attroff(A_REVERSE);
+        self.window.timeout(-1);
+        let rows = loop {
+            match self.window.getch() {
+                Some(Input::Character(c)) if c == next_page => break Some(self.page_rows()),
+                Some(Input::Character(c)) if c == next_line => break Some(1),
+                Some(Input::KeyEnter) if next_line == '\n' => break Some(1),
+                Some(Input::Character(c)) if c == quit => break None,
+                _ => {}
+            }
+        };
+        let y = self.window.get_cur_y();
+        self.window.mv(y, 0);
+        self.window.clrtoeol();
+        rows
+    }
+
+    // prints the output of `result` the way its format says
+    fn show_output(&mut self, result: &CommandResult) {
+        match result.format {
+            Format::Plain => self.print_output(&result.output, !result.success),
+            Format::Markdown => {
+                let lines = markdown::render(&result.output, self.window.get_max_x() as usize);
+                self.print_styled(&lines);
+            }
+            Format::Diff => {
+                let lines = diff::render(&result.output);
+                self.print_styled(&lines);
+            }
+        }
+    }
+
+    // prints lines of styled pieces, each line after the output prefix
+    fn print_styled(&mut self, lines: &[Line]) {
+        self.fold = None;
+        // a full row would be followed by an empty one
+        let columns = (self.window.get_max_x() as usize).saturating_sub(self.output_prefix.chars().count() + 1).max(1);
+        let mut lines = lines.to_vec();
+        if self.show_timestamps {
+            let stamp = output::timestamp(self.timestamps, self.command_start);
+            for line in &mut lines {
+                line.insert(0, (stamp.clone(), Style::Dim));
+            }
+        }
+        if self.truncate_lines {
+            lines = lines.iter().map(|line| markdown::truncate(line, columns)).collect();
+        }
+        let mut left = self.page_rows();
+        let mut shown = lines.len();
+        let paged = self.paging && lines.len() > left;
+        for (i, line) in lines.iter().enumerate() {
+            if paged && left == 0 {
+                match self.more() {
+                    Some(rows) => left = rows,
+                    None => {
+                        shown = i;
+                        break;
+                    }
+                }
+            }
+            left = left.saturating_sub(1);
+            self.window.addstr(&self.output_prefix);
+            for (text, style) in line {
+                let attributes = self.attributes(*style);
+                self.window.attron(attributes);
+                self.window.addstr(text);
+                self.window.attroff(attributes);
+            }
+            self.window.addstr("\n");
+        }
+        let text = markdown::text(&lines[..shown]).split('\n').map(|line| format!("{}{}", self.output_prefix, line)).collect::<Vec<_>>();
+        self.scrollback.push(&text.join("\n"));
+    }
+
+    // asks for the secret until the authenticator accepts it, false once
+    // the attempts are used up
+    fn login(&mut self, authenticator: &dyn Authenticator, attempts: usize) -> bool {
+        for _ in 0..attempts {
+            self.window.addstr(authenticator.prompt());
+            let secret = self.read_secret();
+            self.window.addstr("\n");
+            match authenticator.authenticate(&secret) {
+                // unlocking takes the user who logged in
+                Some(user) if self.status.user.as_ref().is_none_or(|u| *u == user) => {
+                    self.status.user = Some(user);
+                    return true;
+                }
+                _ => {}
+            }
+            let message = self.messages.login_failed();
+            self.print_output(&message, true);
+        }
+        let message = self.messages.too_many_attempts();
+        self.print_output(&message, true);
+        self.window.refresh();
+        false
+    }
+
+    // whether the terminal has waited for a key long enough to lock
+    fn idle(&self) -> bool {
+        self.idle_lock.is_some_and(|idle| self.last_key.elapsed() >= idle)
+    }
+
+    // clears the screen until the user unlocks the terminal, exiting if
+    // they can't, then draws the output and the line being edited again
+    fn lock(&mut self) {
+        let pos = self.pos as usize;
+        // what unlocking prints isn't drawn again
+        let (lines, _) = self.scrollback.usage();
+        self.window.clear();
+        self.window.mv(0, 0);
+        let any_key = self.unlock.is_none() && self.authenticator.is_none();
+        self.window.addstr(format!("{}\n", self.messages.locked(any_key)));
+        self.window.refresh();
+        let unlocked = if let Some(ref unlock) = self.unlock {
+            endwin();
+            if CURSOR_SHAPED.with(|shaped| shaped.get()) {
+                set_cursor_shape(None);
+            }
+            set_editing_modes(false);
+            let unlocked = unlock();
+            self.window.refresh();
+            set_editing_modes(true);
+            self.update_cursor_shape();
+            unlocked
+        } else if let Some(authenticator) = self.authenticator.take() {
+            let unlocked = self.login(authenticator.as_ref(), self.login_attempts);
+            self.authenticator = Some(authenticator);
+            unlocked
+        } else {
+            self.window.timeout(-1);
+            self.window.getch();
+            true
+        };
+        if !unlocked {
+            endwin();
+            set_editing_modes(false);
+            process::exit(1);
+        }
+        self.scrollback.pop(self.scrollback.usage().0.saturating_sub(lines));
+        self.window.mv(self.window.get_max_y() - 1, 0);
+        self.repaint_scrollback();
+        self.print_prompt();
+        self.window.addstr(drawable(valid_prefix(&self.buf)));
+        self.pos = self.buf.len() as i32;
+        self.move_to(pos);
+        self.last_key = Instant::now();
+    }
+
+    // reads a line showing a star for each character
+    fn read_secret(&mut self) -> String {
+        let mut secret = String::new();
+        self.window.timeout(-1);
+        loop {
+            match self.window.getch() {
+                Some(Input::Character('\n')) | Some(Input::Character('\r')) | None => return secret,
+                Some(Input::KeyBackspace) | Some(Input::Character('\u{7f}')) | Some(Input::Character('\u{8}'))
+                    if secret.pop().is_some() => {
+                    let (y, x) = self.window.get_cur_yx();
+                    self.window.mv(y, x - 1);
+                    self.window.delch();
+                }
+                Some(Input::Character(c)) if !c.is_control() => {
+                    secret.push(c);
+                    self.window.addch('*');
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn attributes(&self, style: Style) -> chtype {
+        match style {
+            Style::Plain => A_NORMAL,
+            Style::Bold => A_BOLD,
+            Style::Dim => A_DIM,
+            Style::Underline => A_UNDERLINE,
+            Style::Reverse => A_REVERSE,
+            Style::Error => self.error_attributes,
+            Style::Added => self.added_attributes,
+            Style::Removed => self.removed_attributes,
+        }
+    }
+
+    fn print_banner(&mut self, banner: &str, style: Style) {
+        // a full row followed by a newline would leave an empty row
+        let text = output::wrap_words(banner.trim_end_matches('\n'), self.window.get_max_x() as usize - 1);
+        let attributes = self.attributes(style);
+        self.window.attron(attributes);
+        self.window.addstr(format!("{}\n", text));
+        self.window.attroff(attributes);
+        self.scrollback.push(&text);
+    }
+
+    // renders the primary prompt again, with the job indicator
+    fn refresh_prompt(&mut self) {
+        if let Some(ref prompt) = self.prompt_fn {
+            self.own_prompt = prompt(&self.status);
+        }
+        let indicator = match self.jobs.len() {
+            running if self.job_indicator && running > 0 => self.messages.jobs_running(running),
+            _ => String::new(),
+        };
+        let primary = self.prompt == self.primary_prompt;
+        self.primary_prompt = format!("{}{}", indicator, self.own_prompt);
+        if primary {
+            self.prompt = self.primary_prompt.clone();
+        }
+    }
+
+    // shows the cursor's shape for the mode, again after programs given the
+    // terminal may have changed it
+    fn update_cursor_shape(&mut self) {
+        let (insert, overwrite) = match self.cursor_shapes {
+            Some(shapes) => shapes,
+            None => return,
+        };
+        let shape = if self.overwrite { overwrite } else { insert };
+        if self.cursor_shape != Some(shape) || !CURSOR_SHAPED.with(|shaped| shaped.get()) {
+            set_cursor_shape(Some(shape));
+            self.cursor_shape = Some(shape);
+        }
+    }
+
+    fn print_prompt(&self) {
+        let failed = self.failure_prompt_style && !self.status.last_success && self.prompt == self.primary_prompt;
+        if failed {
+            self.window.attron(self.error_attributes);
+        }
+        self.window.addstr(self.prompt.as_str());
+        if failed {
+            self.window.attroff(self.error_attributes);
+        }
+    }
+
+    fn input(&mut self) -> String {
+        self.update_cursor_shape();
+        self.refresh_prompt();
+        self.prompt = self.primary_prompt.clone();
+        self.print_prompt();
+        self.pos = 0;
+        self.last_key = Instant::now();
+        if let Some(text) = self.preload.take() {
+            self.load_text(&text);
+        }
+        #[cfg(feature = "latency-probe")]
+        let mut drawn = Instant::now();
+        loop {
+            self.report_jobs();
+            self.show_notifications();
+            self.show_status();
+            let ch = match self.pending.pop_front() {
+                Some(ch) => Some(ch),
+                None => self.read_key(),
+            };
+            #[cfg(feature = "latency-probe")]
+            let (read, browsed) = (Instant::now(), self.history.position());
+            self.hide_status();
+            let line = match ch {
+                Some(ch) => {
+                    self.clear_highlights();
+                    let line = self.handle_input(ch);
+                    self.report_key(ch);
+                    line
+                }
+                // the next key of a sequence didn't come in time
+                None if !self.chord.is_empty() => self.resolve_chord(true),
+                None if self.idle() => {
+                    self.lock();
+                    continue;
+                }
+                None => {
+                    if let Some(ranges) = self.async_highlighter.as_mut().and_then(|h| h.poll()) {
+                        self.clear_highlights();
+                        self.apply_highlights(ranges);
+                        self.highlight_search();
+                        self.highlight_brackets();
+                        self.highlight_fields();
+                    }
+                    continue;
+                }
+            };
+            if let Some(line) = line {
+                if self.verbosity == Verbosity::Quiet {
+                    // not echoed, so taking no rows
+                    self.block.push(line);
+                } else {
+                    self.push_block_line(line);
+                }
+                if self.multiline && self.continue_input() {
+                    continue;
+                }
+                self.prompt = self.primary_prompt.clone();
+                let command = self.block.join("\n");
+                self.block.clear();
+                self.block_rows = 0;
+                let excluded = self.history_exclude.as_ref().is_some_and(|exclude| exclude(&command));
+                self.recorded = if command.trim().is_empty() || excluded { None } else { Some(self.history.len()) };
+                if self.recorded.is_some() {
+                    // saved once it has run, with how long it took
+                    self.history.add_command(command.clone());
+                }
+                if let Some(next) = self.get_next.take() {
+                    self.preload = self.history.get(next).cloned();
+                    self.history.browse_from(next);
+                }
+                return command;
+            }
+            self.highlight_line();
+            self.highlight_search();
+            self.highlight_brackets();
+            self.highlight_fields();
+            #[cfg(feature = "latency-probe")]
+            {
+                self.window.refresh();
+                let kind = match () {
+                    _ if self.history.position() != browsed => "history",
+                    _ if read - drawn < Duration::from_millis(1) => "paste",
+                    _ if self.buf.len() >= probe::LONG_LINE => "long-line",
+                    _ => "key",
+                };
+                probe::record(kind, read.elapsed());
+                drawn = Instant::now();
+            }
+        }
+    }
+
+    // tells the plugins about a key handled
+    fn report_key(&self, key: Key) {
+        if self.plugins.is_empty() {
+            return;
+        }
+        let (key, state) = (keymap::describe(&[key]), self.editor_state());
+        for plugin in &self.plugins {
+            plugin.on_key(&key, &state);
+        }
+    }
+
+    // waits for a key, only `key_timeout` long in the middle of a sequence
+    fn read_key(&mut self) -> Option<Key> {
+        let timeout = match self.chord.is_empty() {
+            false => self.key_timeout,
+            // wakes up to report background jobs and notifications, or
+            // to show output being viewed
+            true if self.async_highlighter.as_ref().is_some_and(|h| h.pending()) => {
+                HIGHLIGHT_INTERVAL.as_millis() as i32
+            }
+            true if !self.jobs.is_empty() || self.notifier.is_some() || self.read_only => JOBS_INTERVAL.as_millis() as i32,
+            true => -1,
+        };
+        // wakes up to lock the terminal once idle long enough
+        let timeout = match self.idle_lock.map(|idle| idle.saturating_sub(self.last_key.elapsed())) {
+            Some(left) if timeout < 0 || (left.as_millis() as i32) < timeout => left.as_millis() as i32,
+            _ => timeout,
+        };
+        // keys already typed, as when pasting, are handled before the screen
+        // is updated, once for all of them. Resizing may have marked the
+        // input cell as changed, which reading would draw
+        self.input.untouch();
+        self.input.timeout(0);
+        let input = match self.input.getch() {
+            Some(input) => input,
+            None => {
+                self.window.refresh();
+                self.input.timeout(timeout);
+                self.input.getch()?
+            }
+        };
+        self.last_key = Instant::now();
+        #[cfg(windows)]
+        {
+            if let Some(key) = keymap::decode_pdcurses(input) {
+                return Some(key);
+            }
+        }
+        if input != Input::Character('\u{1b}') {
+            return Some(Key::Input(input));
+        }
+        // a character right after ESC is an Alt combination
+        self.input.timeout(self.escape_timeout);
+        match self.input.getch() {
+            Some(Input::Character('[')) => Some(self.read_csi()),
+            Some(Input::Character(c)) if c != '\u{1b}' => Some(Key::Alt(c)),
+            Some(other) => {
+                self.pending.push_back(Key::Input(other));
+                Some(Key::Input(input))
+            }
+            None => Some(Key::Input(input)),
+        }
+    }
+
+    // reads the rest of an `ESC [` sequence curses didn't recognize, which
+    // is how xterm-like terminals send Ctrl+arrows and other modified keys,
+    // and some send Home and End whatever terminfo says. Sequences that aren't keys are dropped rather than typed.
+    fn read_csi(&mut self) -> Key {
+        let mut params = String::new();
+        loop {
+            match self.input.getch() {
+                Some(Input::Character(c)) if ('0'..='?').contains(&c) => params.push(c),
+                Some(Input::Character('~')) if params == paste::START => return self.read_paste(),
+                Some(Input::Character(c)) if ('@'..='~').contains(&c) => {
+                    return keymap::decode_csi(&params, c).unwrap_or(Key::Input(Input::Unknown(0)));
+                }
+                _ if params.is_empty() => return Key::Alt('['),
+                _ => return Key::Input(Input::Unknown(0)),
+            }
+        }
+    }
+
+    // reads pasted text up to the sequence ending it, keeping it sanitized
+    // for `paste`. Keys curses decoded from escape sequences in it are dropped
+    fn read_paste(&mut self) -> Key {
+        let mut text = String::new();
+        self.input.timeout(PASTE_TIMEOUT.as_millis() as i32);
+        while let Some(input) = self.input.getch() {
+            if let Input::Character(c) = input {
+                text.push(c);
+                if text.ends_with(paste::END) {
+                    text.truncate(text.len() - paste::END.len());
+                    break;
+                }
+            }
+        }
+        let text = paste::sanitize(&text, self.tab_width, self.escape_pasted_controls);
+        if !self.confirm_paste || !text.contains('\n') || self.confirm_pasted(&text) {
+            self.pasted.push_str(&text);
+        }
+        Key::Paste
+    }
+
+    // shows the lines of a paste and asks whether to run them
+    fn confirm_pasted(&self, text: &str) -> bool {
+        let lines: Vec<&str> = text.lines().collect();
+        let shown = (self.window.get_max_y() - 7).max(1) as usize;
+        let mut body = lines[..shown.min(lines.len())].join("\n");
+        if lines.len() > shown {
+            body.push('\n');
+            body.push_str(&self.messages.paste_lines_hidden(lines.len() - shown));
+        }
+        let question = self.messages.confirm_paste(lines.len());
+        matches!(self.show_popup(&body, Some(&question)), Some(Input::Character('y' | 'Y')))
+    }
+
+    fn continuation_prompt(&self) -> String {
+        let indent = (width::text_width(&self.primary_prompt) - 4).max(0) as usize;
+        format!("{}... ", " ".repeat(indent))
+    }
+
+    // records a line that has just been printed with the current prompt
+    fn push_block_line(&mut self, line: String) {
+        let column = self.window.get_max_x();
+        self.block_rows += (width::text_width(&self.prompt) + width::text_width(&line)) / column + 1;
+        self.block.push(line);
+    }
+
+    // the whole command being edited, including earlier lines of a block
+    fn current_text(&self) -> String {
+        let mut lines = self.block.clone();
+        lines.push(String::from_utf8(self.buf.clone()).unwrap());
+        lines.join("\n")
+    }
+
+    // replaces what is being edited with `text`, printing all but its last
+    // line above the prompt with continuation prompts
+    fn load_text(&mut self, text: &str) {
+        let text = normalize::nfc(text);
+        self.clear_block();
+        let mut lines: Vec<&str> = text.split('\n').collect();
+        let last = lines.pop().unwrap_or("");
+        for line in lines {
+            self.window.addstr(format!("{}\n", drawable(line)));
+            self.push_block_line(line.to_owned());
+            self.prompt = self.continuation_prompt();
+            self.print_prompt();
+        }
+        self.buf.extend(last.as_bytes());
+        self.window.addstr(drawable(last));
+        self.pos = self.buf.len() as i32;
+    }
+
+    // clears the edited line and any block lines above it
+    fn clear_block(&mut self) {
+        if self.block.is_empty() {
+            self.clear_line();
+            return;
+        }
+        let top = (self.line_start_position().1 - self.block_rows).max(0);
+        let mut y = self.line_end_position().1;
+        while y >= top {
+            self.window.mv(y, 0);
+            self.window.deleteln();
+            y -= 1;
+        }
+        self.block.clear();
+        self.block_rows = 0;
+        self.buf.clear();
+        self.pos = 0;
+        self.prompt = self.primary_prompt.clone();
+        self.window.mv(top, 0);
+        self.print_prompt();
+    }
+
+    fn save_history(&mut self) {
+        if let Some(ref path) = self.history_file {
+            if let Err(e) = self.history.save(path, self.history_cipher.as_deref()) {
+                let message = self.messages.error("history", &e.to_string());
+                self.window.addstr(format!("{}\n", message));
+            }
+        }
+    }
+
+    // starts a continuation line if the block so far has unclosed brackets
+    // or the last line ends in a backslash
+    fn continue_input(&mut self) -> bool {
+        let last = self.block.last().cloned().unwrap_or_default();
+        if last.ends_with('\\') {
+            self.block.last_mut().unwrap().pop();
+        } else if brackets::depth(&self.block.join("\n")) <= 0 {
+            return false;
+        }
+        self.prompt = self.continuation_prompt();
+        self.print_prompt();
+        if self.auto_indent {
+            let mut indent: String = last.chars().take_while(|c| *c == ' ').collect();
+            if self.indent_after_brace && last.trim_end().ends_with(['{', '[', '(']) {
+                indent.push_str("    ");
+            }
+            if !indent.is_empty() {
+                self.insert(indent);
+            }
+        }
+        true
+    }
+
+    fn handle_input(&mut self, ch: Key) -> Option<String> {
+        if ch == Key::Input(Input::KeyResize) {
+            self.on_resized();
+            return None;
+        }
+        if ch == Key::Paste {
+            return self.paste();
+        }
+        self.chord.push(ch);
+        match self.keymap.lookup(&self.chord) {
+            Lookup::Prefix => None,
+            Lookup::Found(action) => {
+                let keys = self.take_chord();
+                self.perform(action, &keys)
+            }
+            Lookup::None => self.resolve_chord(false),
+        }
+    }
+
+    // acts on an unfinished sequence that can't be completed: runs the
+    // longest bound part of it (with `timed_out`, possibly all of it), or
+    // else inserts the first key, then feeds the remaining keys back in
+    fn resolve_chord(&mut self, timed_out: bool) -> Option<String> {
+        let keys = self.take_chord();
+        let longest = if timed_out { keys.len() } else { keys.len() - 1 };
+        let bound = (1..=longest).rev()
+            .find_map(|n| self.keymap.get(&keys[..n]).map(|action| (n, action)));
+        let (used, ret) = match bound {
+            Some((n, action)) => (n, self.perform(action, &keys[..n])),
+            None => (1, self.self_insert(keys[0])),
+        };
+        for &key in keys[used..].iter().rev() {
+            self.pending.push_front(key);
+        }
+        ret
+    }
+
+    // inserts the first line of the pasted text, running it if more
+    // follow, as typing them would, and leaves the others for later
+    fn paste(&mut self) -> Option<String> {
+        let text = mem::take(&mut self.pasted);
+        if self.read_only {
+            return None;
+        }
+        self.substring_search = None;
+        self.candidates.clear();
+        let (line, rest) = match text.find('\n') {
+            Some(end) => (&text[..end], Some(&text[end + 1..])),
+            None => (text.as_str(), None),
+        };
+        if !line.is_empty() {
+            self.insert(line.to_owned());
+        }
+        let rest = rest?;
+        if !rest.is_empty() {
+            self.pasted = rest.to_owned();
+            self.pending.push_front(Key::Paste);
+        }
+        Some(self.line_feed())
+    }
+
+    fn take_chord(&mut self) -> Vec<Key> {
+        self.chord.drain(..).collect()
+    }
+
+    // shows the pending keys of a sequence, or else the length of a line at
+    // its limit, the overwrite mode or a notification, in the bottom right
+    // corner, short of the last cell, writing to which would scroll the
+    // window
+    fn show_status(&mut self) {
+        self.hide_status();
+        let text = if !self.chord.is_empty() {
+            keymap::describe(&self.chord)
+        } else if let Some((count, max)) = self.max_input.map(|max| (valid_prefix(&self.buf).chars().count(), max))
+            .filter(|&(count, max)| count >= max) {
+            format!("{}/{}", count, max)
+        } else if self.overwrite {
+            "OVR".to_owned()
+        } else if let Some(ref notification) = self.unacknowledged {
+            // at most half the row
+            let columns = self.window.get_max_x() as usize / 2;
+            notification.message.chars().take(columns).collect()
+        } else {
+            return;
+        };
+        let text = format!(" {} ", text);
+        let cursor = self.current_position();
+        let (rows, columns) = self.window.get_max_yx();
+        let y = rows - 1;
+        let x = (columns - 1 - text.len() as i32).max(0);
+        for (i, c) in text.chars().enumerate() {
+            let position = Position(x + i as i32, y);
+            self.status_cells.push((position, self.window.mvinch(y, position.0)));
+            self.window.mvaddch(y, position.0, c);
+        }
+        self.window.mvchgat(y, x, text.len() as i32, A_REVERSE, 0);
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    fn hide_status(&mut self) {
+        if self.status_cells.is_empty() {
+            return;
+        }
+        let cursor = self.current_position();
+        for (Position(x, y), cell) in self.status_cells.drain(..) {
+            self.window.mvaddch(y, x, cell);
+        }
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    fn perform(&mut self, action: Action, keys: &[Key]) -> Option<String> {
+        // only what changes how output is shown
+        if self.read_only && !matches!(action, Action::CopyMode | Action::ToggleWrap | Action::ToggleTimestamps) {
+            return None;
+        }
+        match action {
+            Action::StartMacro => { self.macros.start(); }
+            Action::EndMacro => { self.macros.stop(); }
+            Action::PlayMacro if !self.macros.is_recording() => {
+                let keys = self.macros.last().to_vec();
+                for key in keys.into_iter().rev() {
+                    self.pending.push_front(key);
+                }
+            }
+            Action::PlayMacro => {}
+            _ => {
+                for &key in keys {
+                    self.macros.record(key);
+                }
+            }
+        }
+        if action != Action::HistorySubstringSearchUp && action != Action::HistorySubstringSearchDown {
+            self.substring_search = None;
+        }
+        let tabbed = self.tabbed;
+        self.tabbed = action == Action::Complete;
+        if !self.tabbed {
+            self.candidates.clear();
+        }
+        match action {
+            Action::Complete | Action::DeleteBackward | Action::DeleteForward | Action::MoveLeft | Action::MoveRight
+            | Action::MoveWordLeft | Action::MoveWordRight | Action::MoveToStart | Action::MoveToEnd => {}
+            // other edits could move the text under the placeholders
+            _ => self.fields.clear(),
+        }
+        match action {
+            Action::AcceptLine => {
+                self.expand_abbreviation();
+                return Some(self.line_feed());
+            }
+            Action::Complete if !self.fields.is_empty() => self.next_field(),
+            Action::Complete => { self.complete(tabbed); }
+            Action::DeleteBackward if self.pos == 0 => self.ring(),
+            Action::DeleteBackward => { self.delete_backward(); }
+            Action::DeleteForward if self.pos == self.buf.len() as i32 => self.ring(),
+            Action::DeleteForward => { self.delete_forward(); }
+            Action::ClearToStart => { self.clear_to_start(); }
+            Action::ClearLine => { self.clear_line(); }
+            Action::MoveToStart => { self.move_to_start(); }
+            Action::MoveToEnd => { self.move_to_end(); }
+            Action::MoveLeft if self.pos == 0 => self.ring(),
+            Action::MoveLeft => { self.move_left(); }
+            Action::MoveRight if self.pos == self.buf.len() as i32 => self.ring(),
+            Action::MoveRight => { self.move_right(); }
+            Action::MoveWordLeft => { self.move_word_left(); }
+            Action::MoveWordRight => { self.move_word_right(); }
+            Action::PrevCommand => { self.prev_command(); }
+            Action::HistorySubstringSearchUp => { self.history_substring_search(true); }
+            Action::HistorySubstringSearchDown => { self.history_substring_search(false); }
+            Action::NextCommand => { self.next_command(); }
+            Action::ShowHelp => { self.show_help(); }
+            Action::ToggleOverwrite => {
+                self.overwrite = !self.overwrite;
+                self.update_cursor_shape();
+            }
+            Action::CopyMode => { self.copy_mode(); }
+            Action::Yank => { self.yank(); }
+            Action::RerunLast => return self.rerun_last(),
+            Action::AcknowledgeNotification => { self.unacknowledged = None; }
+            Action::ToggleFold => { self.toggle_fold(); }
+            Action::ToggleWrap => { self.truncate_lines = !self.truncate_lines; }
+            Action::ToggleTimestamps => { self.show_timestamps = !self.show_timestamps; }
+            Action::Custom(name) => return self.custom_action(name),
+            Action::ExpandSnippet => {
+                let line = String::from_utf8(self.buf[..self.pos as usize].to_vec()).unwrap();
+                let word = line.rsplit(' ').next().unwrap_or("");
+                if !self.expand_snippet(word) {
+                    self.ring();
+                }
+            }
+            Action::OperateAndGetNext => {
+                let next = self.history.position() + 1;
+                self.get_next = if next < self.history.len() { Some(next) } else { None };
+                self.expand_abbreviation();
+                return Some(self.line_feed());
+            }
+            Action::StartMacro | Action::EndMacro | Action::PlayMacro => {}
+        }
+        None
+    }
+
+    // what an unbound key does: printable characters are typed
+    fn self_insert(&mut self, ch: Key) -> Option<String> {
+        if self.read_only {
+            return None;
+        }
+        self.macros.record(ch);
+        self.tabbed = false;
+        self.substring_search = None;
+        self.candidates.clear();
+        match ch {
+            // what an input method commits arrives as typed characters, its
+            // preedit being drawn by the terminal itself at the cursor
+            Key::Input(Input::Character(c)) if !c.is_control() => {
+                if c == ' ' && self.fields.is_empty() {
+                    self.expand_abbreviation();
+                }
+                if self.overwrite {
+                    self.delete_forward();
+                }
+                self.clear_field();
+                self.insert_char(c);
+            }
+            _ => {}
+        }
+        None
+    }
 
-impl<F> Terminal<F>
-    where F: Fn(String) -> String {
-    pub fn run(process: F) {
-        let window = initscr();
-        window.keypad(true);
-        window.scrollok(true);
-        window.setscrreg(0, window.get_max_y());
-        noecho();
-        let mut t = Terminal {
-            prompt: "debug> ".to_owned(),
-            window,
-            history: CommandHistory::default(),
-            buf: Vec::new(),
-            pos: 0,
-            process,
+    // expands the first word when the cursor sits right after it
+    fn expand_abbreviation(&mut self) {
+        let word = &self.buf[..self.pos as usize];
+        if word.is_empty() || word.contains(&b' ') {
+            return;
+        }
+        let expansion = match self.abbreviations.get(&String::from_utf8_lossy(word)) {
+            Some(expansion) => expansion.to_owned(),
+            None => return,
         };
-        loop {
-            let command = t.input();
-            let result = (t.process)(command);
-            t.window.printw(format!("{}\n", result));
+        let tail = String::from_utf8(self.buf[self.pos as usize..].to_vec()).unwrap();
+        self.clear_line();
+        let pos = expansion.len();
+        self.insert(expansion + &tail);
+        self.move_to(pos);
+    }
+
+    fn complete(&mut self, again: bool) {
+        let line = String::from_utf8(self.buf[..self.pos as usize].to_vec()).unwrap();
+        if let Some(param) = self.param.clone() {
+            // a param value is completed whole
+            let matcher = self.matcher;
+            let candidates = param.candidates(&line).into_iter().filter(|c| matcher.matches(c, &line)).collect();
+            return self.show_completion(&line, candidates, again, false);
+        }
+        let word = line.rsplit(' ').next().unwrap_or("").to_owned();
+        if let (Some(cwd), Some(dir)) = (self.cwd.as_ref(), line.strip_prefix("cd ")) {
+            // a directory ends with `/`, which more may follow
+            if !dir.contains(' ') {
+                let candidates = workdir::complete(cwd, dir);
+                let separate = candidates.len() == 1 && !candidates[0].ends_with('/');
+                return self.show_completion(&word, candidates, again, separate);
+            }
+        }
+        let (completers, matcher) = (&self.completers, self.matcher);
+        let mut candidates = workdir::in_dir(self.cwd.as_deref(), || completion::candidates(completers, matcher, &line, &word));
+        // snippets and command names complete the first word, those run
+        // most often first
+        if line == word {
+            let matcher = self.matcher;
+            candidates.extend(self.snippets.keys()
+                .filter(|name| matcher.matches(name, &word) && !candidates.contains(name))
+                .cloned()
+                .collect::<Vec<_>>());
+            let directory_builtins = if self.cwd.is_some() { DIRECTORY_BUILTINS } else { &[] };
+            let mut names: Vec<&str> = self.command_names.iter().map(|s| s.as_str())
+                .chain(BUILTINS.iter().cloned())
+                .chain(directory_builtins.iter().cloned())
+                .filter(|name| matcher.matches(name, &word) && !candidates.iter().any(|c| c == name))
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            let top = self.history.top_commands(usize::MAX);
+            names.sort_by_key(|name| top.iter().position(|usage| usage.name == *name).unwrap_or(top.len()));
+            candidates.extend(names.into_iter().map(|name| name.to_owned()));
         }
+        if self.complete_from_history {
+            let matcher = self.matcher;
+            candidates.extend(completion::history_arguments(self.history.iter(), &line, &word)
+                .into_iter()
+                .filter(|c| matcher.matches(c, &word) && !candidates.contains(c))
+                .collect::<Vec<_>>());
+        }
+        if candidates.len() == 1 && line == word && self.snippets.contains_key(&candidates[0]) {
+            self.replace_word(&word, &candidates[0]);
+            self.expand_snippet(&candidates[0]);
+            return;
+        }
+        self.show_completion(&word, candidates, again, true);
     }
 
-    fn print_prompt(&self) {
-        self.window.printw(self.prompt.as_str());
+    // inserts the only candidate for `word`, followed by a space with
+    // `separate`, or else their common prefix or the list of them
+    fn show_completion(&mut self, word: &str, candidates: Vec<String>, again: bool, separate: bool) {
+        match candidates.len() {
+            0 => self.ring(),
+            1 if separate => self.replace_word(word, &format!("{} ", candidates[0])),
+            1 => self.replace_word(word, &candidates[0]),
+            _ if self.completion_policy == Policy::TwoStage && !again => {
+                let prefix = completion::common_prefix(&candidates);
+                if prefix.len() > word.len() && self.matcher.matches(&prefix, word) {
+                    self.replace_word(word, &prefix);
+                }
+                self.ring();
+            }
+            _ => {
+                self.show_candidates(&candidates);
+                self.candidates = candidates;
+            }
+        }
     }
 
-    fn input(&mut self) -> String {
+    // the line as custom actions and highlighters see it
+    fn editor_state(&self) -> EditorState {
+        let mut state = EditorState::new(String::from_utf8(self.buf.clone()).unwrap(), self.pos as usize);
+        state.prompt = self.prompt.clone();
+        state.block = self.block.clone();
+        state.candidates = self.candidates.clone();
+        state.history_entry = if self.history.at_top() { None } else { Some(self.history.position()) };
+        state.overwrite = self.overwrite;
+        state
+    }
+
+    // runs a custom action on the line, then shows the line it left
+    fn custom_action(&mut self, name: &str) -> Option<String> {
+        let mut state = self.editor_state();
+        let buffer = state.buffer().to_owned();
+        if let Some(action) = self.actions.get(name) {
+            action(&mut state);
+        }
+        if state.buffer() != buffer || state.cursor() != self.pos as usize || state.prompt_refreshed() {
+            self.clear_line();
+            if state.prompt_refreshed() {
+                self.refresh_prompt();
+                let y = self.window.get_cur_y();
+                self.window.mv(y, 0);
+                self.window.clrtoeol();
+                self.print_prompt();
+            }
+            self.insert(state.buffer().to_owned());
+            self.move_to(state.cursor());
+        }
+        if state.accepted() {
+            self.expand_abbreviation();
+            return Some(self.line_feed());
+        }
+        None
+    }
+
+    // replaces `word`, before the cursor, with the snippet it names and
+    // selects its first placeholder
+    fn expand_snippet(&mut self, word: &str) -> bool {
+        let (text, mut fields) = match self.snippets.get(word) {
+            Some(template) => snippet::expand(template),
+            None => return false,
+        };
+        self.replace_word(word, &text);
+        let start = self.pos as usize - text.len();
+        for field in fields.iter_mut() {
+            field.start += start;
+        }
+        self.fields = fields;
+        self.field = 0;
+        if !self.fields.is_empty() {
+            self.move_to(self.fields[0].start);
+        }
+        true
+    }
+
+    // Tab in a snippet: moves to the next placeholder, and after the last
+    // one to the end of the line
+    fn next_field(&mut self) {
+        self.field += 1;
+        match self.fields.get(self.field).map(|field| field.start) {
+            Some(start) => self.move_to(start),
+            None => {
+                self.fields.clear();
+                self.move_to_end();
+            }
+        }
+    }
+
+    // removes the name of the placeholder about to be typed into
+    fn clear_field(&mut self) {
+        let (start, len) = match self.fields.get(self.field) {
+            Some(field) if !field.filled && field.start == self.pos as usize => (field.start, field.len),
+            _ => return,
+        };
+        self.move_to(start + len);
+        while self.pos as usize > start {
+            self.backspace();
+        }
+    }
+
+    fn move_to(&mut self, index: usize) {
+        while (self.pos as usize) > index {
+            self.move_left();
+        }
+        while (self.pos as usize) < index {
+            self.move_right();
+        }
+    }
+
+    // replaces the word before the cursor
+    fn replace_word(&mut self, word: &str, text: &str) {
+        let start = self.pos as usize - word.len();
+        while self.pos as usize > start {
+            self.backspace();
+        }
+        self.insert(text.to_owned());
+    }
+
+    // lists candidates in columns below the line, then redraws the line;
+    // when the previous list is still right above it, only its rows are
+    // drawn again
+    fn show_candidates(&mut self, candidates: &[String]) {
+        let column = self.window.get_max_x() as usize;
+        let width = candidates.iter().map(|c| c.len()).max().unwrap_or(0) + 2;
+        let per_row = (column / width).max(1);
+        let rows: Vec<String> = candidates.chunks(per_row)
+            .map(|row| row.iter().map(|c| format!("{:width$}", c, width = width)).collect::<String>())
+            .map(|row| row.trim_end().to_owned())
+            .collect();
+        if self.redraw_listed(&rows) {
+            return;
+        }
+        let pos = self.pos as usize;
+        self.move_to_end();
+        self.window.addstr(format!("\n{}\n", drawable(&rows.join("\n"))));
         self.print_prompt();
-        self.pos = 0;
-        loop {
-            if let Some(ch) = self.window.getch() {
-                match ch {
-                    Input::Character(c) => {
-                        match c {
-                            '\n' => { return self.line_feed(); }
-                            '\t' => {}
-                            '\u{7f}' => { self.backspace(); }
-                            '\u{15}' => {
-                                // ctrl+U
-                                self.clear_to_start();
-                            }
-                            '\u{c}' => {
-                                // ctrl+L
-                                self.clear_line();
-                            }
-                            '\u{1}' => {
-                                // ctl+A
-                                self.move_to_start();
-                            }
-                            '\u{5}' => {
-                                // ctrl+E
-                                self.move_to_end();
-                            }
-                            x if (x as u8) >= 0x20 && (x as u8) <= 0x7E => { self.insert(x.to_string()); }
-                            _ => {}
-                        }
-                    }
-                    Input::KeyBackspace => {self.backspace();}
-                    Input::KeyResize => { self.on_resized(); }
-                    Input::KeyUp => { self.prev_command(); }
-                    Input::KeyDown => { self.next_command(); }
-                    Input::KeyLeft => { self.move_left(); }
-                    Input::KeyRight => { self.move_right(); }
-                    x => { println!("{:?}", x); }
+        self.window.addstr(drawable(valid_prefix(&self.buf)));
+        self.move_to(pos);
+        self.listed = rows;
+    }
+
+    // draws `rows` over the last list of candidates if it is still on the
+    // screen right above the line and has room for them, its first rows
+    // left blank when they aren't all needed
+    fn redraw_listed(&mut self, rows: &[String]) -> bool {
+        let listed = mem::take(&mut self.listed);
+        let top = self.line_start_position().1 - listed.len() as i32;
+        if listed.is_empty() || rows.len() > listed.len() || top < 0 {
+            return false;
+        }
+        // reading the screen moves the cursor
+        let cursor = self.current_position();
+        let shown = self.shows(top, &listed);
+        self.window.mv(cursor.1, cursor.0);
+        if !shown {
+            return false;
+        }
+        let mut padded = vec![String::new(); listed.len() - rows.len()];
+        padded.extend(rows.iter().cloned());
+        for (i, (row, old)) in padded.iter().zip(&listed).enumerate() {
+            if row != old {
+                self.window.mv(top + i as i32, 0);
+                self.window.clrtoeol();
+                self.window.addstr(row);
+            }
+        }
+        self.window.mv(cursor.1, cursor.0);
+        self.listed = padded;
+        true
+    }
+
+    // whether the screen shows `rows`, ASCII only, from row `top` on
+    fn shows(&self, top: i32, rows: &[String]) -> bool {
+        let column = self.window.get_max_x();
+        rows.iter().enumerate().all(|(i, row)| {
+            row.is_ascii() && row.len() <= column as usize && (0..column).all(|x| {
+                // the character is in the low byte; pancurses' A_CHARTEXT
+                // lacks the mask's lower bits
+                let shown = self.window.mvinch(top + i as i32, x) as u8;
+                shown == *row.as_bytes().get(x as usize).unwrap_or(&b' ')
+            })
+        })
+    }
+
+    // shows the help of the command being typed in an overlay until a key
+    // is pressed
+    fn show_help(&mut self) {
+        let line = String::from_utf8(self.buf.clone()).unwrap();
+        let name = match line.split_whitespace().next() {
+            Some(name) => name.to_owned(),
+            None => return,
+        };
+        let help = match self.command_help {
+            Some(ref help) => help(&name),
+            None => None,
+        };
+        let text = help.unwrap_or_else(|| self.messages.no_help(&name));
+        self.show_popup(&text, None);
+    }
+
+    // shows `text` in a box over the screen, with `footer` at its bottom
+    // however much of the text fits, until a key is pressed, which it returns
+    fn show_popup(&self, text: &str, footer: Option<&str>) -> Option<Input> {
+        let (rows, columns) = self.window.get_max_yx();
+        let width = (columns - 4).max(10) as usize;
+        let wrap = |text: &str| {
+            let mut lines = Vec::new();
+            for line in text.lines() {
+                let chars: Vec<char> = line.chars().collect();
+                if chars.is_empty() {
+                    lines.push(String::new());
+                }
+                for chunk in chars.chunks(width - 2) {
+                    lines.push(chunk.iter().collect::<String>());
+                }
+            }
+            lines
+        };
+        let mut lines = wrap(text);
+        let footer = footer.map(wrap).unwrap_or_default();
+        let room = (rows - 4).max(1) as usize;
+        if footer.is_empty() {
+            lines.truncate(room);
+        } else {
+            lines.truncate(room.saturating_sub(footer.len() + 1));
+            lines.push(String::new());
+            lines.extend(footer);
+        }
+        let w = (lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) + 4) as i32;
+        let h = lines.len() as i32 + 2;
+        let popup = newwin(h, w, (rows - h) / 2, (columns - w) / 2);
+        popup.draw_box(0, 0);
+        for (i, line) in lines.iter().enumerate() {
+            popup.mvaddstr(i as i32 + 1, 2, drawable(line));
+        }
+        popup.refresh();
+        let key = popup.getch();
+        popup.delwin();
+        self.window.touch();
+        self.window.refresh();
+        key
+    }
+
+    fn rerun_last(&mut self) -> Option<String> {
+        let last = match self.history.len() {
+            0 => None,
+            n => self.history.get(n - 1).cloned(),
+        };
+        match last {
+            Some(command) => {
+                self.load_text(&command);
+                Some(self.line_feed())
+            }
+            None => {
+                self.ring();
+                None
+            }
+        }
+    }
+
+    fn copy_mode(&mut self) {
+        if let Some(text) = copy_mode::run(&self.scrollback.lines(), self.messages.as_ref()) {
+            self.kill(text);
+        }
+        self.window.touch();
+        self.window.refresh();
+    }
+
+    fn kill(&mut self, text: String) {
+        if self.kill_ring.len() == KILL_RING_SIZE {
+            self.kill_ring.remove(0);
+        }
+        self.kill_ring.push(text);
+    }
+
+    fn yank(&mut self) {
+        match self.kill_ring.last() {
+            // the line editor holds a single line
+            Some(text) => {
+                let text = text.replace('\n', " ");
+                self.insert(text);
+            }
+            None => self.ring(),
+        }
+    }
+
+    fn highlight_brackets(&mut self) {
+        if !self.match_brackets {
+            return;
+        }
+        let (at, matched) = match brackets::find_match(&self.buf, self.pos as usize) {
+            Some(found) => found,
+            None => return,
+        };
+        let cursor = self.current_position();
+        let (index, attributes, pair) = match matched {
+            Some(index) => (index, A_REVERSE, 0),
+            None => (at, A_BOLD, ERROR_PAIR),
+        };
+        let Position(x, y) = self.buf_position(index);
+        self.window.mvchgat(y, x, 1, attributes, pair);
+        self.highlighted.push(Position(x, y));
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    // styles the line as the highlighter says
+    fn highlight_line(&mut self) {
+        let ranges = match (&self.highlighter, self.async_highlighter.is_some()) {
+            (Some(highlighter), _) => highlighter.highlight(&self.editor_state()),
+            (None, true) => {
+                // styled once the background highlighter is done
+                let state = self.editor_state();
+                match self.async_highlighter.as_mut().and_then(|h| h.request(state)) {
+                    Some(ranges) => ranges,
+                    None => return,
                 }
             }
+            (None, false) => return,
+        };
+        self.apply_highlights(ranges);
+    }
+
+    fn apply_highlights(&mut self, ranges: Highlights) {
+        let cursor = self.current_position();
+        for (range, style) in ranges {
+            let (attributes, pair) = match style {
+                Style::Plain => (A_NORMAL, 0),
+                Style::Bold => (A_BOLD, 0),
+                Style::Dim => (A_DIM, 0),
+                Style::Underline => (A_UNDERLINE, 0),
+                Style::Reverse => (A_REVERSE, 0),
+                Style::Error => (A_BOLD, ERROR_PAIR),
+                Style::Added => (A_NORMAL, ADDED_PAIR),
+                Style::Removed => (A_NORMAL, REMOVED_PAIR),
+            };
+            for index in range.start..range.end.min(self.buf.len()) {
+                let Position(x, y) = self.buf_position(index);
+                self.window.mvchgat(y, x, 1, attributes, pair);
+                self.highlighted.push(Position(x, y));
+            }
+        }
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    // underlines the placeholders not typed into yet, the current one in
+    // reverse video
+    fn highlight_fields(&mut self) {
+        let cursor = self.current_position();
+        for (i, field) in self.fields.iter().enumerate().filter(|(_, field)| !field.filled) {
+            let attributes = if i == self.field { A_REVERSE } else { A_UNDERLINE };
+            for index in field.start..field.start + field.len {
+                let Position(x, y) = self.buf_position(index);
+                self.window.mvchgat(y, x, 1, attributes, 0);
+                self.highlighted.push(Position(x, y));
+            }
+        }
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    fn clear_highlights(&mut self) {
+        if self.highlighted.is_empty() {
+            return;
+        }
+        let cursor = self.current_position();
+        for Position(x, y) in self.highlighted.drain(..) {
+            self.window.mvchgat(y, x, 1, A_NORMAL, 0);
         }
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    // screen position of the buffer byte at `index`
+    fn buf_position(&self, index: usize) -> Position {
+        self.position_after(self.line_start_position(), index)
     }
 
     fn on_resized(&mut self) {
@@ -89,44 +2542,118 @@ impl<F> Terminal<F>
     }
 
     fn line_feed(&mut self) -> String {
-        let ret = String::from_utf8(self.buf.clone()).unwrap();
+        self.fold = None;
+        let ret = submitted(&self.buf);
         self.clear_line();
-        self.window.printw(format!("{}\n", ret));
-        if ret.trim().len() > 0 {
-            self.history.add_command(ret.clone());
+        if self.verbosity == Verbosity::Quiet {
+            let y = self.window.get_cur_y();
+            self.window.mv(y, 0);
+            self.window.clrtoeol();
+            self.pos = 0;
+            return ret;
+        }
+        let mut prompt = self.prompt.clone();
+        match self.transient_prompt {
+            Some(ref marker) if self.prompt == self.primary_prompt => {
+                let y = self.window.get_cur_y();
+                self.window.mv(y, 0);
+                self.window.clrtoeol();
+                self.window.addstr(marker);
+                prompt = marker.clone();
+            }
+            _ => {}
         }
+        self.window.addstr(format!("{}\n", ret));
+        self.scrollback.push(&format!("{}{}", prompt, ret));
         self.pos = 0;
-        return ret;
+        ret
     }
 
     fn prev_command(&mut self) {
         if self.history.at_top() {
-            let command = String::from_utf8(self.buf.clone()).unwrap();
-            self.history.add_command(command);
+            let command = self.current_text();
+            self.history.save_draft(command);
             self.history.prev_command();
         }
-        self.clear_line();
-        if let Some(command) = self.history.prev_command() {
-            self.buf.extend(command.as_bytes());
-            self.window.printw(command);
+        match self.history.prev_command().cloned() {
+            Some(command) => self.load_text(&command),
+            None => self.ring(),
         }
-        self.pos = self.buf.len() as i32;
     }
 
     fn next_command(&mut self) {
-        self.clear_line();
-        if let Some(command) = self.history.next_command() {
-            self.buf.extend(command.as_bytes());
-            self.window.printw(command);
+        match self.history.next_command().cloned() {
+            Some(command) => self.load_text(&command),
+            None => self.ring(),
+        }
+    }
+
+    // recalls the previous (or next) entry containing the text the line had
+    // when the search started
+    fn history_substring_search(&mut self, backward: bool) {
+        let query = match self.substring_search.clone() {
+            Some(query) => query,
+            None => {
+                let query = self.current_text();
+                if self.history.at_top() {
+                    self.history.save_draft(query.clone());
+                }
+                self.substring_search = Some(query.clone());
+                query
+            }
+        };
+        let (shown, query) = (self.current_text(), query.to_ascii_lowercase());
+        let found = self.history.search(backward, |entry| entry != shown && entry.to_ascii_lowercase().contains(&query));
+        match found.cloned() {
+            Some(command) => self.load_text(&command),
+            None if !backward => match self.history.back_to_draft().cloned() {
+                Some(draft) => self.load_text(&draft),
+                None => self.ring(),
+            },
+            None => self.ring(),
+        }
+    }
+
+    // highlights what history-substring-search found in the recalled line
+    fn highlight_search(&mut self) {
+        let query = match self.substring_search {
+            Some(ref query) if !query.is_empty() => query.to_ascii_lowercase(),
+            _ => return,
+        };
+        let text = String::from_utf8_lossy(&self.buf).to_ascii_lowercase();
+        let cursor = self.current_position();
+        for (start, _) in text.match_indices(&query) {
+            for index in start..start + query.len() {
+                let Position(x, y) = self.buf_position(index);
+                self.window.mvchgat(y, x, 1, A_BOLD | A_REVERSE, 0);
+                self.highlighted.push(Position(x, y));
+            }
+        }
+        self.window.mv(cursor.1, cursor.0);
+    }
+
+    fn ring(&self) {
+        match self.bell {
+            Bell::Audible => { beep(); }
+            Bell::Visible => { flash(); }
+            Bell::Off => {}
         }
-        self.pos = self.buf.len() as i32;
     }
 
     fn insert(&mut self, text: String) {
+        let mut text = normalize::into_nfc(text);
+        if let Some(max) = self.max_input {
+            let room = max.saturating_sub(valid_prefix(&self.buf).chars().count());
+            if let Some((end, _)) = text.char_indices().nth(room) {
+                self.ring();
+                text.truncate(end);
+            }
+        }
+        snippet::inserted(&mut self.fields, self.pos as usize, text.len());
         if self.pos == self.buf.len() as i32 {
             self.buf.extend(text.as_bytes());
-            self.pos += text.as_bytes().len() as i32;
-            self.window.printw(text);
+            self.pos += text.len() as i32;
+            self.window.addstr(drawable(&text));
         } else {
             let tmp = {
                 let pre = &self.buf[0..self.pos as usize];
@@ -137,27 +2664,79 @@ impl<F> Terminal<F>
                 tmp.extend(end);
                 tmp
             };
-            let len = text.as_bytes().len() as i32;
-            let pos = self.pos + len;
-            for _ in 0..len {
-                self.move_right();
-            }
-            let position = self.current_position();
+            let pos = self.pos as usize + text.len();
             self.clear_line();
             self.buf = tmp;
-            self.window.printw(String::from_utf8(self.buf.clone()).unwrap());
-            self.pos = pos;
-            self.window.mv(position.1, position.0);
+            self.window.addstr(drawable(valid_prefix(&self.buf)));
+            self.pos = self.buf.len() as i32;
+            self.move_to(pos);
+        }
+    }
+
+    fn insert_char(&mut self, c: char) {
+        if self.indent_after_brace && (c == '}' || c == ']' || c == ')') && self.prompt != self.primary_prompt {
+            // a closing bracket typed into the indentation dedents the line
+            let before = &self.buf[..self.pos as usize];
+            if before.len() >= 4 && before.iter().all(|&b| b == b' ') {
+                for _ in 0..4 {
+                    self.backspace();
+                }
+            }
+        }
+        if self.auto_pairs {
+            let (prev, next) = self.chars_around();
+            if next == Some(c) && is_closing(c) {
+                self.move_right();
+                return;
+            }
+            // don't pair the apostrophe in "don't"
+            let in_word = prev.is_some_and(|p| p.is_alphanumeric());
+            if let Some(close) = closing_pair(c) {
+                if !(c == '\'' || c == '"') || !in_word {
+                    self.insert(format!("{}{}", c, close));
+                    self.move_left();
+                    return;
+                }
+            }
+        }
+        self.insert(c.to_string());
+    }
+
+    fn delete_backward(&mut self) {
+        if self.auto_pairs {
+            let (prev, next) = self.chars_around();
+            if next.is_some() && prev.and_then(closing_pair) == next {
+                self.move_right();
+                self.backspace();
+            }
         }
+        self.backspace();
+    }
 
+    // the characters before and after the cursor
+    fn chars_around(&self) -> (Option<char>, Option<char>) {
+        let pos = self.pos as usize;
+        (valid_prefix(&self.buf[..pos]).chars().next_back(), valid_prefix(&self.buf[pos..]).chars().next())
+    }
+
+    // deletes the character under the cursor
+    fn delete_forward(&mut self) {
+        if self.pos < self.buf.len() as i32 {
+            self.move_right();
+            self.backspace();
+        }
     }
 
     fn clear_to_start(&mut self) {
+        if self.pos > 0 {
+            let killed = String::from_utf8(self.buf[..self.pos as usize].to_vec()).unwrap();
+            self.kill(killed);
+        }
         let tmp = self.buf[self.pos as usize..].to_owned();
         let origin = self.line_start_position();
         self.clear_line();
         self.buf = tmp;
-        self.window.printw(String::from_utf8(self.buf.clone()).unwrap());
+        self.window.addstr(drawable(valid_prefix(&self.buf)));
         self.window.mv(origin.1, origin.0);
     }
 
@@ -165,18 +2744,26 @@ impl<F> Terminal<F>
         if self.pos == 0 {
 
         } else if self.pos == self.buf.len() as i32 {
+            let end = self.pos as usize;
             self.move_left();
+            for index in (self.pos as usize..end).rev() {
+                snippet::removed(&mut self.fields, index);
+            }
             self.window.delch();
-            self.buf.pop();
+            self.buf.truncate(self.pos as usize);
         } else {
+            let end = self.pos as usize;
             self.move_left();
-            self.buf.remove(self.pos as usize);
+            for index in (self.pos as usize..end).rev() {
+                snippet::removed(&mut self.fields, index);
+            }
+            self.buf.drain(self.pos as usize..end);
             let p = self.current_position();
             let pos = self.pos;
             let tmp = self.buf.clone();
             self.clear_line();
             self.buf = tmp;
-            self.window.printw(String::from_utf8(self.buf.clone()).unwrap());
+            self.window.addstr(drawable(valid_prefix(&self.buf)));
             self.window.mv(p.1, p.0);
             self.pos = pos;
         }
@@ -203,25 +2790,40 @@ impl<F> Terminal<F>
 
     fn move_left(&mut self) {
         if self.pos > 0 {
-            let Position(x, y) = self.current_position();
-            if x == 0 {
-                self.window.mv(y - 1, self.window.get_max_x() - 1);
-            } else {
-                self.window.mv(y, x - 1);
-            }
-            self.pos -= 1;
+            let start = self.line_start_position();
+            self.pos -= cell_before(&self.buf, self.pos as usize) as i32;
+            let Position(x, y) = self.position_after(start, self.pos as usize);
+            self.window.mv(y, x);
         }
     }
 
     fn move_right(&mut self) {
         if self.pos < self.buf.len() as i32 {
-            let Position(x, y) = self.current_position();
-            if x == self.window.get_max_x() - 1 {
-                self.window.mv(y + 1, 0);
-            } else {
-                self.window.mv(y, x + 1);
-            }
-            self.pos += 1;
+            let start = self.line_start_position();
+            self.pos += cell_after(&self.buf, self.pos as usize) as i32;
+            let Position(x, y) = self.position_after(start, self.pos as usize);
+            self.window.mv(y, x);
+        }
+    }
+
+    // to the start of the word before the cursor
+    fn move_word_left(&mut self) {
+        while self.pos > 0 && self.buf[self.pos as usize - 1] == b' ' {
+            self.move_left();
+        }
+        while self.pos > 0 && self.buf[self.pos as usize - 1] != b' ' {
+            self.move_left();
+        }
+    }
+
+    // to the end of the word after the cursor
+    fn move_word_right(&mut self) {
+        let len = self.buf.len() as i32;
+        while self.pos < len && self.buf[self.pos as usize] == b' ' {
+            self.move_right();
+        }
+        while self.pos < len && self.buf[self.pos as usize] != b' ' {
+            self.move_right();
         }
     }
 
@@ -239,23 +2841,37 @@ impl<F> Terminal<F>
 
     fn line_start_position(&self) -> Position {
         let y = self.window.get_cur_y();
-        let column = self.window.get_max_x();
-        let line_count = (self.pos + 1 - (column - self.prompt.len() as i32) + column - 1) / column + 1;
-        Position(self.prompt.len() as i32, y - line_count + 1)
+        let x = width::text_width(&self.prompt);
+        let Position(_, rows) = self.position_after(Position(x, 0), self.pos as usize);
+        Position(x, y - rows)
     }
 
     fn line_end_position(&self) -> Position {
-        let data_len = self.buf.len() as i32;
+        self.position_after(self.line_start_position(), self.buf.len())
+    }
+
+    // where the cursor is left once the line's first `index` bytes are drawn
+    // from `start`, wrapping as curses does: a wide character that doesn't
+    // fit in a row starts the next one
+    fn position_after(&self, start: Position, index: usize) -> Position {
         let column = self.window.get_max_x();
-        let Position(x, y) = self.line_start_position();
-        if data_len <= column - self.prompt.len() as i32 {
-            Position(x + data_len, y)
-        } else {
-            let line_count = (data_len - (column - self.prompt.len() as i32) + column - 1) / column + 1;
-            let end_x = (data_len - (column - self.prompt.len() as i32)) % column;
-            let end_y = y + line_count - 1;
-            Position(end_x, end_y)
+        let Position(mut x, mut y) = start;
+        for c in valid_prefix(&self.buf[..index]).chars() {
+            let width = width::width(c);
+            if width == 0 {
+                continue;
+            }
+            if x + width > column {
+                x = 0;
+                y += 1;
+            }
+            x += width;
+            if x >= column {
+                x = 0;
+                y += 1;
+            }
         }
+        Position(x, y)
     }
 
     #[allow(dead_code)]
@@ -274,3 +2890,153 @@ impl<F> Terminal<F>
     }
 }
 
+
+fn closing_pair(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
+
+fn is_closing(c: char) -> bool {
+    matches!(c, ')' | ']' | '}' | '"' | '\'')
+}
+
+// the characters `bytes` of the edited line start with, up to any cut in
+// the middle of one. Text from right-to-left scripts is kept and drawn in
+// its logical order, left to right, so a character's cell doesn't depend on
+// the characters around it
+fn valid_prefix(bytes: &[u8]) -> &str {
+    match str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+    }
+}
+
+// the line as it is run: normalized as a whole, as a combining mark typed
+// on its own was normalized apart from the character before it
+fn submitted(buf: &[u8]) -> String {
+    normalize::into_nfc(String::from_utf8(buf.to_vec()).unwrap())
+}
+
+// a new file holding `text`, which only the user can read, with a name no
+// one else could have guessed and created first
+fn temp_file(text: &str) -> io::Result<PathBuf> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    for _ in 0..16 {
+        let path = env::temp_dir().join(format!("term-rs-fc-{:016x}.txt", random()));
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        return match file.write_all(text.as_bytes()) {
+            Ok(()) => Ok(path),
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        };
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no unused temporary file name"))
+}
+
+// from /dev/urandom where there is one, else from the randomly keyed
+// hasher of the standard library
+fn random() -> u64 {
+    let mut bytes = [0; 8];
+    if fs::File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut bytes)).is_ok() {
+        return u64::from_ne_bytes(bytes);
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.finish()
+}
+
+// `text` without NULs, for addstr: they end a C string, where curses would
+// cut the text, and pancurses panics instead. Text from outside, such as
+// history files or file names, is drawn with addstr and never printw, which
+// would take a % in it for a conversion.
+fn drawable(text: &str) -> Cow<'_, str> {
+    if text.contains('\0') {
+        Cow::Owned(text.replace('\0', ""))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+// the length in bytes of the cell ending at `pos`
+fn cell_before(buf: &[u8], pos: usize) -> usize {
+    let text = match str::from_utf8(&buf[..pos]) {
+        Ok(text) => text,
+        Err(_) => return 1,
+    };
+    let mut len = 0;
+    for c in text.chars().rev() {
+        len += c.len_utf8();
+        if !normalize::is_combining(c) {
+            break;
+        }
+    }
+    len
+}
+
+// the length in bytes of the cell starting at `pos`
+fn cell_after(buf: &[u8], pos: usize) -> usize {
+    let text = match str::from_utf8(&buf[pos..]) {
+        Ok(text) => text,
+        Err(_) => return 1,
+    };
+    let mut chars = text.chars();
+    let mut len = chars.next().map_or(0, char::len_utf8);
+    for c in chars.take_while(|&c| normalize::is_combining(c)) {
+        len += c.len_utf8();
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{drawable, submitted, temp_file};
+    use super::super::normalize;
+
+    #[test]
+    fn drawable_text() {
+        assert!(matches!(drawable("100% %s%n"), Cow::Borrowed("100% %s%n")));
+        assert_eq!(drawable("a\0b\0"), "ab");
+    }
+
+    #[test]
+    fn submitted_line_is_nfc() {
+        // typed as two keys, each normalized as it is inserted
+        let mut buf = Vec::new();
+        buf.extend_from_slice(normalize::nfc("e").as_bytes());
+        buf.extend_from_slice(normalize::nfc("\u{301}").as_bytes());
+        assert_eq!(buf, "e\u{301}".as_bytes());
+        assert_eq!(submitted(&buf), "\u{e9}");
+        assert_eq!(submitted("ls -l".as_bytes()), "ls -l");
+    }
+
+    #[test]
+    fn temp_files() {
+        let (first, second) = (temp_file("ls -l").unwrap(), temp_file("").unwrap());
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "ls -l");
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+}
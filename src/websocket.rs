@@ -0,0 +1,532 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::remote::{Accepted, Connection, Listener};
+
+// RFC 6455: appended to the client's key to prove the handshake was read
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// how long a connecting client may take to send its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// the most a client may send as its handshake, and in one frame: keystrokes
+// and pastes, not files
+const MAX_HANDSHAKE: usize = 8 * 1024;
+const MAX_FRAME: u64 = 64 * 1024;
+
+// close status codes
+const PROTOCOL_ERROR: u16 = 1002;
+const TOO_BIG: u16 = 1009;
+
+/// Accepts WebSocket connections from a terminal emulator in the browser,
+/// such as xterm.js: keystrokes arrive as text frames and output leaves as
+/// text frames of ANSI text. There is no JSON framing, and lines are edited
+/// by a minimal editor of the connection's own rather than the curses one,
+/// without history browsing or completion.
+pub struct WebSocketListener {
+    listener: TcpListener,
+}
+
+impl WebSocketListener {
+    pub fn new(listener: TcpListener) -> Self {
+        WebSocketListener { listener }
+    }
+}
+
+impl Listener for WebSocketListener {
+    fn accept_client(&self) -> io::Result<Accepted> {
+        loop {
+            let (mut stream, _) = self.listener.accept()?;
+            // a client failing its handshake doesn't stop the server
+            if handshake(&mut stream).is_err() {
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
+            }
+            let frames = Frames { stream: Arc::new(Mutex::new(stream.try_clone()?)), hidden: Arc::default() };
+            let keystrokes = Keystrokes {
+                stream,
+                echo: frames.clone(),
+                line: String::new(),
+                partial: Vec::new(),
+                ready: VecDeque::new(),
+                escape: Escape::None,
+                closed: false,
+            };
+            return Ok((Box::new(BufReader::new(keystrokes)), Box::new(frames)));
+        }
+    }
+}
+
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    // byte by byte, so nothing after the request is consumed
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_HANDSHAKE {
+            stream.write_all(b"HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    stream.set_read_timeout(None)?;
+    let request = String::from_utf8_lossy(&request);
+    let key = request.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim().to_owned());
+    let key = match key {
+        Some(key) => key,
+        None => {
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WebSocket handshake"));
+        }
+    };
+    let accept = base64(&sha1(format!("{}{}", key, GUID).as_bytes()));
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                    Sec-WebSocket-Accept: {}\r\n\r\n", accept)
+}
+
+// Server frames, unmasked. Each write is sent as one text frame; the
+// remote module only writes whole strings, so frames are valid UTF-8.
+#[derive(Clone)]
+struct Frames {
+    stream: Arc<Mutex<TcpStream>>,
+    // whether keystrokes are echoed as stars
+    hidden: Arc<AtomicBool>,
+}
+
+impl Frames {
+    fn send(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len if len <= 0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.lock().unwrap().write_all(&frame)
+    }
+}
+
+impl Write for Frames {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            self.send(TEXT, buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Frames {
+    fn close(&self) {
+        let _ = self.send(CLOSE, &[]);
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn hide_input(&mut self, hide: bool) {
+        self.hidden.store(hide, Ordering::SeqCst);
+    }
+
+    fn peer(&self) -> Option<String> {
+        self.stream.lock().unwrap().peer_addr().ok().map(|addr| addr.ip().to_string())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Escape {
+    None,
+    // after ESC
+    Started,
+    // in an ESC [ or ESC O sequence, until its final byte
+    Sequence,
+}
+
+// Turns the keystrokes of the browser terminal into lines, echoing them
+// back: a minimal line editor with Backspace, Ctrl+U and Ctrl+C. Keys
+// sent as escape sequences, like the arrows, are ignored.
+struct Keystrokes {
+    stream: TcpStream,
+    echo: Frames,
+    line: String,
+    // the start of a character whose rest is in the next frame
+    partial: Vec<u8>,
+    ready: VecDeque<u8>,
+    escape: Escape,
+    closed: bool,
+}
+
+impl Keystrokes {
+    // the payload of the next data frame, answering pings on the way
+    fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let mut header = [0; 2];
+            self.stream.read_exact(&mut header)?;
+            let opcode = header[0] & 0xf;
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0; 2];
+                    self.stream.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0; 8];
+                    self.stream.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            // clients must mask their frames, and keep them small
+            let refused = match len {
+                _ if header[1] & 0x80 == 0 => Some(PROTOCOL_ERROR),
+                len if len > MAX_FRAME => Some(TOO_BIG),
+                _ => None,
+            };
+            if let Some(status) = refused {
+                let _ = self.echo.send(CLOSE, &status.to_be_bytes());
+                return Ok(None);
+            }
+            let mut mask = [0; 4];
+            self.stream.read_exact(&mut mask)?;
+            let mut payload = Vec::new();
+            (&mut self.stream).take(len).read_to_end(&mut payload)?;
+            if (payload.len() as u64) < len {
+                return Ok(None);
+            }
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+            match opcode {
+                CLOSE => {
+                    let _ = self.echo.send(CLOSE, &[]);
+                    return Ok(None);
+                }
+                PING => self.echo.send(PONG, &payload)?,
+                PONG => {}
+                _ => return Ok(Some(payload)),
+            }
+        }
+    }
+
+    fn key(&mut self, c: char) -> io::Result<()> {
+        match (self.escape, c) {
+            (Escape::None, '\u{1b}') => self.escape = Escape::Started,
+            (Escape::Started, '[') | (Escape::Started, 'O') => self.escape = Escape::Sequence,
+            (Escape::Started, _) => self.escape = Escape::None,
+            (Escape::Sequence, '@'..='~') => self.escape = Escape::None,
+            (Escape::Sequence, _) => {}
+            (Escape::None, '\r') | (Escape::None, '\n') => {
+                self.echo.send(TEXT, b"\r\n")?;
+                self.ready.extend(self.line.bytes());
+                self.ready.push_back(b'\n');
+                self.line.clear();
+            }
+            (Escape::None, '\u{7f}') | (Escape::None, '\u{8}') => {
+                if self.line.pop().is_some() {
+                    self.echo.send(TEXT, b"\x08 \x08")?;
+                }
+            }
+            (Escape::None, '\u{15}') => {
+                let erase = "\x08 \x08".repeat(self.line.chars().count());
+                self.echo.send(TEXT, erase.as_bytes())?;
+                self.line.clear();
+            }
+            (Escape::None, '\u{3}') => {
+                self.echo.send(TEXT, b"^C\r\n")?;
+                self.line.clear();
+                self.ready.push_back(b'\n');
+            }
+            (Escape::None, '\u{4}') if self.line.is_empty() => self.closed = true,
+            (Escape::None, c) if !c.is_control() => {
+                self.line.push(c);
+                let c = if self.echo.hidden.load(Ordering::SeqCst) { '*' } else { c };
+                let mut echo = [0; 4];
+                self.echo.send(TEXT, c.encode_utf8(&mut echo).as_bytes())?;
+            }
+            (Escape::None, _) => {}
+        }
+        Ok(())
+    }
+}
+
+impl Read for Keystrokes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.ready.is_empty() && !self.closed {
+            match self.read_frame()? {
+                Some(payload) => {
+                    self.partial.extend_from_slice(&payload);
+                    let complete = match str::from_utf8(&self.partial) {
+                        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                        _ => self.partial.len(),
+                    };
+                    let text: Vec<u8> = self.partial.drain(..complete).collect();
+                    for c in String::from_utf8_lossy(&text).chars() {
+                        self.key(c)?;
+                    }
+                }
+                None => self.closed = true,
+            }
+        }
+        let len = buf.len().min(self.ready.len());
+        for (i, byte) in self.ready.drain(..len).enumerate() {
+            buf[i] = byte;
+        }
+        Ok(len)
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut ret = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        ret[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    ret
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut ret = String::new();
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use super::{base64, sha1, WebSocketListener};
+    use super::super::remote::{Accepted, Listener};
+
+    const REQUEST: &str = "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+    // a client frame, masked unless `mask` is None
+    fn frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        let masked = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => frame.push(masked | len as u8),
+            len if len <= 0xffff => {
+                frame.push(masked | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(masked | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                frame.extend_from_slice(&mask);
+                frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+            }
+            None => frame.extend_from_slice(payload),
+        }
+        frame
+    }
+
+    fn text(payload: &str) -> Vec<u8> {
+        frame(true, 0x1, payload.as_bytes(), Some([0x37, 0xfa, 0x21, 0x3d]))
+    }
+
+    // the response to the handshake, read up to its blank line
+    fn response(client: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0];
+        while !response.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    // a server frame: its opcode and payload
+    fn server_frame(client: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        client.read_exact(&mut header).unwrap();
+        assert_eq!(header[1] & 0x80, 0, "server frames are unmasked");
+        let len = match header[1] {
+            126 => {
+                let mut len = [0; 2];
+                client.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        client.read_exact(&mut payload).unwrap();
+        (header[0] & 0xf, payload)
+    }
+
+    // a server and a client that has completed its handshake
+    fn connect() -> (Accepted, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(REQUEST.as_bytes()).unwrap();
+        let accepted = WebSocketListener::new(listener).accept_client().unwrap();
+        response(&mut client);
+        (accepted, client)
+    }
+
+    #[test]
+    fn accept_key() {
+        // the example of RFC 6455
+        let key = "dGhlIHNhbXBsZSBub25jZQ==258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+        assert_eq!(base64(&sha1(key.as_bytes())), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(base64(&sha1(b"")), "2jmj7l5rSw0yVb/vlWAYkK/YBwk=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // clients failing their handshake are refused, and the next accepted
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut long = TcpStream::connect(addr).unwrap();
+        long.write_all(format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(8 * 1024)).as_bytes()).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(REQUEST.as_bytes()).unwrap();
+        WebSocketListener::new(listener).accept_client().unwrap();
+        assert!(response(&mut plain).starts_with("HTTP/1.1 400 "));
+        assert!(response(&mut long).starts_with("HTTP/1.1 431 "));
+        let response = response(&mut client);
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn masked_keystrokes() {
+        let ((mut input, _), mut client) = connect();
+        client.write_all(&text("lx\u{7f}s")).unwrap();
+        client.write_all(&text("\r")).unwrap();
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        assert_eq!(line, "ls\n");
+        let echo: Vec<_> = (0..5).map(|_| server_frame(&mut client)).collect();
+        let echo: Vec<_> = echo.iter().map(|(opcode, payload)| (*opcode, &payload[..])).collect();
+        assert_eq!(echo, [(0x1, &b"l"[..]), (0x1, b"x"), (0x1, b"\x08 \x08"), (0x1, b"s"), (0x1, b"\r\n")]);
+    }
+
+    #[test]
+    fn fragments() {
+        let ((mut input, _), mut client) = connect();
+        let mask = Some([1, 2, 3, 4]);
+        // "é" split between a text frame and its continuation, with a ping
+        // in between, and a frame split between writes
+        let bytes = "cé\r".as_bytes();
+        client.write_all(&frame(false, 0x1, &bytes[..2], mask)).unwrap();
+        client.write_all(&frame(true, 0x9, b"hi", mask)).unwrap();
+        let last = frame(true, 0x0, &bytes[2..], mask);
+        client.write_all(&last[..3]).unwrap();
+        client.flush().unwrap();
+        client.write_all(&last[3..]).unwrap();
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        assert_eq!(line, "cé\n");
+        assert_eq!(server_frame(&mut client), (0x1, b"c".to_vec()));
+        assert_eq!(server_frame(&mut client), (0xa, b"hi".to_vec()));
+        assert_eq!(server_frame(&mut client), (0x1, "é".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn unmasked_frames_are_refused() {
+        let ((mut input, _), mut client) = connect();
+        client.write_all(&frame(true, 0x1, b"ls\r", None)).unwrap();
+        let mut line = String::new();
+        assert_eq!(input.read_line(&mut line).unwrap(), 0);
+        assert_eq!(server_frame(&mut client), (0x8, 1002u16.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn large_frames_are_refused() {
+        let ((mut input, _), mut client) = connect();
+        // with a 16-bit length
+        client.write_all(&text(&format!("{}\r", "x".repeat(299)))).unwrap();
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        assert_eq!(line.len(), 300);
+        // over 64 KiB, refused by its header before the payload is sent
+        let mut header = vec![0x81, 0x80 | 127];
+        header.extend_from_slice(&(64 * 1024 + 1u64).to_be_bytes());
+        client.write_all(&header).unwrap();
+        line.clear();
+        assert_eq!(input.read_line(&mut line).unwrap(), 0);
+        let mut close = None;
+        while close.is_none() {
+            let (opcode, payload) = server_frame(&mut client);
+            if opcode == 0x8 {
+                close = Some(payload);
+            }
+        }
+        assert_eq!(close.unwrap(), 1009u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    // the working directory of the session whose command is being processed
+    static CURRENT_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// The working directory of the session whose command is being processed
+/// or completed, as `cd` left it, see `TerminalBuilder::working_directory`.
+/// Otherwise, and outside of processing, the process's.
+pub fn current_dir() -> PathBuf {
+    CURRENT_DIR.with(|dir| dir.borrow().clone())
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_default()
+}
+
+/// `path` relative to `current_dir` if it isn't absolute, for commands that
+/// open files.
+pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+    current_dir().join(path)
+}
+
+// the session's directory set for the command being processed, if any
+pub(crate) fn tracked() -> Option<PathBuf> {
+    CURRENT_DIR.with(|dir| dir.borrow().clone())
+}
+
+// runs `f` with `dir` as the current directory
+pub(crate) fn in_dir<F, T>(dir: Option<&Path>, f: F) -> T
+    where F: FnOnce() -> T {
+    let previous = CURRENT_DIR.with(|current| current.replace(dir.map(|d| d.to_owned())));
+    let ret = f();
+    CURRENT_DIR.with(|current| *current.borrow_mut() = previous);
+    ret
+}
+
+// the directory `cd` moves to from `dir`: `target` relative to it, or the
+// home directory without one
+pub(crate) fn change(dir: &Path, target: Option<&str>) -> io::Result<PathBuf> {
+    let target = match target {
+        Some(target) => dir.join(target),
+        None => env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?,
+    };
+    let target = fs::canonicalize(target)?;
+    if !target.is_dir() {
+        return Err(io::Error::other("not a directory"));
+    }
+    Ok(target)
+}
+
+// the subdirectories of `dir` completing `word`, relative as it is, with
+// a trailing `/`
+pub(crate) fn complete(dir: &Path, word: &str) -> Vec<String> {
+    let (parent, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..i + 1], &word[i + 1..]),
+        None => ("", word),
+    };
+    let entries = match fs::read_dir(dir.join(if parent.is_empty() { "." } else { parent })) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut ret: Vec<String> = entries.filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(prefix) && (!name.starts_with('.') || prefix.starts_with('.')))
+        .map(|name| format!("{}{}/", parent, name))
+        .collect();
+    ret.sort();
+    ret
+}